@@ -0,0 +1,19 @@
+use prost::Message;
+
+// The protobuf definitions are parsed in Rust rather than with protoc, so building doesn't need it
+// installed. The parsed descriptors are handed to tonic to generate the service from
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto");
+    let descriptors = protobuf_parse::Parser::new()
+        .pure()
+        .include("proto")
+        .input("proto/todos.proto")
+        .file_descriptor_set()?;
+    let descriptors = protobuf::Message::write_to_bytes(&descriptors)?;
+    let descriptors = prost_types::FileDescriptorSet::decode(descriptors.as_slice())?;
+    // The generated `connect` helper needs the 2021 prelude, clients are created from a channel
+    tonic_build::configure()
+        .build_transport(false)
+        .compile_fds(descriptors)?;
+    Ok(())
+}
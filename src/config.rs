@@ -1,6 +1,8 @@
 use crate::error::{Error, Result};
-use config::{Config, Environment, File};
+use chrono::prelude::*;
+use config::{Config, Environment, File, FileFormat};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 const DEFAULT_CONFIG_PATH: &str = "./config/Default.yml";
 const CONFIG_FILE_PREFIX: &str = "./config/";
@@ -43,23 +45,255 @@ pub struct DatabaseSettings {
     pub uri: String,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    // Human readable lines
+    Pretty,
+    // One JSON object per line, for log pipelines
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    Daily,
+    Never,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LogFileSettings {
+    pub directory: String,
+    // Log files are named with this prefix followed by the date they were started on
+    pub prefix: String,
+    pub rotation: LogRotation,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(from = "LoggerConfig")]
 pub struct LoggerSettings {
+    // EnvFilter directives, can be changed at runtime through the admin endpoint
     pub rules: Vec<String>,
+    pub format: LogFormat,
+    // Logs are also written to rotated files when set
+    pub file: Option<LogFileSettings>,
+}
+
+// The log section used to be just the list of rules. Configurations written that way still load,
+// and log in the pretty format without a file
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LoggerConfig {
+    Rules(Vec<String>),
+    Settings {
+        rules: Vec<String>,
+        format: LogFormat,
+        file: Option<LogFileSettings>,
+    },
+}
+
+impl From<LoggerConfig> for LoggerSettings {
+    fn from(config: LoggerConfig) -> Self {
+        match config {
+            LoggerConfig::Rules(rules) => Self {
+                rules,
+                format: LogFormat::Pretty,
+                file: None,
+            },
+            LoggerConfig::Settings {
+                rules,
+                format,
+                file,
+            } => Self {
+                rules,
+                format,
+                file,
+            },
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServerSettings {
     pub application_port: u16,
+    // An IPv4 or IPv6 address or a hostname
     pub address: String,
+    // Targets the HTTP server listens on, like "[::1]:3030", "localhost:3030" or
+    // "unix:/run/warp_crud.sock". Without any, it listens on the address and application port
+    #[serde(default)]
+    pub listen: Vec<String>,
+    // The gRPC service is served on this port of every IP address the HTTP server listens on, or
+    // of the address when HTTP is only served on Unix sockets
+    pub grpc_port: u16,
+    pub shutdown: ShutdownSettings,
+    // Serve HTTPS instead of plain HTTP
+    pub tls: Option<TlsSettings>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TlsSettings {
+    // PEM files of the certificate chain and its private key
+    pub certificate: String,
+    pub key: String,
+    // How often the files are checked for a renewed certificate, in seconds
+    pub reload_interval: u64,
+    // Port of a plain HTTP listener redirecting every request to HTTPS, none without one
+    pub redirect_port: Option<u16>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ShutdownSettings {
+    // How long readiness fails before the listener closes, so load balancers stop sending
+    // requests first, in seconds
+    pub drain_period: u64,
+    // How long open requests get to finish after the listener closed, in seconds
+    pub timeout: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IdempotencySettings {
+    // How long a stored response is replayed for, in seconds
+    pub window: i64,
+    // How long a request holds its key before a retry may take it over, in seconds
+    pub lease: i64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrashSettings {
+    // How long deleted todos are kept before being purged, in seconds
+    pub retention: i64,
+    // How often the trash is checked for expired todos, in seconds
+    pub purge_interval: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventSource {
+    // Events come from writes made by this instance
+    Local,
+    // Events come from a mongodb change stream, so every instance sees writes made by the others.
+    // Requires the database to run as a replica set
+    ChangeStream,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EventSettings {
+    // Number of events buffered for each listener before slow listeners miss events
+    pub capacity: usize,
+    // Number of recent events kept for clients resuming the feed with Last-Event-ID
+    pub backlog: usize,
+    pub source: EventSource,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WebhookSettings {
+    // Number of times a delivery is attempted before it is marked as failed
+    pub max_attempts: u32,
+    // Delay before the first retry in milliseconds, doubled after every failed attempt
+    pub initial_backoff: u64,
+    // Longest delay between two attempts, in milliseconds
+    pub max_backoff: u64,
+    // How long to wait for a receiver to respond, in seconds
+    pub timeout: u64,
+    // Number of webhooks a session can register
+    pub max_per_session: u64,
+    // Networks webhooks may be delivered to even though they are loopback, private or link-local,
+    // e.g. 10.1.0.0/16 for receivers on the internal network. Every other such address is refused
+    #[serde(default)]
+    pub allowed_networks: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VersionSettings {
+    // When the version was deprecated, sent in the Deprecation header
+    pub deprecated: Option<DateTime<Utc>>,
+    // When the version will stop being served, sent in the Sunset header
+    pub sunset: Option<DateTime<Utc>>,
+    // Path of the version that replaces it, sent as a successor-version link
+    pub successor: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApiSettings {
+    // Lifecycle of each API version, versions without an entry are current
+    #[serde(default)]
+    pub versions: HashMap<String, VersionSettings>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CompressionSettings {
+    // Responses smaller than this many bytes are sent uncompressed
+    pub threshold: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CachePolicy {
+    // Requests with paths starting with this prefix use the policy, the longest prefix wins
+    pub path: String,
+    // Sent as the Cache-Control header of responses that don't set their own
+    pub cache_control: Option<String>,
+    // Tag responses with an ETag and answer matching If-None-Match requests with 304 Not Modified
+    #[serde(default)]
+    pub etag: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HttpSettings {
+    pub compression: CompressionSettings,
+    #[serde(default)]
+    pub caching: Vec<CachePolicy>,
+    // Serve the frontend from this directory instead of the files built into the executable.
+    // Builds without the embedded-assets feature read it from `static` when this isn't set
+    pub static_dir: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MetricsSettings {
+    // How long a session counts as active after its last request, in seconds
+    pub session_window: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TelemetrySettings {
+    // OTLP gRPC endpoint of the collector spans are exported to, nothing is exported without one
+    pub otlp_endpoint: Option<String>,
+    // Fraction of traces sampled, from 0 to 1. Requests joining an upstream trace follow its choice
+    pub sampling_ratio: f64,
+    // Reported as the service.name of exported spans
+    pub service_name: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HealthSettings {
+    // How long the readiness probe waits for the database to answer, in milliseconds
+    pub database_timeout: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AdminSettings {
+    // Bearer token of the admin endpoints, they refuse every request without one
+    pub token: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Settings {
     pub environment: Env,
     pub database: DatabaseSettings,
-    pub log: Vec<String>,
+    pub log: LoggerSettings,
     pub server: ServerSettings,
+    pub idempotency: IdempotencySettings,
+    pub trash: TrashSettings,
+    pub events: EventSettings,
+    pub webhooks: WebhookSettings,
+    pub api: ApiSettings,
+    pub http: HttpSettings,
+    pub metrics: MetricsSettings,
+    pub telemetry: TelemetrySettings,
+    pub health: HealthSettings,
+    #[serde(default)]
+    pub admin: AdminSettings,
 }
 
 impl Settings {
@@ -78,24 +312,86 @@ impl Settings {
             .map_err(|source| Error::ConfigurationError { source })?;
 
         // Convert it into a settings Struct and raise an error if we could not
-        settings
+        let settings: Settings = settings
             .try_into()
-            .map_err(|source| Error::ConfigurationError { source })
+            .map_err(|source| Error::ConfigurationError { source })?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    // Refuse values that deserialize fine but would make the server panic once it runs
+    fn validate(&self) -> Result<()> {
+        let mut positive = vec![
+            ("trash.purge_interval", self.trash.purge_interval),
+            ("events.capacity", self.events.capacity as u64),
+        ];
+        if let Some(tls) = &self.server.tls {
+            positive.push(("server.tls.reload_interval", tls.reload_interval));
+        }
+
+        if let Some((key, _)) = positive.into_iter().find(|(_, value)| *value == 0) {
+            return Err(Error::ConfigurationError {
+                source: config::ConfigError::Message(format!("{} must be greater than 0", key)),
+            });
+        }
+
+        for network in &self.webhooks.allowed_networks {
+            if network.parse::<ipnet::IpNet>().is_err() {
+                return Err(Error::ConfigurationError {
+                    source: config::ConfigError::Message(format!(
+                        "webhooks.allowed_networks: \"{}\" is not a network in CIDR notation",
+                        network
+                    )),
+                });
+            }
+        }
+        Ok(())
     }
 }
 
 fn collect_configuration_files<'a>(config: &'a mut Config, env: &str) -> Result<&'a mut Config> {
     // Merge Default Settings
-    config
-        .merge(File::with_name(DEFAULT_CONFIG_PATH))
-        .map_err(|source| Error::ConfigurationError { source })?;
+    merge_configuration_file(config, DEFAULT_CONFIG_PATH, "Default")?;
 
     //Merge the specific environment settings
+    merge_configuration_file(config, &format!("{}{}.yml", CONFIG_FILE_PREFIX, env), env)
+}
+
+// Merge a configuration file from disk, or the copy built into the executable when it isn't there
+fn merge_configuration_file<'a>(
+    config: &'a mut Config,
+    path: &str,
+    env: &str,
+) -> Result<&'a mut Config> {
+    if !std::path::Path::new(path).exists() {
+        if let Some(contents) = embedded_configuration_file(env) {
+            return config
+                .merge(File::from_str(contents, FileFormat::Yaml))
+                .map_err(|source| Error::ConfigurationError { source });
+        }
+    }
+
     config
-        .merge(File::with_name(&format!("{}{}", CONFIG_FILE_PREFIX, env)))
+        .merge(File::with_name(path))
         .map_err(|source| Error::ConfigurationError { source })
 }
 
+#[cfg(feature = "embedded-assets")]
+fn embedded_configuration_file(env: &str) -> Option<&'static str> {
+    match env {
+        "Default" => Some(include_str!("../config/Default.yml")),
+        "Test" => Some(include_str!("../config/Test.yml")),
+        "Development" => Some(include_str!("../config/Development.yml")),
+        "Production" => Some(include_str!("../config/Production.yml")),
+        _ => None,
+    }
+}
+
+#[cfg(not(feature = "embedded-assets"))]
+fn embedded_configuration_file(_env: &str) -> Option<&'static str> {
+    None
+}
+
 fn collect_environment_variables(config: &mut Config) -> Result<&mut Config> {
     // Get database login information from the Environment
     // These Env Variables should be EA_DATABASE__URI
@@ -1,6 +1,9 @@
+use crate::config;
+use async_graphql::Enum;
 use chrono::prelude::*;
-use mongodb::bson::serde_helpers;
-use serde::{Deserialize, Serialize};
+use mongodb::bson::{oid::ObjectId, serde_helpers};
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Person {
@@ -15,30 +18,97 @@ pub struct PersonRequest {
     pub fname: String,
     pub lname: String,
 }
-#[derive(Serialize, Deserialize)]
+
+// Result of a health probe, following the draft IETF health check response format
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Pass,
+    Fail,
+}
+
+#[derive(Deserialize, Serialize, Debug, JsonSchema)]
+pub struct HealthCheck {
+    pub name: String,
+    pub status: HealthStatus,
+    // How long the check took, for checks that talk to a dependency
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, JsonSchema)]
+pub struct HealthReport {
+    // Pass only when every check passed
+    pub status: HealthStatus,
+    pub checks: Vec<HealthCheck>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct TodoRequest {
     pub name: String,
+    // Left out keeps the due date a todo has, null clears it
+    #[serde(
+        default,
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub due: Option<Option<DateTime<Utc>>>,
 }
 
-#[derive(Deserialize, Serialize)]
+// Tell a field that was left out, None, apart from one that was set to null, Some(None)
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+// Maximum number of todo items a single list can hold
+pub const MAX_TODOS: usize = 10;
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
 pub struct Todo {
     pub id: uuid::Uuid,
     pub name: String,
+    // When the todo was last changed
     pub timestamp: DateTime<Utc>,
+    // Todos from before this was stored get their timestamp, see `db::initialize`
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub completed: bool,
+    #[serde(default)]
+    pub completed_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub due: Option<DateTime<Utc>>,
+    // A priority from A (highest) to Z, as used by todo.txt
+    #[serde(default)]
+    pub priority: Option<char>,
 }
 
 impl From<TodoRequest> for Todo {
     fn from(request: TodoRequest) -> Self {
-        Self::new(&request.name)
+        Self {
+            due: request.due.flatten(),
+            ..Self::new(&request.name)
+        }
     }
 }
 
 impl From<&str> for Todo {
     fn from(todo: &str) -> Self {
+        let now = Utc::now();
         Self {
             id: uuid::Uuid::new_v4(),
             name: todo.to_owned(),
-            timestamp: Utc::now(),
+            timestamp: now,
+            created_at: now,
+            completed: false,
+            completed_at: None,
+            due: None,
+            priority: None,
         }
     }
 }
@@ -49,7 +119,7 @@ impl Todo {
     }
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Default, Clone)]
 pub struct Session {
     #[serde(with = "serde_helpers::uuid_as_binary")]
     id: uuid::Uuid,
@@ -67,14 +137,6 @@ impl Session {
     }
 }
 
-impl Default for Session {
-    fn default() -> Self {
-        Self {
-            id: Default::default(),
-        }
-    }
-}
-
 impl From<uuid::Uuid> for Session {
     fn from(id: uuid::Uuid) -> Self {
         Self { id }
@@ -85,4 +147,531 @@ impl From<uuid::Uuid> for Session {
 pub struct TodoList {
     pub session: Session,
     pub todos: Vec<Todo>,
+    #[serde(default)]
+    pub trash: Vec<TrashedTodo>,
+    // Hash of the token that grants read access to the list's calendar feed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub calendar_token: Option<String>,
+}
+
+// A deleted todo, kept around until it is restored or purged
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct TrashedTodo {
+    pub todo: Todo,
+    #[serde(with = "serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub deleted_at: DateTime<Utc>,
+}
+
+impl From<Todo> for TrashedTodo {
+    fn from(todo: Todo) -> Self {
+        Self {
+            todo,
+            deleted_at: Utc::now(),
+        }
+    }
+}
+
+// The public view of a trashed todo
+#[derive(Deserialize, Serialize, Debug, JsonSchema)]
+pub struct DeletedTodo {
+    #[serde(flatten)]
+    pub todo: Todo,
+    pub deleted_at: DateTime<Utc>,
+}
+
+impl From<TrashedTodo> for DeletedTodo {
+    fn from(trashed: TrashedTodo) -> Self {
+        Self {
+            todo: trashed.todo,
+            deleted_at: trashed.deleted_at,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, JsonSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOperation {
+    Create {
+        name: String,
+        #[serde(default)]
+        due: Option<DateTime<Utc>>,
+    },
+    Update {
+        id: uuid::Uuid,
+        name: String,
+        #[serde(
+            default,
+            deserialize_with = "present",
+            skip_serializing_if = "Option::is_none"
+        )]
+        due: Option<Option<DateTime<Utc>>>,
+    },
+    Delete {
+        id: uuid::Uuid,
+    },
+    Complete {
+        id: uuid::Uuid,
+        #[serde(default = "default_completed")]
+        completed: bool,
+    },
+}
+
+fn default_completed() -> bool {
+    true
+}
+
+impl BatchOperation {
+    // Apply the operation to a list of todos in place, returning the todo it touched
+    pub fn apply(&self, todos: &mut Vec<Todo>) -> crate::Result<Todo> {
+        match self {
+            BatchOperation::Create { name, due } => {
+                if todos.len() >= MAX_TODOS {
+                    return Err(crate::error::Error::TodoLimitError(MAX_TODOS));
+                }
+                let todo = Todo {
+                    due: *due,
+                    ..Todo::new(name)
+                };
+                todos.push(todo.clone());
+                Ok(todo)
+            }
+            BatchOperation::Update { id, name, due } => {
+                let todo = find_todo(todos, id)?;
+                todo.name = name.to_owned();
+                if let Some(due) = due {
+                    todo.due = *due;
+                }
+                todo.timestamp = Utc::now();
+                Ok(todo.clone())
+            }
+            BatchOperation::Delete { id } => {
+                let index = todos
+                    .iter()
+                    .position(|todo| &todo.id == id)
+                    .ok_or(crate::error::Error::NonexistentResourceError)?;
+                Ok(todos.remove(index))
+            }
+            BatchOperation::Complete { id, completed } => {
+                let todo = find_todo(todos, id)?;
+                todo.completed = *completed;
+                todo.completed_at = if *completed { Some(Utc::now()) } else { None };
+                todo.timestamp = Utc::now();
+                Ok(todo.clone())
+            }
+        }
+    }
+}
+
+fn find_todo<'a>(todos: &'a mut [Todo], id: &uuid::Uuid) -> crate::Result<&'a mut Todo> {
+    todos
+        .iter_mut()
+        .find(|todo| &todo.id == id)
+        .ok_or(crate::error::Error::NonexistentResourceError)
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, JsonSchema, Enum)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    Ok,
+    Failed,
+    Skipped,
+}
+
+#[derive(Deserialize, Serialize, Debug, JsonSchema)]
+pub struct BatchResult {
+    pub index: usize,
+    pub status: BatchStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub todo: Option<Todo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, JsonSchema)]
+pub struct BatchResponse {
+    pub committed: bool,
+    pub results: Vec<BatchResult>,
+}
+
+impl BatchResponse {
+    // Run every operation against the list in order. If any operation fails the whole batch is
+    // rejected and the operations after it are reported as skipped
+    pub fn execute(operations: &[BatchOperation], todos: &mut Vec<Todo>) -> Self {
+        let mut results = Vec::with_capacity(operations.len());
+        let mut committed = true;
+
+        for (index, operation) in operations.iter().enumerate() {
+            if !committed {
+                results.push(BatchResult {
+                    index,
+                    status: BatchStatus::Skipped,
+                    todo: None,
+                    error: None,
+                });
+                continue;
+            }
+
+            match operation.apply(todos) {
+                Ok(todo) => results.push(BatchResult {
+                    index,
+                    status: BatchStatus::Ok,
+                    todo: Some(todo),
+                    error: None,
+                }),
+                Err(error) => {
+                    committed = false;
+                    results.push(BatchResult {
+                        index,
+                        status: BatchStatus::Failed,
+                        todo: None,
+                        error: Some(error.to_string()),
+                    });
+                }
+            }
+        }
+
+        Self { committed, results }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub body: String,
+    #[serde(skip)]
+    pub replayed: bool,
+}
+
+impl StoredResponse {
+    pub fn new(status: u16, body: String) -> Self {
+        Self {
+            status,
+            body,
+            replayed: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct IdempotencyRecord {
+    pub session: Session,
+    pub key: String,
+    pub fingerprint: String,
+    pub response: Option<StoredResponse>,
+    // Identifies the request holding the key, only it may complete or release the claim
+    #[serde(with = "serde_helpers::uuid_as_binary", default)]
+    pub claim: uuid::Uuid,
+    // A claim still without a response by then belongs to a request that never finished, e.g.
+    // because the server crashed, and can be taken over by a retry
+    #[serde(with = "serde_helpers::chrono_datetime_as_bson_datetime", default)]
+    pub locked_until: DateTime<Utc>,
+    #[serde(with = "serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+}
+
+impl IdempotencyRecord {
+    pub fn new(
+        session: &Session,
+        key: String,
+        fingerprint: String,
+        settings: &config::IdempotencySettings,
+    ) -> Self {
+        let now = Utc::now();
+        Self {
+            session: Session::from(*session.id()),
+            key,
+            fingerprint,
+            response: None,
+            claim: uuid::Uuid::new_v4(),
+            locked_until: now + chrono::Duration::seconds(settings.lease),
+            expires_at: now + chrono::Duration::seconds(settings.window),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, JsonSchema, Enum)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryAction {
+    CreateTodo,
+    UpdateTodo,
+    DeleteTodo,
+    DeleteAllTodos,
+    RestoreTodo,
+    Batch,
+    Import,
+}
+
+// A mutation of a todo list, along with the state of the list before it was applied
+#[derive(Deserialize, Serialize)]
+pub struct HistoryEntry {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub session: Session,
+    pub action: HistoryAction,
+    pub todo: Option<uuid::Uuid>,
+    #[serde(with = "serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub timestamp: DateTime<Utc>,
+    pub before: Vec<Todo>,
+    // The state of the list when the entry was undone, used to redo it
+    pub after: Option<Vec<Todo>>,
+    pub undone: bool,
+}
+
+impl HistoryEntry {
+    pub fn new(
+        session: &Session,
+        action: HistoryAction,
+        todo: Option<uuid::Uuid>,
+        before: Vec<Todo>,
+    ) -> Self {
+        Self {
+            id: None,
+            session: Session::from(*session.id()),
+            action,
+            todo,
+            timestamp: Utc::now(),
+            before,
+            after: None,
+            undone: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct HistoryQuery {
+    #[serde(default = "default_history_limit")]
+    pub limit: i64,
+}
+
+fn default_history_limit() -> i64 {
+    20
+}
+
+// The public view of a history entry, without the list snapshots
+#[derive(Deserialize, Serialize, Debug, JsonSchema)]
+pub struct Change {
+    pub id: String,
+    pub action: HistoryAction,
+    pub todo: Option<uuid::Uuid>,
+    pub timestamp: DateTime<Utc>,
+    pub undone: bool,
+}
+
+impl From<HistoryEntry> for Change {
+    fn from(entry: HistoryEntry) -> Self {
+        Self {
+            id: entry.id.map(|id| id.to_hex()).unwrap_or_default(),
+            action: entry.action,
+            todo: entry.todo,
+            timestamp: entry.timestamp,
+            undone: entry.undone,
+        }
+    }
+}
+
+// A calendar feed URL, the token in it is only handed out when the feed is created
+#[derive(Deserialize, Serialize, Debug, JsonSchema)]
+pub struct CalendarFeed {
+    pub token: String,
+    pub path: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    TodoCreated,
+    TodoCompleted,
+    TodoDeleted,
+    ListCleared,
+}
+
+// The EnvFilter directives logs are filtered with, e.g. "info,warp_crud=debug"
+#[derive(Deserialize, Serialize, Debug, JsonSchema)]
+pub struct LogFilter {
+    pub filter: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, JsonSchema)]
+pub struct WebhookRequest {
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Webhook {
+    pub id: uuid::Uuid,
+    pub session: Session,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    // Shared with the receiver so it can verify payloads were sent by us
+    pub secret: String,
+    #[serde(with = "serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+}
+
+impl Webhook {
+    pub fn new(session: &Session, request: WebhookRequest) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4(),
+            session: Session::from(*session.id()),
+            url: request.url,
+            events: request.events,
+            secret: hex::encode(rand::random::<[u8; 32]>()),
+            created_at: Utc::now(),
+        }
+    }
+}
+
+// The public view of a webhook, the secret is only handed out when the webhook is registered
+#[derive(Deserialize, Serialize, Debug, JsonSchema)]
+pub struct WebhookView {
+    pub id: uuid::Uuid,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<Webhook> for WebhookView {
+    fn from(webhook: Webhook) -> Self {
+        Self {
+            id: webhook.id,
+            url: webhook.url,
+            events: webhook.events,
+            secret: None,
+            created_at: webhook.created_at,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryState {
+    Pending,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Delivery {
+    pub id: uuid::Uuid,
+    pub webhook: uuid::Uuid,
+    pub session: Session,
+    pub event: WebhookEvent,
+    pub state: DeliveryState,
+    pub attempts: u32,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    #[serde(with = "serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Delivery {
+    pub fn new(webhook: &Webhook, event: WebhookEvent) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4(),
+            webhook: webhook.id,
+            session: Session::from(*webhook.session.id()),
+            event,
+            state: DeliveryState::Pending,
+            attempts: 0,
+            status: None,
+            error: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+}
+
+// The public view of a delivery attempt
+#[derive(Deserialize, Serialize, Debug, JsonSchema)]
+pub struct DeliveryView {
+    pub id: uuid::Uuid,
+    pub webhook: uuid::Uuid,
+    pub event: WebhookEvent,
+    pub state: DeliveryState,
+    pub attempts: u32,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<Delivery> for DeliveryView {
+    fn from(delivery: Delivery) -> Self {
+        Self {
+            id: delivery.id,
+            webhook: delivery.webhook,
+            event: delivery.event,
+            state: delivery.state,
+            attempts: delivery.attempts,
+            status: delivery.status,
+            error: delivery.error,
+            created_at: delivery.created_at,
+            updated_at: delivery.updated_at,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DeliveryQuery {
+    pub webhook: Option<uuid::Uuid>,
+    #[serde(default = "default_history_limit")]
+    pub limit: i64,
+}
+
+// Types of version 2 of the API, converted to and from the types the database stores so both
+// versions can be served side by side
+pub mod v2 {
+    use super::*;
+
+    #[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+    #[schemars(rename = "TodoV2")]
+    pub struct Todo {
+        pub id: uuid::Uuid,
+        pub title: String,
+        pub created_at: DateTime<Utc>,
+        pub completed: bool,
+        pub completed_at: Option<DateTime<Utc>>,
+        pub due: Option<DateTime<Utc>>,
+        pub priority: Option<char>,
+    }
+
+    impl From<super::Todo> for Todo {
+        fn from(todo: super::Todo) -> Self {
+            Self {
+                id: todo.id,
+                title: todo.name,
+                created_at: todo.created_at,
+                completed: todo.completed,
+                completed_at: todo.completed_at,
+                due: todo.due,
+                priority: todo.priority,
+            }
+        }
+    }
+
+    #[derive(Deserialize, Serialize, Debug, JsonSchema)]
+    #[schemars(rename = "TodoRequestV2")]
+    pub struct TodoRequest {
+        pub title: String,
+        #[serde(
+            default,
+            deserialize_with = "present",
+            skip_serializing_if = "Option::is_none"
+        )]
+        pub due: Option<Option<DateTime<Utc>>>,
+    }
+
+    impl From<TodoRequest> for super::TodoRequest {
+        fn from(request: TodoRequest) -> Self {
+            Self {
+                name: request.title,
+                due: request.due,
+            }
+        }
+    }
 }
@@ -1,8 +1,21 @@
-use crate::{data, error::Error::*, Result};
+use crate::{data, error::Error::*, formats, metrics, Result};
 
 use chrono::prelude::*;
+use futures::future::BoxFuture;
+use futures::TryStreamExt;
 use mongodb::bson;
 use mongodb::bson::{doc, serde_helpers::serialize_uuid_as_binary, Bson, Document, Serializer};
+use mongodb::change_stream::event::{ChangeStreamEvent, ResumeToken};
+use mongodb::change_stream::ChangeStream;
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::options::{
+    ChangeStreamOptions, FindOneOptions, FindOptions, FullDocumentType, IndexOptions,
+    UpdateModifications,
+};
+use mongodb::IndexModel;
+use std::future::Future;
+use tracing::field::Empty;
+use tracing::Instrument;
 use uuid::Uuid;
 
 const DB_NAME: &str = "warp_crud"; // database name
@@ -10,15 +23,210 @@ const DB_NAME: &str = "warp_crud"; // database name
 // Create a few consts for inserting and requesting from database
 const SESSION: &str = "session.id";
 const TODOS: &str = "todos";
+const IDEMPOTENCY: &str = "idempotency";
+const HISTORY: &str = "history";
+const WEBHOOKS: &str = "webhooks";
+const DELIVERIES: &str = "deliveries";
+
+// Number of changes kept in the history of each list
+const HISTORY_LIMIT: u64 = 50;
+
+// Number of times a batch is re-applied when the list changes underneath it
+const BATCH_RETRIES: usize = 3;
 
 pub(crate) type Client = mongodb::Client;
 
+// Time each operation for the metrics and trace it as a span of its own. Only the public functions
+// are wrapped, the helpers they share are not, so each call is counted once. The operation is boxed
+// so its state isn't stored inline in the futures of every handler that awaits it
+fn instrumented<'a, T>(
+    operation: &'static str,
+    future: impl Future<Output = Result<T>> + Send + 'a,
+) -> BoxFuture<'a, Result<T>> {
+    let span = tracing::info_span!(
+        "db",
+        otel.name = %format!("{} {}", DB_NAME, operation),
+        otel.kind = "client",
+        otel.status_code = Empty,
+        db.system = "mongodb",
+        db.name = DB_NAME,
+        db.operation = operation,
+    );
+    Box::pin(
+        async move {
+            let result = metrics::observe_db(operation, future).await;
+            if result.is_err() {
+                tracing::Span::current().record("otel.status_code", "ERROR");
+            }
+            result
+        }
+        .instrument(span),
+    )
+}
+
 pub async fn ping(client: &Client) -> Result<Document> {
+    instrumented("ping", async move {
+        client
+            .database("admin")
+            .run_command(doc! {"ping":1}, None)
+            .await
+            .map_err(MongoQueryError)
+    })
+    .await
+}
+
+// Create the indexes the application relies on and bring stored todos up to date, this is safe to
+// run on every startup
+pub async fn initialize(client: &Client) -> Result<()> {
+    instrumented("initialize", async move {
+        let idempotency = client.database(DB_NAME).collection::<Document>(IDEMPOTENCY);
+
+        // Keys are unique per session so concurrent retries can't both claim the same key
+        idempotency
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! {SESSION: 1, "key": 1})
+                    .options(IndexOptions::builder().unique(true).build())
+                    .build(),
+                None,
+            )
+            .await
+            .map_err(MongoQueryError)?;
+
+        // Let mongo clean up records once their replay window has passed
+        idempotency
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! {"expires_at": 1})
+                    .options(
+                        IndexOptions::builder()
+                            .expire_after(std::time::Duration::from_secs(0))
+                            .build(),
+                    )
+                    .build(),
+                None,
+            )
+            .await
+            .map_err(MongoQueryError)?;
+
+        // Used by the background task that purges expired todos from the trash
+        client
+            .database(DB_NAME)
+            .collection::<Document>(TODOS)
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! {"trash.deleted_at": 1})
+                    .build(),
+                None,
+            )
+            .await
+            .map_err(MongoQueryError)?;
+
+        // Calendar feeds are looked up by their token, which can only belong to a single list
+        client
+            .database(DB_NAME)
+            .collection::<Document>(TODOS)
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! {"calendar_token": 1})
+                    .options(IndexOptions::builder().unique(true).sparse(true).build())
+                    .build(),
+                None,
+            )
+            .await
+            .map_err(MongoQueryError)?;
+
+        client
+            .database(DB_NAME)
+            .collection::<Document>(HISTORY)
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! {SESSION: 1, "timestamp": -1})
+                    .build(),
+                None,
+            )
+            .await
+            .map_err(MongoQueryError)?;
+
+        client
+            .database(DB_NAME)
+            .collection::<Document>(WEBHOOKS)
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! {SESSION: 1, "events": 1})
+                    .build(),
+                None,
+            )
+            .await
+            .map_err(MongoQueryError)?;
+
+        client
+            .database(DB_NAME)
+            .collection::<Document>(DELIVERIES)
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! {SESSION: 1, "created_at": -1})
+                    .build(),
+                None,
+            )
+            .await
+            .map_err(MongoQueryError)?;
+
+        backfill_created_at(client).await
+    })
+    .await
+}
+
+// Todos stored before they had a creation time get their last change time, the closest thing
+// they have. Lists, the trash and history snapshots all hold todos
+async fn backfill_created_at(client: &Client) -> Result<()> {
+    let missing = |field: &str| doc! {field: {"$elemMatch": {"created_at": {"$exists": false}}}};
+    let backfilled = |todos: &str| {
+        doc! {"$map": {
+            "input": todos,
+            "in": {"$mergeObjects": [{"created_at": "$$this.timestamp"}, "$$this"]},
+        }}
+    };
+
     client
-        .database("admin")
-        .run_command(doc! {"ping":1}, None)
+        .database(DB_NAME)
+        .collection::<Document>(TODOS)
+        .update_many(
+            doc! {"$or": [
+                missing("todos"),
+                {"trash": {"$elemMatch": {"todo.created_at": {"$exists": false}}}},
+            ]},
+            vec![doc! {"$set": {
+                "todos": backfilled("$todos"),
+                "trash": {"$map": {
+                    // Lists from before the trash don't have one
+                    "input": {"$ifNull": ["$trash", []]},
+                    "as": "trashed",
+                    "in": {"$mergeObjects": ["$$trashed", {"todo": {"$mergeObjects": [
+                        {"created_at": "$$trashed.todo.timestamp"},
+                        "$$trashed.todo",
+                    ]}}]},
+                }},
+            }}],
+            None,
+        )
         .await
-        .map_err(MongoQueryError)
+        .map_err(MongoQueryError)?;
+
+    client
+        .database(DB_NAME)
+        .collection::<Document>(HISTORY)
+        .update_many(
+            doc! {"$or": [missing("before"), missing("after")]},
+            vec![doc! {"$set": {
+                "before": backfilled("$before"),
+                "after": backfilled("$after"),
+            }}],
+            None,
+        )
+        .await
+        .map_err(MongoQueryError)?;
+    Ok(())
 }
 
 pub fn uuid_to_bson(uuid: &Uuid) -> Result<Bson> {
@@ -26,37 +234,125 @@ pub fn uuid_to_bson(uuid: &Uuid) -> Result<Bson> {
 }
 
 pub async fn create_todo_list(client: &Client) -> Result<data::TodoList> {
-    // Create a new dummy todo list
-    let todo_list = data::TodoList {
-        session: data::Session::new(),
-        todos: vec!["Delete This Todo".into()],
-    };
+    instrumented("create_todo_list", async move {
+        // Create a new dummy todo list
+        let todo_list = data::TodoList {
+            session: data::Session::new(),
+            todos: vec!["Delete This Todo".into()],
+            trash: Vec::new(),
+            calendar_token: None,
+        };
+
+        // Insert it into the Database
+        client
+            .database(DB_NAME)
+            .collection::<data::TodoList>(TODOS)
+            .insert_one(&todo_list, None)
+            .await
+            .map_err(MongoQueryError)?;
+        Ok(todo_list)
+    })
+    .await
+}
+
+pub async fn get_todo_list(client: &Client, session: &data::Session) -> Result<data::TodoList> {
+    instrumented("get_todo_list", find_todo_list(client, session)).await
+}
+
+// The lookup behind get_todo_list, for the operations that read the list on their way
+async fn find_todo_list(client: &Client, session: &data::Session) -> Result<data::TodoList> {
+    let filter = doc! {SESSION: uuid_to_bson(session.id())?};
 
-    // Insert it into the Database
     client
         .database(DB_NAME)
         .collection::<data::TodoList>(TODOS)
-        .insert_one(&todo_list, None)
+        .find_one(Some(filter), None)
         .await
-        .map_err(MongoQueryError)?;
-    Ok(todo_list)
+        .map_err(MongoQueryError)?
+        .ok_or(NonexistentResourceError)
+}
+
+// Look up the list a calendar feed token belongs to
+pub async fn get_todo_list_by_calendar_token(
+    client: &Client,
+    token_hash: &str,
+) -> Result<data::TodoList> {
+    instrumented("get_todo_list_by_calendar_token", async move {
+        client
+            .database(DB_NAME)
+            .collection::<data::TodoList>(TODOS)
+            .find_one(doc! {"calendar_token": token_hash}, None)
+            .await
+            .map_err(MongoQueryError)?
+            .ok_or(NonexistentResourceError)
+    })
+    .await
+}
+
+// Set the hash of the token for a list's calendar feed, replacing any previous one. Passing None
+// revokes the feed
+pub async fn set_calendar_token(
+    client: &Client,
+    session: &data::Session,
+    token_hash: Option<&str>,
+) -> Result<()> {
+    instrumented("set_calendar_token", async move {
+        let filter = doc! {SESSION: uuid_to_bson(session.id())?};
+        let update = match token_hash {
+            Some(token_hash) => doc! {"$set": {"calendar_token": token_hash}},
+            None => doc! {"$unset": {"calendar_token": ""}},
+        };
+
+        let result = client
+            .database(DB_NAME)
+            .collection::<Document>(TODOS)
+            .update_one(filter, update, None)
+            .await
+            .map_err(MongoQueryError)?;
+
+        if result.matched_count == 0 {
+            return Err(NonexistentResourceError);
+        }
+        Ok(())
+    })
+    .await
 }
 
 pub async fn get_todos(client: &Client, session: &data::Session) -> Result<Vec<data::Todo>> {
-    let filter = doc! {SESSION: uuid_to_bson(session.id())?};
+    instrumented("get_todos", async move {
+        Ok(find_todo_list(client, session).await?.todos)
+    })
+    .await
+}
 
-    let result = client
+// Apply an update to a todo list, recording the state of the list before the update in its history.
+// The filter must only match lists the update changes, returns false if it matched none
+async fn update_todo_list(
+    client: &Client,
+    session: &data::Session,
+    filter: Document,
+    update: impl Into<UpdateModifications>,
+    action: data::HistoryAction,
+    todo: Option<Uuid>,
+) -> Result<bool> {
+    // find_one_and_update hands back the document as it was before the update was applied
+    let before = client
         .database(DB_NAME)
         .collection::<data::TodoList>(TODOS)
-        // .collection::<Document>(TODOS)
-        .find_one(Some(filter), None)
+        .find_one_and_update(filter, update, None)
         .await
         .map_err(MongoQueryError)?;
 
-    if let Some(todo_list) = result {
-        Ok(todo_list.todos)
-    } else {
-        Err(NonexistentResourceError)
+    match before {
+        Some(before) => {
+            record_history(
+                client,
+                data::HistoryEntry::new(session, action, todo, before.todos),
+            )
+            .await?;
+            Ok(true)
+        }
+        None => Ok(false),
     }
 }
 
@@ -65,26 +361,32 @@ pub async fn create_todo(
     session: &data::Session,
     todo: &data::Todo,
 ) -> Result<()> {
-    // Create a TODO and Only keep the 10 most recent ones
-    let filter = doc! {SESSION: uuid_to_bson(session.id())?};
-    let todo = bson::to_bson(todo).map_err(SerializationError)?;
-    let update = doc! {
-    "$push": {
-        "todos": {
-            "$each": vec![todo],
-            "$slice": 10,
-        }
-    }};
-
-    // Find the Document and push a todo
-    client
-        .database(DB_NAME)
-        .collection::<Document>(TODOS)
-        .find_one_and_update(filter, update, None)
-        .await
-        .map_err(MongoQueryError)?;
+    instrumented("create_todo", async move {
+        // A full list doesn't match, so the todo is never pushed past the limit
+        let filter = doc! {
+            SESSION: uuid_to_bson(session.id())?,
+            format!("todos.{}", data::MAX_TODOS - 1): {"$exists": false},
+        };
+        let todo_id = todo.id;
+        let todo = bson::to_bson(todo).map_err(SerializationError)?;
+        let update = doc! {"$push": {"todos": todo}};
 
-    Ok(())
+        // Find the Document and push a todo
+        let matched = update_todo_list(
+            client,
+            session,
+            filter,
+            update,
+            data::HistoryAction::CreateTodo,
+            Some(todo_id),
+        )
+        .await?;
+        if !matched {
+            return Err(TodoLimitError(data::MAX_TODOS));
+        }
+        Ok(())
+    })
+    .await
 }
 
 pub async fn update_todo(
@@ -93,21 +395,56 @@ pub async fn update_todo(
     todo_id: &uuid::Uuid,
     update: &data::TodoRequest,
 ) -> Result<()> {
-    let filter = doc! {
-        SESSION: uuid_to_bson(session.id())?,
-        "todos.id": bson::to_bson(todo_id).map_err(SerializationError)?
-    };
+    instrumented("update_todo", async move {
+        let filter = doc! {
+            SESSION: uuid_to_bson(session.id())?,
+            "todos.id": bson::to_bson(todo_id).map_err(SerializationError)?
+        };
 
-    let update = doc! { "$set": { "todos.$.name": &update.name , "todos.$.timestamp": bson::to_bson(&Utc::now()).unwrap()}};
+        let mut set = doc! {
+            "todos.$.name": &update.name,
+            "todos.$.timestamp": bson::to_bson(&Utc::now()).unwrap(),
+        };
+        // A request without a due date keeps the one the todo has
+        if let Some(due) = &update.due {
+            set.insert(
+                "todos.$.due",
+                bson::to_bson(due).map_err(SerializationError)?,
+            );
+        }
+        let update = doc! {"$set": set};
 
-    client
-        .database(DB_NAME)
-        .collection::<Document>(TODOS)
-        .find_one_and_update(filter, update, None)
-        .await
-        .map_err(MongoQueryError)?;
+        let matched = update_todo_list(
+            client,
+            session,
+            filter,
+            update,
+            data::HistoryAction::UpdateTodo,
+            Some(*todo_id),
+        )
+        .await?;
+        if !matched {
+            return Err(NonexistentResourceError);
+        }
+        Ok(())
+    })
+    .await
+}
 
-    Ok(())
+// Build an update pipeline that moves the todos matching `condition` into the trash in a single
+// atomic update, setting the remaining todos to `remaining`
+fn move_to_trash(condition: Document, remaining: impl Into<Bson>) -> Vec<Document> {
+    vec![doc! {"$set": {
+        "trash": {"$concatArrays": [
+            {"$ifNull": ["$trash", []]},
+            {"$map": {
+                "input": {"$filter": {"input": "$todos", "as": "todo", "cond": condition}},
+                "as": "todo",
+                "in": {"todo": "$$todo", "deleted_at": "$$NOW"},
+            }},
+        ]},
+        TODOS: remaining.into(),
+    }}]
 }
 
 pub async fn delete_todo(
@@ -115,30 +452,726 @@ pub async fn delete_todo(
     session: &data::Session,
     todo_id: &uuid::Uuid,
 ) -> Result<()> {
-    let filter = doc! {SESSION: uuid_to_bson(session.id())?};
-    let update =
-        doc! {"$pull": {"todos": {"id": bson::to_bson(todo_id).map_err(SerializationError)?}}};
+    instrumented("delete_todo", async move {
+        let id = bson::to_bson(todo_id).map_err(SerializationError)?;
+        // Deleting a todo that isn't in the list leaves nothing to undo
+        let filter = doc! {SESSION: uuid_to_bson(session.id())?, "todos.id": &id};
+        let update = move_to_trash(
+            doc! {"$eq": ["$$todo.id", &id]},
+            doc! {"$filter": {"input": "$todos", "as": "todo", "cond": {"$ne": ["$$todo.id", &id]}}},
+        );
 
-    client
+        let matched = update_todo_list(
+            client,
+            session,
+            filter,
+            update,
+            data::HistoryAction::DeleteTodo,
+            Some(*todo_id),
+        )
+        .await?;
+        if !matched {
+            return Err(NonexistentResourceError);
+        }
+        Ok(())
+    })
+    .await
+}
+
+// Returns false if the list was already empty
+pub async fn delete_all_todos(client: &Client, session: &data::Session) -> Result<bool> {
+    instrumented("delete_all_todos", async move {
+        // Clearing an empty list leaves nothing to undo
+        let filter = doc! {SESSION: uuid_to_bson(session.id())?, "todos.0": {"$exists": true}};
+        let update = move_to_trash(doc! {"$literal": true}, Bson::Array(Vec::new()));
+
+        update_todo_list(
+            client,
+            session,
+            filter,
+            update,
+            data::HistoryAction::DeleteAllTodos,
+            None,
+        )
+        .await
+    })
+    .await
+}
+
+fn set_todos(todos: &[data::Todo]) -> Result<Document> {
+    Ok(doc! {"$set": {TODOS: bson::to_bson(todos).map_err(SerializationError)?}})
+}
+
+// Update a list, but only if its todos still match what the caller last read.
+// Returns false if the list was modified in the meantime
+async fn replace_todos(
+    client: &Client,
+    session: &data::Session,
+    original: &[data::Todo],
+    update: Document,
+) -> Result<bool> {
+    let filter = doc! {
+        SESSION: uuid_to_bson(session.id())?,
+        TODOS: bson::to_bson(original).map_err(SerializationError)?,
+    };
+
+    let result = client
         .database(DB_NAME)
         .collection::<Document>(TODOS)
-        .find_one_and_update(filter, update, None)
+        .update_one(filter, update, None)
         .await
         .map_err(MongoQueryError)?;
 
-    Ok(())
+    Ok(result.matched_count == 1)
 }
 
-pub async fn delete_all_todos(client: &Client, session: &data::Session) -> Result<()> {
-    let filter = doc! {SESSION: uuid_to_bson(session.id())?};
-    let update = doc! { "$set": {"todos": []}};
+pub async fn execute_batch(
+    client: &Client,
+    session: &data::Session,
+    operations: &[data::BatchOperation],
+) -> Result<data::BatchResponse> {
+    instrumented("execute_batch", async move {
+        for _ in 0..BATCH_RETRIES {
+            let original = find_todo_list(client, session).await?.todos;
+            let mut todos = original.clone();
 
-    client
+            // Apply the operations in memory, nothing is written unless all of them succeed
+            let response = data::BatchResponse::execute(operations, &mut todos);
+            if !response.committed {
+                return Ok(response);
+            }
+
+            // Deleted todos go to the trash along with the new list
+            let trashed: Vec<data::TrashedTodo> = operations
+                .iter()
+                .zip(&response.results)
+                .filter(|(operation, _)| matches!(operation, data::BatchOperation::Delete { .. }))
+                .filter_map(|(_, result)| result.todo.clone().map(data::TrashedTodo::from))
+                .collect();
+            let mut update = set_todos(&todos)?;
+            update.insert(
+                "$push",
+                doc! {"trash": {"$each": bson::to_bson(&trashed).map_err(SerializationError)?}},
+            );
+
+            // Only replace the todos if nobody else modified the list since we read it, updates to a
+            // single document are atomic so the batch is either applied fully or not at all
+            if replace_todos(client, session, &original, update).await? {
+                let entry =
+                    data::HistoryEntry::new(session, data::HistoryAction::Batch, None, original);
+                record_history(client, entry).await?;
+                return Ok(response);
+            }
+            tracing::debug!("Todo list changed while applying batch, retrying");
+        }
+
+        Err(ConcurrentModificationError)
+    })
+    .await
+}
+
+pub async fn import_todos(
+    client: &Client,
+    session: &data::Session,
+    mode: formats::ImportMode,
+    import: &formats::Import,
+) -> Result<(formats::ImportReport, Vec<data::Todo>)> {
+    instrumented("import_todos", async move {
+        for _ in 0..BATCH_RETRIES {
+            let original = find_todo_list(client, session).await?;
+            let mut todos = original.todos.clone();
+            let mut trash = original.trash;
+            let report = formats::ImportReport::execute(mode, import, &mut todos, &mut trash);
+
+            let update = doc! {"$set": {
+                TODOS: bson::to_bson(&todos).map_err(SerializationError)?,
+                "trash": bson::to_bson(&trash).map_err(SerializationError)?,
+            }};
+            if replace_todos(client, session, &original.todos, update).await? {
+                let entry = data::HistoryEntry::new(
+                    session,
+                    data::HistoryAction::Import,
+                    None,
+                    original.todos,
+                );
+                record_history(client, entry).await?;
+                return Ok((report, todos));
+            }
+            tracing::debug!("Todo list changed while importing, retrying");
+        }
+
+        Err(ConcurrentModificationError)
+    })
+    .await
+}
+
+fn history_filter(session: &data::Session) -> Result<Document> {
+    Ok(doc! {SESSION: uuid_to_bson(session.id())?})
+}
+
+async fn record_history(client: &Client, entry: data::HistoryEntry) -> Result<()> {
+    let history = client
         .database(DB_NAME)
-        .collection::<Document>(TODOS)
-        .find_one_and_update(filter, update, None)
+        .collection::<data::HistoryEntry>(HISTORY);
+
+    // A new change invalidates anything that was undone before it
+    let mut undone = history_filter(&entry.session)?;
+    undone.insert("undone", true);
+    history
+        .delete_many(undone, None)
         .await
         .map_err(MongoQueryError)?;
 
+    history
+        .insert_one(&entry, None)
+        .await
+        .map_err(MongoQueryError)?;
+
+    // Only keep a limited number of changes per list
+    let options = FindOptions::builder()
+        .sort(doc! {"timestamp": -1, "_id": -1})
+        .skip(HISTORY_LIMIT)
+        .projection(doc! {"_id": 1})
+        .build();
+    let stale: Vec<Bson> = client
+        .database(DB_NAME)
+        .collection::<Document>(HISTORY)
+        .find(history_filter(&entry.session)?, options)
+        .await
+        .map_err(MongoQueryError)?
+        .try_collect::<Vec<Document>>()
+        .await
+        .map_err(MongoQueryError)?
+        .into_iter()
+        .filter_map(|document| document.get("_id").cloned())
+        .collect();
+
+    if !stale.is_empty() {
+        history
+            .delete_many(doc! {"_id": {"$in": stale}}, None)
+            .await
+            .map_err(MongoQueryError)?;
+    }
+
     Ok(())
 }
+
+pub async fn get_history(
+    client: &Client,
+    session: &data::Session,
+    limit: i64,
+) -> Result<Vec<data::HistoryEntry>> {
+    instrumented("get_history", async move {
+        if limit < 1 || limit > HISTORY_LIMIT as i64 {
+            return Err(ValidationError(format!(
+                "limit must be between 1 and {}",
+                HISTORY_LIMIT
+            )));
+        }
+        let options = FindOptions::builder()
+            .sort(doc! {"timestamp": -1, "_id": -1})
+            .limit(limit)
+            .build();
+
+        client
+            .database(DB_NAME)
+            .collection::<data::HistoryEntry>(HISTORY)
+            .find(history_filter(session)?, options)
+            .await
+            .map_err(MongoQueryError)?
+            .try_collect()
+            .await
+            .map_err(MongoQueryError)
+    })
+    .await
+}
+
+// Set the todos of a list to a snapshot from its history. Todos that come back from the trash
+// this way are taken out of it
+fn restore_todos(todos: &[data::Todo]) -> Result<Document> {
+    let ids = todos
+        .iter()
+        .map(|todo| bson::to_bson(&todo.id))
+        .collect::<std::result::Result<Vec<Bson>, _>>()
+        .map_err(SerializationError)?;
+    let mut update = set_todos(todos)?;
+    update.insert("$pull", doc! {"trash": {"todo.id": {"$in": ids}}});
+    Ok(update)
+}
+
+// Revert the most recent change that has not been undone, returning the restored todos
+pub async fn undo(client: &Client, session: &data::Session) -> Result<Vec<data::Todo>> {
+    instrumented("undo", async move {
+        let mut filter = history_filter(session)?;
+        filter.insert("undone", false);
+        let options = FindOneOptions::builder()
+            .sort(doc! {"timestamp": -1, "_id": -1})
+            .build();
+
+        let entry = client
+            .database(DB_NAME)
+            .collection::<data::HistoryEntry>(HISTORY)
+            .find_one(filter, options)
+            .await
+            .map_err(MongoQueryError)?
+            .ok_or(NonexistentResourceError)?;
+
+        let current = find_todo_list(client, session).await?.todos;
+        if !replace_todos(client, session, &current, restore_todos(&entry.before)?).await? {
+            return Err(ConcurrentModificationError);
+        }
+
+        // Hold on to the current state so the change can be redone
+        let update = doc! {"$set": {
+            "undone": true,
+            "after": bson::to_bson(&current).map_err(SerializationError)?,
+        }};
+        client
+            .database(DB_NAME)
+            .collection::<Document>(HISTORY)
+            .update_one(doc! {"_id": entry.id}, update, None)
+            .await
+            .map_err(MongoQueryError)?;
+
+        Ok(entry.before)
+    })
+    .await
+}
+
+// Reapply the oldest change that was undone, returning the resulting todos
+pub async fn redo(client: &Client, session: &data::Session) -> Result<Vec<data::Todo>> {
+    instrumented("redo", async move {
+        let mut filter = history_filter(session)?;
+        filter.insert("undone", true);
+        let options = FindOneOptions::builder()
+            .sort(doc! {"timestamp": 1, "_id": 1})
+            .build();
+
+        let entry = client
+            .database(DB_NAME)
+            .collection::<data::HistoryEntry>(HISTORY)
+            .find_one(filter, options)
+            .await
+            .map_err(MongoQueryError)?
+            .ok_or(NonexistentResourceError)?;
+        let after = entry.after.ok_or(NonexistentResourceError)?;
+
+        let current = find_todo_list(client, session).await?.todos;
+        if !replace_todos(client, session, &current, restore_todos(&after)?).await? {
+            return Err(ConcurrentModificationError);
+        }
+
+        client
+            .database(DB_NAME)
+            .collection::<Document>(HISTORY)
+            .update_one(
+                doc! {"_id": entry.id},
+                doc! {"$set": {"undone": false}},
+                None,
+            )
+            .await
+            .map_err(MongoQueryError)?;
+
+        Ok(after)
+    })
+    .await
+}
+
+fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    matches!(
+        error.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) if write_error.code == 11000
+    )
+}
+
+fn idempotency_filter(record: &data::IdempotencyRecord) -> Result<Document> {
+    Ok(doc! {SESSION: uuid_to_bson(record.session.id())?, "key": &record.key})
+}
+
+// Matches the record only while it is still held by the request that claimed it
+fn claim_filter(record: &data::IdempotencyRecord) -> Result<Document> {
+    let mut filter = idempotency_filter(record)?;
+    filter.insert("claim", uuid_to_bson(&record.claim)?);
+    Ok(filter)
+}
+
+// Claim an idempotency key for a request. Returns the existing record if the key was already
+// claimed by an earlier request, or None if the caller now owns the key
+pub async fn claim_idempotency_key(
+    client: &Client,
+    record: &data::IdempotencyRecord,
+) -> Result<Option<data::IdempotencyRecord>> {
+    instrumented("claim_idempotency_key", async move {
+        let collection = client
+            .database(DB_NAME)
+            .collection::<data::IdempotencyRecord>(IDEMPOTENCY);
+        let filter = idempotency_filter(record)?;
+
+        // Expired records may linger until mongo's TTL monitor runs, so clear them out first
+        let mut expired = filter.clone();
+        expired.insert("expires_at", doc! {"$lte": bson::DateTime::now()});
+        collection
+            .delete_many(expired, None)
+            .await
+            .map_err(MongoQueryError)?;
+
+        match collection.insert_one(record, None).await {
+            Ok(_) => return Ok(None),
+            Err(error) if is_duplicate_key(&error) => {}
+            Err(error) => return Err(MongoQueryError(error)),
+        }
+
+        // A claim that outlived its lease without a response was never going to get one, so
+        // take it over instead of answering every retry with a conflict
+        let mut abandoned = filter.clone();
+        abandoned.insert("response", Bson::Null);
+        abandoned.insert("locked_until", doc! {"$lte": bson::DateTime::now()});
+        let takeover = doc! {"$set": {
+            "fingerprint": &record.fingerprint,
+            "claim": uuid_to_bson(&record.claim)?,
+            "locked_until": bson::DateTime::from_chrono(record.locked_until),
+            "expires_at": bson::DateTime::from_chrono(record.expires_at),
+        }};
+        if collection
+            .find_one_and_update(abandoned, takeover, None)
+            .await
+            .map_err(MongoQueryError)?
+            .is_some()
+        {
+            return Ok(None);
+        }
+
+        collection
+            .find_one(filter, None)
+            .await
+            .map_err(MongoQueryError)
+    })
+    .await
+}
+
+pub async fn complete_idempotency_key(
+    client: &Client,
+    record: &data::IdempotencyRecord,
+    response: &data::StoredResponse,
+) -> Result<()> {
+    instrumented("complete_idempotency_key", async move {
+        let update =
+            doc! {"$set": {"response": bson::to_bson(response).map_err(SerializationError)?}};
+
+        client
+            .database(DB_NAME)
+            .collection::<Document>(IDEMPOTENCY)
+            .update_one(claim_filter(record)?, update, None)
+            .await
+            .map_err(MongoQueryError)?;
+
+        Ok(())
+    })
+    .await
+}
+
+// Release a claimed key when the request failed so the client is free to retry it
+pub async fn release_idempotency_key(
+    client: &Client,
+    record: &data::IdempotencyRecord,
+) -> Result<()> {
+    instrumented("release_idempotency_key", async move {
+        client
+            .database(DB_NAME)
+            .collection::<Document>(IDEMPOTENCY)
+            .delete_one(claim_filter(record)?, None)
+            .await
+            .map_err(MongoQueryError)?;
+
+        Ok(())
+    })
+    .await
+}
+
+pub async fn get_trash(client: &Client, session: &data::Session) -> Result<Vec<data::TrashedTodo>> {
+    instrumented("get_trash", async move {
+        Ok(find_todo_list(client, session).await?.trash)
+    })
+    .await
+}
+
+// Move a todo out of the trash and back into the list
+pub async fn restore_todo(
+    client: &Client,
+    session: &data::Session,
+    todo_id: &uuid::Uuid,
+) -> Result<()> {
+    instrumented("restore_todo", async move {
+        let list = find_todo_list(client, session).await?;
+        let trashed = list
+            .trash
+            .into_iter()
+            .find(|trashed| &trashed.todo.id == todo_id)
+            .ok_or(NonexistentResourceError)?;
+        if list.todos.len() >= data::MAX_TODOS {
+            return Err(TodoLimitError(data::MAX_TODOS));
+        }
+
+        // Guard against the list filling up or the todo being restored by someone else in the meantime
+        let id = bson::to_bson(todo_id).map_err(SerializationError)?;
+        let filter = doc! {
+            SESSION: uuid_to_bson(session.id())?,
+            "trash.todo.id": &id,
+            format!("todos.{}", data::MAX_TODOS - 1): {"$exists": false},
+        };
+        let update = doc! {
+            "$pull": {"trash": {"todo.id": &id}},
+            "$push": {TODOS: bson::to_bson(&trashed.todo).map_err(SerializationError)?},
+        };
+
+        let before = client
+            .database(DB_NAME)
+            .collection::<data::TodoList>(TODOS)
+            .find_one_and_update(filter, update, None)
+            .await
+            .map_err(MongoQueryError)?
+            .ok_or(ConcurrentModificationError)?;
+
+        record_history(
+            client,
+            data::HistoryEntry::new(
+                session,
+                data::HistoryAction::RestoreTodo,
+                Some(*todo_id),
+                before.todos,
+            ),
+        )
+        .await
+    })
+    .await
+}
+
+// Permanently delete a todo from the trash
+pub async fn purge_todo(
+    client: &Client,
+    session: &data::Session,
+    todo_id: &uuid::Uuid,
+) -> Result<()> {
+    instrumented("purge_todo", async move {
+        let id = bson::to_bson(todo_id).map_err(SerializationError)?;
+        let filter = doc! {SESSION: uuid_to_bson(session.id())?, "trash.todo.id": &id};
+        let update = doc! {"$pull": {"trash": {"todo.id": &id}}};
+
+        let result = client
+            .database(DB_NAME)
+            .collection::<Document>(TODOS)
+            .update_one(filter, update, None)
+            .await
+            .map_err(MongoQueryError)?;
+
+        if result.matched_count == 0 {
+            return Err(NonexistentResourceError);
+        }
+        Ok(())
+    })
+    .await
+}
+
+// Permanently delete everything in the trash of a list
+pub async fn empty_trash(client: &Client, session: &data::Session) -> Result<()> {
+    instrumented("empty_trash", async move {
+        let filter = doc! {SESSION: uuid_to_bson(session.id())?};
+        let update = doc! {"$set": {"trash": []}};
+
+        client
+            .database(DB_NAME)
+            .collection::<Document>(TODOS)
+            .update_one(filter, update, None)
+            .await
+            .map_err(MongoQueryError)?;
+
+        Ok(())
+    })
+    .await
+}
+
+// Permanently delete todos from every list that were trashed before the cutoff, returning the
+// number of lists that were modified
+pub async fn purge_expired_trash(client: &Client, cutoff: DateTime<Utc>) -> Result<u64> {
+    instrumented("purge_expired_trash", async move {
+        let filter = doc! {"trash.deleted_at": {"$lt": bson::DateTime::from_chrono(cutoff)}};
+        let update =
+            doc! {"$pull": {"trash": {"deleted_at": {"$lt": bson::DateTime::from_chrono(cutoff)}}}};
+
+        let result = client
+            .database(DB_NAME)
+            .collection::<Document>(TODOS)
+            .update_many(filter, update, None)
+            .await
+            .map_err(MongoQueryError)?;
+
+        Ok(result.modified_count)
+    })
+    .await
+}
+
+// Watch every todo list for changes, resuming after the given token if the stream was interrupted.
+// Only available when mongo is running as a replica set
+pub async fn watch_todo_lists(
+    client: &Client,
+    resume_after: Option<ResumeToken>,
+) -> Result<ChangeStream<ChangeStreamEvent<data::TodoList>>> {
+    instrumented("watch_todo_lists", async move {
+        let options = ChangeStreamOptions::builder()
+            .full_document(Some(FullDocumentType::UpdateLookup))
+            .resume_after(resume_after)
+            .build();
+
+        client
+            .database(DB_NAME)
+            .collection::<data::TodoList>(TODOS)
+            .watch(None, options)
+            .await
+            .map_err(MongoQueryError)
+    })
+    .await
+}
+
+pub async fn create_webhook(client: &Client, webhook: &data::Webhook) -> Result<()> {
+    instrumented("create_webhook", async move {
+        client
+            .database(DB_NAME)
+            .collection::<data::Webhook>(WEBHOOKS)
+            .insert_one(webhook, None)
+            .await
+            .map_err(MongoQueryError)?;
+
+        Ok(())
+    })
+    .await
+}
+
+pub async fn count_webhooks(client: &Client, session: &data::Session) -> Result<u64> {
+    instrumented("count_webhooks", async move {
+        client
+            .database(DB_NAME)
+            .collection::<Document>(WEBHOOKS)
+            .count_documents(doc! {SESSION: uuid_to_bson(session.id())?}, None)
+            .await
+            .map_err(MongoQueryError)
+    })
+    .await
+}
+
+pub async fn get_webhooks(client: &Client, session: &data::Session) -> Result<Vec<data::Webhook>> {
+    instrumented("get_webhooks", async move {
+        client
+            .database(DB_NAME)
+            .collection::<data::Webhook>(WEBHOOKS)
+            .find(doc! {SESSION: uuid_to_bson(session.id())?}, None)
+            .await
+            .map_err(MongoQueryError)?
+            .try_collect()
+            .await
+            .map_err(MongoQueryError)
+    })
+    .await
+}
+
+// Find the webhooks of a session that are subscribed to an event
+pub async fn get_subscribed_webhooks(
+    client: &Client,
+    session: &data::Session,
+    event: data::WebhookEvent,
+) -> Result<Vec<data::Webhook>> {
+    instrumented("get_subscribed_webhooks", async move {
+        let filter = doc! {
+            SESSION: uuid_to_bson(session.id())?,
+            "events": bson::to_bson(&event).map_err(SerializationError)?,
+        };
+
+        client
+            .database(DB_NAME)
+            .collection::<data::Webhook>(WEBHOOKS)
+            .find(filter, None)
+            .await
+            .map_err(MongoQueryError)?
+            .try_collect()
+            .await
+            .map_err(MongoQueryError)
+    })
+    .await
+}
+
+pub async fn delete_webhook(
+    client: &Client,
+    session: &data::Session,
+    webhook_id: &uuid::Uuid,
+) -> Result<()> {
+    instrumented("delete_webhook", async move {
+        let filter = doc! {
+            SESSION: uuid_to_bson(session.id())?,
+            "id": bson::to_bson(webhook_id).map_err(SerializationError)?,
+        };
+
+        let result = client
+            .database(DB_NAME)
+            .collection::<Document>(WEBHOOKS)
+            .delete_one(filter, None)
+            .await
+            .map_err(MongoQueryError)?;
+
+        if result.deleted_count == 0 {
+            return Err(NonexistentResourceError);
+        }
+        Ok(())
+    })
+    .await
+}
+
+// Insert a delivery, or replace it with its latest state if it already exists
+pub async fn save_delivery(client: &Client, delivery: &data::Delivery) -> Result<()> {
+    instrumented("save_delivery", async move {
+        let filter = doc! {"id": bson::to_bson(&delivery.id).map_err(SerializationError)?};
+        let options = mongodb::options::ReplaceOptions::builder()
+            .upsert(true)
+            .build();
+
+        client
+            .database(DB_NAME)
+            .collection::<data::Delivery>(DELIVERIES)
+            .replace_one(filter, delivery, options)
+            .await
+            .map_err(MongoQueryError)?;
+
+        Ok(())
+    })
+    .await
+}
+
+pub async fn get_deliveries(
+    client: &Client,
+    session: &data::Session,
+    webhook_id: Option<&uuid::Uuid>,
+    limit: i64,
+) -> Result<Vec<data::Delivery>> {
+    instrumented("get_deliveries", async move {
+        let mut filter = doc! {SESSION: uuid_to_bson(session.id())?};
+        if let Some(webhook_id) = webhook_id {
+            filter.insert(
+                "webhook",
+                bson::to_bson(webhook_id).map_err(SerializationError)?,
+            );
+        }
+        let options = FindOptions::builder()
+            .sort(doc! {"created_at": -1})
+            .limit(limit)
+            .build();
+
+        client
+            .database(DB_NAME)
+            .collection::<data::Delivery>(DELIVERIES)
+            .find(filter, options)
+            .await
+            .map_err(MongoQueryError)?
+            .try_collect()
+            .await
+            .map_err(MongoQueryError)
+    })
+    .await
+}
@@ -13,6 +13,15 @@ pub enum Error {
     #[error("Could not Parse Server Configuration from \"{0}\"")]
     ServerConfigError(String),
 
+    #[error("Could not bind server to its address: {0}")]
+    ServerBindError(std::io::Error),
+
+    #[error("Could not listen on {address}: {reason}")]
+    ListenError { address: String, reason: String },
+
+    #[error("Could not load the TLS certificate: {0}")]
+    TlsError(String),
+
     #[error("could not access field in document: {0}")]
     MongoDataError(#[from] bson::document::ValueAccessError),
 
@@ -28,6 +37,42 @@ pub enum Error {
     #[error("Item does not exist in collection")]
     NonexistentResourceError,
 
+    #[error("Todo list cannot hold more than {0} items")]
+    TodoLimitError(usize),
+
+    #[error("A session cannot register more than {0} webhooks")]
+    WebhookLimitError(u64),
+
+    #[error("Todo list was modified by another request")]
+    ConcurrentModificationError,
+
+    #[error("Idempotency key was already used with a different request")]
+    IdempotencyKeyMismatchError,
+
+    #[error("A request with this idempotency key is still being processed")]
+    IdempotentRequestInProgressError,
+
+    #[error("Invalid request: {0}")]
+    ValidationError(String),
+
     #[error("Unhandled Serialization Error: {0}")]
     SerializationError(mongodb::bson::ser::Error),
+
+    #[error("Unhandled JSON Serialization Error: {0}")]
+    JsonSerializationError(serde_json::Error),
+
+    #[error("Unhandled CSV Serialization Error: {0}")]
+    CsvError(csv::Error),
+
+    #[error("Could not encode metrics: {0}")]
+    MetricsError(prometheus::Error),
+
+    #[error("Could not set up trace export: {0}")]
+    TelemetryError(opentelemetry::trace::TraceError),
+
+    #[error("Could not open the log file: {0}")]
+    LogFileError(tracing_appender::rolling::InitError),
+
+    #[error("Could not change the log filter: {0}")]
+    LogFilterError(String),
 }
@@ -0,0 +1,242 @@
+use crate::{config, data};
+use chrono::prelude::*;
+use futures::{Stream, StreamExt};
+use mongodb::change_stream::event::ChangeStreamEvent;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+    TodoCreated {
+        todo: data::Todo,
+    },
+    // Todo ids are sent as id, the name WebSocket clients have been reading them by
+    TodoUpdated {
+        #[serde(rename = "id")]
+        todo_id: uuid::Uuid,
+        name: String,
+    },
+    TodoCompleted {
+        todo: data::Todo,
+    },
+    TodoDeleted {
+        #[serde(rename = "id")]
+        todo_id: uuid::Uuid,
+    },
+    TodoRestored {
+        #[serde(rename = "id")]
+        todo_id: uuid::Uuid,
+    },
+    ListCleared,
+    // The list was replaced wholesale, e.g. by an undo
+    ListChanged {
+        todos: Vec<data::Todo>,
+    },
+    // Events were dropped before they could be delivered, the client should re-read the list
+    Resync,
+}
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::TodoCreated { .. } => "todo_created",
+            EventKind::TodoUpdated { .. } => "todo_updated",
+            EventKind::TodoCompleted { .. } => "todo_completed",
+            EventKind::TodoDeleted { .. } => "todo_deleted",
+            EventKind::TodoRestored { .. } => "todo_restored",
+            EventKind::ListCleared => "list_cleared",
+            EventKind::ListChanged { .. } => "list_changed",
+            EventKind::Resync => "resync",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct Event {
+    // Increases monotonically across the feed, so clients can resume from the last id they saw.
+    // The kind's fields are flattened next to it, where id is already the todo's
+    #[serde(rename = "event_id")]
+    pub id: u64,
+    // Events are only delivered to listeners of the session they belong to
+    #[serde(skip)]
+    pub session: uuid::Uuid,
+    // Where the event came from, only events from the configured source make it into the feed
+    #[serde(skip, default = "default_source")]
+    pub source: config::EventSource,
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+fn default_source() -> config::EventSource {
+    config::EventSource::Local
+}
+
+impl Event {
+    pub fn new(session: &data::Session, kind: EventKind) -> Self {
+        Self {
+            id: 0,
+            session: *session.id(),
+            source: config::EventSource::Local,
+            timestamp: Utc::now(),
+            kind,
+        }
+    }
+}
+
+impl Event {
+    // Build an event from a change to a todo list, if the change left a list behind
+    pub fn from_change(change: ChangeStreamEvent<data::TodoList>) -> Option<Self> {
+        let list = change.full_document?;
+        let cluster_time = change.cluster_time?;
+
+        // Cluster times are ordered across the whole replica set, so every instance watching the
+        // stream ends up with the same ids for the same events
+        let mut event = Event::new(&list.session, EventKind::ListChanged { todos: list.todos });
+        event.id = (u64::from(cluster_time.time) << 32) | u64::from(cluster_time.increment);
+        event.source = config::EventSource::ChangeStream;
+        Some(event)
+    }
+}
+
+// The most recent events of the feed, kept so reconnecting clients can catch up
+struct Backlog {
+    next_id: u64,
+    events: VecDeque<Event>,
+}
+
+impl Backlog {
+    // Check if events after `last_id` were dropped from the backlog, or if the id is from before a
+    // restart and can't be related to the current feed at all
+    fn missed_since(&self, last_id: u64) -> bool {
+        if last_id >= self.next_id {
+            return true;
+        }
+        match self.events.front() {
+            Some(oldest) if oldest.id > last_id => oldest.id != last_id + 1,
+            _ => false,
+        }
+    }
+}
+
+// An in-process broadcast channel that handlers publish change events to after successful writes
+#[derive(Clone)]
+pub struct Hub {
+    sender: broadcast::Sender<Event>,
+    backlog: Arc<Mutex<Backlog>>,
+    settings: config::EventSettings,
+}
+
+impl Hub {
+    pub fn new(settings: &config::EventSettings) -> Self {
+        let (sender, _) = broadcast::channel(settings.capacity);
+        Self {
+            sender,
+            backlog: Arc::new(Mutex::new(Backlog {
+                next_id: 1,
+                events: VecDeque::with_capacity(settings.backlog),
+            })),
+            settings: settings.clone(),
+        }
+    }
+
+    // Publish an event for a write made by this instance
+    pub fn publish(&self, session: &data::Session, kind: EventKind) {
+        let event = Event::new(session, kind);
+        if self.settings.source == config::EventSource::Local {
+            self.publish_to_feed(event);
+        } else {
+            // Other listeners still get to see it, but the feed is sourced from the database
+            let _ = self.sender.send(event);
+        }
+    }
+
+    // Publish an event observed on a mongodb change stream, which already carries its id
+    pub fn publish_change(&self, mut event: Event) {
+        event.source = config::EventSource::ChangeStream;
+        if self.settings.source == config::EventSource::ChangeStream {
+            self.publish_to_feed(event);
+        }
+    }
+
+    fn publish_to_feed(&self, mut event: Event) {
+        let mut backlog = self.backlog.lock().unwrap();
+        if event.source == config::EventSource::Local {
+            event.id = backlog.next_id;
+        }
+        backlog.next_id = event.id + 1;
+        if backlog.events.len() >= self.settings.backlog {
+            backlog.events.pop_front();
+        }
+        backlog.events.push_back(event.clone());
+
+        // Send while holding the lock so subscribers see events in the same order as the backlog.
+        // Sending only fails when nobody is listening, which is fine
+        let _ = self.sender.send(event);
+    }
+
+    // Subscribe to every event published on the hub, whether or not it is part of the feed
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+
+    // Stream the feed of events for a session. When `last_id` is given any buffered events after
+    // it are replayed first, if some of them have already left the buffer a resync is sent instead
+    pub fn feed(
+        &self,
+        session: &data::Session,
+        last_id: Option<u64>,
+    ) -> impl Stream<Item = Event> + Send + 'static {
+        let session = *session.id();
+        let source = self.settings.source;
+
+        // Take the backlog and subscribe under the same lock so no event is missed or repeated
+        let (replay, receiver) = {
+            let backlog = self.backlog.lock().unwrap();
+            let receiver = self.sender.subscribe();
+            let replay = match last_id {
+                Some(last_id) => {
+                    let mut replay: Vec<Event> = backlog
+                        .events
+                        .iter()
+                        .filter(|event| event.id > last_id)
+                        .cloned()
+                        .collect();
+                    if backlog.missed_since(last_id) {
+                        replay.insert(0, resync(session));
+                    }
+                    replay
+                }
+                None => Vec::new(),
+            };
+            (replay, receiver)
+        };
+
+        let live = futures::stream::unfold(receiver, move |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) if event.source == source => return Some((event, receiver)),
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!(skipped, "Listener fell behind on todo events");
+                        return Some((resync(session), receiver));
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+
+        futures::stream::iter(replay)
+            .chain(live)
+            .filter(move |event| futures::future::ready(event.session == session))
+    }
+}
+
+fn resync(session: uuid::Uuid) -> Event {
+    Event::new(&data::Session::from(session), EventKind::Resync)
+}
@@ -0,0 +1,83 @@
+use super::{Export, Import, Row};
+use crate::error::{Error::*, Result};
+
+// Todos and trashed todos share a table, trashed todos are the rows with a deletion time
+pub fn export(export: &Export) -> Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let rows = export
+        .todos
+        .iter()
+        .map(Row::from)
+        .chain(export.trash.iter().map(Row::from));
+    for row in rows {
+        let row = Row {
+            name: escape_formula(&row.name),
+            ..row
+        };
+        writer.serialize(row).map_err(CsvError)?;
+    }
+    writer
+        .into_inner()
+        .map_err(|error| CsvError(error.into_error().into()))
+}
+
+pub fn import(body: &[u8]) -> Result<Import> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(body);
+    let headers = reader
+        .headers()
+        .map_err(|error| ValidationError(format!("could not read CSV header: {}", error)))?
+        .clone();
+
+    let mut import = Import::default();
+    for (index, record) in reader.records().enumerate() {
+        // Fall back to counting rows if the reader can't tell where it is, the header is line 1
+        let line = |position: Option<&csv::Position>| {
+            position.map_or(index + 2, |position| position.line() as usize)
+        };
+        match record {
+            Ok(record) => match record.deserialize::<Row>(Some(&headers)) {
+                Ok(row) => {
+                    let row = Row {
+                        name: unescape_formula(&row.name),
+                        ..row
+                    };
+                    import.push(line(record.position()), row)
+                }
+                Err(error) => import.reject(line(record.position()), error.to_string()),
+            },
+            Err(error) => import.reject(line(error.position()), error.to_string()),
+        }
+    }
+    Ok(import)
+}
+
+// Spreadsheets run cells starting with one of these as formulas, the set OWASP recommends escaping
+const FORMULA_PREFIXES: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+// A name is quoted if it starts a formula, or if it already starts with the quote of one. The
+// latter keeps names the user started with a quote themselves apart from quoted formulas
+fn is_formula(name: &str) -> bool {
+    match name.strip_prefix('\'') {
+        Some(rest) => is_formula(rest),
+        None => name.starts_with(FORMULA_PREFIXES),
+    }
+}
+
+// Quote names that a spreadsheet would run as a formula when the export is opened in one
+fn escape_formula(name: &str) -> String {
+    if is_formula(name) {
+        format!("'{}", name)
+    } else {
+        name.to_owned()
+    }
+}
+
+// Drop the quote added on export, so names round trip unchanged
+fn unescape_formula(name: &str) -> String {
+    match name.strip_prefix('\'') {
+        Some(rest) if is_formula(rest) => rest.to_owned(),
+        _ => name.to_owned(),
+    }
+}
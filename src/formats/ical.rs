@@ -0,0 +1,270 @@
+use super::{Export, Import, Row};
+use crate::data;
+use crate::error::{Error::*, Result};
+use chrono::prelude::*;
+use sha2::{Digest, Sha256};
+
+const PRODUCT_ID: &str = "-//warp_crud//Todo List//EN";
+const DATE_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+// Content lines longer than this many octets have to be folded
+const LINE_LIMIT: usize = 75;
+
+// Todos are exported as VTODO components of a single calendar. Trashed todos are left out,
+// calendar clients have no way to show them
+pub fn export(export: &Export) -> Result<Vec<u8>> {
+    let mut calendar = Calendar::default();
+    calendar.line("BEGIN", "VCALENDAR");
+    calendar.line("VERSION", "2.0");
+    calendar.line("PRODID", PRODUCT_ID);
+    calendar.line("CALSCALE", "GREGORIAN");
+
+    let now = Utc::now();
+    for todo in &export.todos {
+        calendar.todo(todo, now);
+    }
+
+    calendar.line("END", "VCALENDAR");
+    Ok(calendar.0.into_bytes())
+}
+
+#[derive(Default)]
+struct Calendar(String);
+
+impl Calendar {
+    fn todo(&mut self, todo: &data::Todo, now: DateTime<Utc>) {
+        self.line("BEGIN", "VTODO");
+        self.line("UID", &todo.id.to_string());
+        self.line("DTSTAMP", &format_date_time(&now));
+        self.line("CREATED", &format_date_time(&todo.created_at));
+        self.line("LAST-MODIFIED", &format_date_time(&todo.timestamp));
+        self.line("SUMMARY", &escape(&todo.name));
+        if let Some(due) = &todo.due {
+            self.line("DUE", &format_date_time(due));
+        }
+        if todo.completed {
+            self.line("STATUS", "COMPLETED");
+            self.line("PERCENT-COMPLETE", "100");
+            if let Some(completed_at) = &todo.completed_at {
+                self.line("COMPLETED", &format_date_time(completed_at));
+            }
+        } else {
+            self.line("STATUS", "NEEDS-ACTION");
+        }
+        self.line("END", "VTODO");
+    }
+
+    // Write a content line, folding it so no line is longer than the limit
+    fn line(&mut self, name: &str, value: &str) {
+        let line = format!("{}:{}", name, value);
+        let mut length = 0;
+        for character in line.chars() {
+            if length + character.len_utf8() > LINE_LIMIT {
+                // The leading space of a continuation counts towards its length
+                self.0.push_str("\r\n ");
+                length = 1;
+            }
+            self.0.push(character);
+            length += character.len_utf8();
+        }
+        self.0.push_str("\r\n");
+    }
+}
+
+fn format_date_time(date_time: &DateTime<Utc>) -> String {
+    date_time.format(DATE_TIME_FORMAT).to_string()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace(['\r', '\n'], "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut characters = text.chars();
+    while let Some(character) = characters.next() {
+        if character != '\\' {
+            unescaped.push(character);
+            continue;
+        }
+        match characters.next() {
+            Some('n') | Some('N') => unescaped.push('\n'),
+            Some(escaped) => unescaped.push(escaped),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+// Time zones that are just another name for UTC, times in any other zone can't be converted
+// without a time zone database
+const UTC_TIME_ZONES: [&str; 4] = ["UTC", "Etc/UTC", "GMT", "Etc/GMT"];
+
+// Parse a DATE-TIME or DATE value. Times without a zone are read as UTC and dates as midnight UTC,
+// that's as precise as a todo list needs to be. Times in other zones are refused rather than being
+// read as UTC and ending up hours off
+fn parse_date_time(value: &str, tzid: Option<&str>) -> std::result::Result<DateTime<Utc>, String> {
+    if let Some(tzid) = tzid.filter(|tzid| !UTC_TIME_ZONES.contains(tzid)) {
+        return Err(format!(
+            "time zone \"{}\" is not supported, times have to be in UTC",
+            tzid
+        ));
+    }
+    let value = value.trim_end_matches('Z');
+    NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+        .or_else(|_| {
+            NaiveDate::parse_from_str(value, "%Y%m%d")
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap())
+        })
+        .map(|date_time| Utc.from_utc_datetime(&date_time))
+        .map_err(|_| format!("invalid date \"{}\"", value))
+}
+
+// Todos from other calendars have arbitrary UIDs, derive an id from those so importing the same
+// calendar twice updates the todos instead of duplicating them
+fn parse_uid(uid: &str) -> uuid::Uuid {
+    uuid::Uuid::parse_str(uid).unwrap_or_else(|_| {
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&Sha256::digest(uid.as_bytes())[..16]);
+        uuid::Builder::from_bytes(bytes)
+            .set_variant(uuid::Variant::RFC4122)
+            .set_version(uuid::Version::Random)
+            .build()
+    })
+}
+
+// A VTODO as it is being read, along with the line it started on
+struct Component {
+    line: usize,
+    // Depth of components nested inside the todo, like alarms, whose properties are skipped
+    nested: usize,
+    row: Row,
+    status: Option<String>,
+    error: Option<String>,
+}
+
+impl Component {
+    fn new(line: usize) -> Self {
+        Self {
+            line,
+            nested: 0,
+            row: Row {
+                id: None,
+                name: String::new(),
+                priority: None,
+                timestamp: None,
+                created_at: None,
+                completed: None,
+                completed_at: None,
+                due: None,
+                deleted_at: None,
+            },
+            status: None,
+            error: None,
+        }
+    }
+
+    fn property(&mut self, name: &str, tzid: Option<&str>, value: &str) {
+        let date_time = || parse_date_time(value, tzid).map(Some);
+        let result = match name {
+            "UID" => {
+                self.row.id = Some(parse_uid(value));
+                Ok(())
+            }
+            "SUMMARY" => {
+                self.row.name = unescape(value);
+                Ok(())
+            }
+            "CREATED" => date_time().map(|created| self.row.created_at = created),
+            "LAST-MODIFIED" => date_time().map(|modified| self.row.timestamp = modified),
+            // The time the component was stamped stands in for the creation time it left out
+            "DTSTAMP" if self.row.created_at.is_none() => {
+                date_time().map(|stamp| self.row.created_at = stamp)
+            }
+            "DUE" => date_time().map(|due| self.row.due = due),
+            "COMPLETED" => date_time().map(|completed| self.row.completed_at = completed),
+            "STATUS" => {
+                self.status = Some(value.to_uppercase());
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+        if let Err(error) = result {
+            self.error.get_or_insert(error);
+        }
+    }
+
+    fn finish(mut self, import: &mut Import) {
+        if let Some(error) = self.error {
+            return import.reject(self.line, error);
+        }
+        self.row.completed =
+            Some(self.status.as_deref() == Some("COMPLETED") || self.row.completed_at.is_some());
+        import.push(self.line, self.row)
+    }
+}
+
+pub fn import(body: &[u8]) -> Result<Import> {
+    let text = std::str::from_utf8(body)
+        .map_err(|_| ValidationError(String::from("calendar must be UTF-8 encoded")))?;
+
+    // Unfold continuation lines first, remembering the line each content line started on
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        match (
+            line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')),
+            lines.last_mut(),
+        ) {
+            (Some(continuation), Some((_, previous))) => previous.push_str(continuation),
+            _ if line.is_empty() => {}
+            _ => lines.push((index + 1, line.to_owned())),
+        }
+    }
+    if !matches!(lines.first(), Some((_, line)) if line.eq_ignore_ascii_case("BEGIN:VCALENDAR")) {
+        return Err(ValidationError(String::from(
+            "calendar must start with BEGIN:VCALENDAR",
+        )));
+    }
+
+    let mut import = Import::default();
+    let mut component: Option<Component> = None;
+    for (line, content) in lines {
+        let (name, value) = match content.split_once(':') {
+            Some((name, value)) => (name, value),
+            None => {
+                import.reject(line, "content line is missing a value");
+                continue;
+            }
+        };
+        // Parameters like VALUE=DATE don't change how values are read, only the time zone does
+        let mut parameters = name.split(';');
+        let name = parameters.next().unwrap_or_default().to_ascii_uppercase();
+        let tzid = parameters.find_map(|parameter| {
+            let (key, tzid) = parameter.split_once('=')?;
+            key.eq_ignore_ascii_case("TZID")
+                .then(|| tzid.trim_matches('"'))
+        });
+
+        match (name.as_str(), component.as_mut()) {
+            ("BEGIN", None) if value.eq_ignore_ascii_case("VTODO") => {
+                component = Some(Component::new(line))
+            }
+            ("BEGIN", Some(component)) => component.nested += 1,
+            ("END", Some(component)) if component.nested > 0 => component.nested -= 1,
+            ("END", Some(_)) if value.eq_ignore_ascii_case("VTODO") => {
+                if let Some(component) = component.take() {
+                    component.finish(&mut import)
+                }
+            }
+            (_, Some(component)) if component.nested == 0 => component.property(&name, tzid, value),
+            _ => {}
+        }
+    }
+    if let Some(component) = component {
+        import.reject(component.line, "VTODO is missing its END line");
+    }
+    Ok(import)
+}
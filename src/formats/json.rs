@@ -0,0 +1,40 @@
+use super::{Export, Import, Row};
+use crate::error::{Error::*, Result};
+use serde::Deserialize;
+
+// Imports take either a full export or a plain array of todos
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Document {
+    List {
+        todos: Vec<serde_json::Value>,
+        #[serde(default)]
+        trash: Vec<serde_json::Value>,
+    },
+    Todos(Vec<serde_json::Value>),
+}
+
+pub fn export(export: &Export) -> Result<Vec<u8>> {
+    serde_json::to_vec_pretty(export).map_err(JsonSerializationError)
+}
+
+pub fn import(body: &[u8]) -> Result<Import> {
+    let document: Document = serde_json::from_slice(body)
+        .map_err(|error| ValidationError(format!("could not read JSON import: {}", error)))?;
+    let values = match document {
+        Document::List { mut todos, trash } => {
+            todos.extend(trash);
+            todos
+        }
+        Document::Todos(todos) => todos,
+    };
+
+    let mut import = Import::default();
+    for (index, value) in values.into_iter().enumerate() {
+        match serde_json::from_value::<Row>(value) {
+            Ok(row) => import.push(index + 1, row),
+            Err(error) => import.reject(index + 1, error.to_string()),
+        }
+    }
+    Ok(import)
+}
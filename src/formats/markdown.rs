@@ -0,0 +1,118 @@
+use super::{Export, Import, Row};
+use crate::data;
+use crate::error::{Error::*, Result};
+
+// An item of a GitHub-style Markdown checklist, like `- [x] Run To The Hills!`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChecklistItem {
+    pub checked: bool,
+    pub text: String,
+    // Written as a trailing `<!-- id:... -->` comment, so a merge import of an export updates the
+    // todos. Rendered Markdown doesn't show it
+    pub id: Option<uuid::Uuid>,
+}
+
+impl ChecklistItem {
+    // Parse a line of a checklist, lines that aren't checklist items give None
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_start();
+        let item = ["- ", "* ", "+ "]
+            .iter()
+            .find_map(|bullet| line.strip_prefix(bullet))?
+            .trim_start();
+        let (checked, text) = if let Some(text) = item.strip_prefix("[ ]") {
+            (false, text)
+        } else if let Some(text) = item
+            .strip_prefix("[x]")
+            .or_else(|| item.strip_prefix("[X]"))
+        {
+            (true, text)
+        } else {
+            return None;
+        };
+
+        // The checkbox has to be followed by whitespace or nothing at all
+        if !text.is_empty() && !text.starts_with(char::is_whitespace) {
+            return None;
+        }
+        let text = text.trim();
+        let (text, id) = match parse_id(text) {
+            Some((text, id)) => (text, Some(id)),
+            None => (text, None),
+        };
+        Some(Self {
+            checked,
+            text: text.to_owned(),
+            id,
+        })
+    }
+}
+
+// Split the id comment off the end of an item, comments that don't hold an id are left in the text
+fn parse_id(text: &str) -> Option<(&str, uuid::Uuid)> {
+    let (text, comment) = text.strip_suffix("-->")?.rsplit_once("<!--")?;
+    let id = uuid::Uuid::parse_str(comment.trim().strip_prefix("id:")?).ok()?;
+    Some((text.trim_end(), id))
+}
+
+impl std::fmt::Display for ChecklistItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let checkbox = if self.checked { "[x]" } else { "[ ]" };
+        write!(f, "- {} {}", checkbox, self.text)?;
+        match self.id {
+            Some(id) => write!(f, " <!-- id:{} -->", id),
+            None => Ok(()),
+        }
+    }
+}
+
+impl From<&data::Todo> for ChecklistItem {
+    fn from(todo: &data::Todo) -> Self {
+        Self {
+            checked: todo.completed,
+            // An item has to fit on a single line
+            text: todo.name.split_whitespace().collect::<Vec<_>>().join(" "),
+            id: Some(todo.id),
+        }
+    }
+}
+
+impl From<ChecklistItem> for Row {
+    fn from(item: ChecklistItem) -> Self {
+        Self {
+            id: item.id,
+            name: item.text,
+            priority: None,
+            timestamp: None,
+            created_at: None,
+            completed: Some(item.checked),
+            completed_at: None,
+            due: None,
+            deleted_at: None,
+        }
+    }
+}
+
+// Todos are written as a checklist, trashed todos are left out
+pub fn export(export: &Export) -> Result<Vec<u8>> {
+    let mut text = String::new();
+    for todo in &export.todos {
+        text.push_str(&ChecklistItem::from(todo).to_string());
+        text.push('\n');
+    }
+    Ok(text.into_bytes())
+}
+
+// Lines that aren't checklist items, like headings or prose, are skipped over
+pub fn import(body: &[u8]) -> Result<Import> {
+    let text = std::str::from_utf8(body)
+        .map_err(|_| ValidationError(String::from("Markdown must be UTF-8 encoded")))?;
+
+    let mut import = Import::default();
+    for (index, line) in text.lines().enumerate() {
+        if let Some(item) = ChecklistItem::parse(line) {
+            import.push(index + 1, item.into());
+        }
+    }
+    Ok(import)
+}
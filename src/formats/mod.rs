@@ -0,0 +1,336 @@
+use crate::data;
+use crate::error::Result;
+use chrono::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+// Conversions between todo lists and the file formats they are exported as, each format module
+// can also be used on its own without the server
+pub mod csv;
+pub mod ical;
+pub mod json;
+pub mod markdown;
+pub mod todotxt;
+
+// The file formats a todo list can be exported as and imported from
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    #[default]
+    Json,
+    Csv,
+    Ical,
+    TodoTxt,
+    Markdown,
+}
+
+impl Format {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Csv => "text/csv",
+            Format::Ical => "text/calendar; charset=utf-8",
+            Format::TodoTxt => "text/plain; charset=utf-8",
+            Format::Markdown => "text/markdown; charset=utf-8",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Csv => "csv",
+            Format::Ical => "ics",
+            Format::TodoTxt => "txt",
+            Format::Markdown => "md",
+        }
+    }
+}
+
+// Everything in a todo list that goes into an export. The session is left out on purpose, anyone
+// holding it has access to the list
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Export {
+    pub todos: Vec<data::Todo>,
+    #[serde(default)]
+    pub trash: Vec<data::DeletedTodo>,
+}
+
+impl From<data::TodoList> for Export {
+    fn from(list: data::TodoList) -> Self {
+        Self {
+            todos: list.todos,
+            trash: list
+                .trash
+                .into_iter()
+                .map(data::DeletedTodo::from)
+                .collect(),
+        }
+    }
+}
+
+pub fn export(export: &Export, format: Format) -> Result<Vec<u8>> {
+    match format {
+        Format::Json => json::export(export),
+        Format::Csv => csv::export(export),
+        Format::Ical => ical::export(export),
+        Format::TodoTxt => todotxt::export(export),
+        Format::Markdown => markdown::export(export),
+    }
+}
+
+pub fn import(body: &[u8], format: Format) -> Result<Import> {
+    match format {
+        Format::Json => json::import(body),
+        Format::Csv => csv::import(body),
+        Format::Ical => ical::import(body),
+        Format::TodoTxt => todotxt::import(body),
+        Format::Markdown => markdown::import(body),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: Format,
+}
+
+// How imported todos are combined with the ones already in the list
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    // Imported todos are added to the list, todos with an id already in the list overwrite it
+    #[default]
+    Merge,
+    // The list is swapped out for the imported todos, todos left out go to the trash
+    Replace,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub format: Format,
+    #[serde(default)]
+    pub mode: ImportMode,
+}
+
+#[derive(Deserialize, Serialize, Debug, JsonSchema)]
+pub struct ImportReport {
+    pub mode: ImportMode,
+    // Number of todos that made it into the list
+    pub imported: usize,
+    // Number of todos that were imported straight into the trash
+    pub trashed: usize,
+    pub rejected: Vec<RejectedRow>,
+}
+
+impl ImportReport {
+    // Apply an import to a list in memory, rows that don't fit in the list are rejected
+    pub fn execute(
+        mode: ImportMode,
+        import: &Import,
+        todos: &mut Vec<data::Todo>,
+        trash: &mut Vec<data::TrashedTodo>,
+    ) -> Self {
+        let mut rejected: Vec<RejectedRow> = import
+            .rejected
+            .iter()
+            .map(|row| RejectedRow {
+                row: row.row,
+                reason: row.reason.clone(),
+            })
+            .collect();
+
+        let replaced = match mode {
+            ImportMode::Merge => Vec::new(),
+            ImportMode::Replace => std::mem::take(todos),
+        };
+
+        let mut imported = 0;
+        for row in &import.todos {
+            if let Some(existing) = todos.iter_mut().find(|todo| todo.id == row.todo.id) {
+                *existing = row.todo.clone();
+            } else if todos.len() < data::MAX_TODOS {
+                todos.push(row.todo.clone());
+            } else {
+                rejected.push(RejectedRow {
+                    row: row.row,
+                    reason: crate::error::Error::TodoLimitError(data::MAX_TODOS).to_string(),
+                });
+                continue;
+            }
+            imported += 1;
+        }
+
+        // Replaced todos that weren't imported again can still be restored from the trash
+        trash.extend(
+            replaced
+                .into_iter()
+                .filter(|old| todos.iter().all(|todo| todo.id != old.id))
+                .map(data::TrashedTodo::from),
+        );
+
+        let mut trashed = 0;
+        for row in &import.trash {
+            let deleted = &row.trashed;
+            let is_known = todos.iter().any(|todo| todo.id == deleted.todo.id)
+                || trash.iter().any(|old| old.todo.id == deleted.todo.id);
+            if is_known {
+                continue;
+            }
+            if trash.len() >= MAX_IMPORTED_TRASH {
+                rejected.push(RejectedRow {
+                    row: row.row,
+                    reason: format!(
+                        "trash cannot hold more than {} imported todos",
+                        MAX_IMPORTED_TRASH
+                    ),
+                });
+                continue;
+            }
+            trash.push(deleted.clone());
+            trashed += 1;
+        }
+
+        // A todo can't be in the list and the trash at the same time
+        trash.retain(|old| todos.iter().all(|todo| todo.id != old.todo.id));
+
+        rejected.sort_by_key(|row| row.row);
+        Self {
+            mode,
+            imported,
+            trashed,
+            rejected,
+        }
+    }
+}
+
+// Imports can't grow the trash past this, the same way MAX_TODOS bounds the list
+pub const MAX_IMPORTED_TRASH: usize = 100;
+
+// A single todo as it appears in an imported file, anything but the name can be left out
+#[derive(Deserialize, Serialize, Debug)]
+struct Row {
+    #[serde(default)]
+    id: Option<uuid::Uuid>,
+    name: String,
+    #[serde(default)]
+    priority: Option<char>,
+    #[serde(default)]
+    timestamp: Option<DateTime<Utc>>,
+    #[serde(default)]
+    created_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    completed: Option<bool>,
+    #[serde(default)]
+    completed_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    due: Option<DateTime<Utc>>,
+    // Only set for todos that were in the trash
+    #[serde(default)]
+    deleted_at: Option<DateTime<Utc>>,
+}
+
+impl From<&data::Todo> for Row {
+    fn from(todo: &data::Todo) -> Self {
+        Self {
+            id: Some(todo.id),
+            name: todo.name.clone(),
+            priority: todo.priority,
+            timestamp: Some(todo.timestamp),
+            created_at: Some(todo.created_at),
+            completed: Some(todo.completed),
+            completed_at: todo.completed_at,
+            due: todo.due,
+            deleted_at: None,
+        }
+    }
+}
+
+impl From<&data::DeletedTodo> for Row {
+    fn from(deleted: &data::DeletedTodo) -> Self {
+        Self {
+            deleted_at: Some(deleted.deleted_at),
+            ..Row::from(&deleted.todo)
+        }
+    }
+}
+
+// A todo read from an imported file, along with where in the file it came from
+#[derive(Debug)]
+pub struct ImportedTodo {
+    pub row: usize,
+    pub todo: data::Todo,
+}
+
+// A todo read from an imported file that goes straight into the trash
+#[derive(Debug)]
+pub struct ImportedTrash {
+    pub row: usize,
+    pub trashed: data::TrashedTodo,
+}
+
+// A row of an imported file that could not be turned into a todo
+#[derive(Deserialize, Serialize, Debug, JsonSchema)]
+pub struct RejectedRow {
+    // Line of the file for the text based formats, position of the todo for JSON with the trash
+    // following the todos
+    pub row: usize,
+    pub reason: String,
+}
+
+// The contents of an imported file, rows that could not be read are collected instead of failing
+// the whole import
+#[derive(Debug, Default)]
+pub struct Import {
+    pub todos: Vec<ImportedTodo>,
+    pub trash: Vec<ImportedTrash>,
+    pub rejected: Vec<RejectedRow>,
+    ids: HashSet<uuid::Uuid>,
+}
+
+impl Import {
+    pub fn reject(&mut self, row: usize, reason: impl Into<String>) {
+        self.rejected.push(RejectedRow {
+            row,
+            reason: reason.into(),
+        });
+    }
+
+    fn push(&mut self, row: usize, imported: Row) {
+        let name = imported.name.trim();
+        if name.is_empty() {
+            return self.reject(row, "todo name cannot be empty");
+        }
+        let id = imported.id.unwrap_or_else(uuid::Uuid::new_v4);
+        if !self.ids.insert(id) {
+            return self.reject(row, format!("todo {} appears more than once", id));
+        }
+
+        let completed = imported
+            .completed
+            .unwrap_or(imported.completed_at.is_some());
+        // Files that only have one of the times use it for both
+        let timestamp = imported
+            .timestamp
+            .or(imported.created_at)
+            .unwrap_or_else(Utc::now);
+        let todo = data::Todo {
+            id,
+            name: name.to_owned(),
+            timestamp,
+            created_at: imported.created_at.unwrap_or(timestamp),
+            completed,
+            completed_at: imported.completed_at.filter(|_| completed),
+            due: imported.due,
+            priority: imported.priority.filter(char::is_ascii_uppercase),
+        };
+        match imported.deleted_at {
+            Some(deleted_at) => self.trash.push(ImportedTrash {
+                row,
+                trashed: data::TrashedTodo { todo, deleted_at },
+            }),
+            None => self.todos.push(ImportedTodo { row, todo }),
+        }
+    }
+}
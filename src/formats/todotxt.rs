@@ -0,0 +1,211 @@
+use super::{Export, Import, Row};
+use crate::data;
+use crate::error::{Error::*, Result};
+use chrono::prelude::*;
+use std::fmt;
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+// A single line of a todo.txt file, see https://github.com/todotxt/todo.txt for the format
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Task {
+    pub completed: bool,
+    pub priority: Option<char>,
+    pub completion_date: Option<NaiveDate>,
+    pub creation_date: Option<NaiveDate>,
+    // Read from and written to the `due:` tag
+    pub due: Option<NaiveDate>,
+    // Read from and written to the `id:` tag, so a merge import of an export updates the todos
+    pub id: Option<uuid::Uuid>,
+    // The description, including contexts, projects and any tags other than `due:`, `id:` and
+    // `pri:`. Those tags are part of the description too when their values don't parse
+    pub text: String,
+}
+
+impl Task {
+    pub fn parse(line: &str) -> Self {
+        let mut task = Task::default();
+        let mut rest = line.trim();
+
+        if let Some(completed) = rest.strip_prefix("x ") {
+            task.completed = true;
+            rest = completed.trim_start();
+        }
+        if let Some((priority, remainder)) = parse_priority(rest) {
+            task.priority = Some(priority);
+            rest = remainder;
+        }
+
+        // Completed tasks lead with their completion date, followed by the creation date
+        let (first, remainder) = parse_date(rest);
+        rest = remainder;
+        if task.completed {
+            task.completion_date = first;
+            let (creation, remainder) = parse_date(rest);
+            task.creation_date = creation;
+            rest = remainder;
+        } else {
+            task.creation_date = first;
+        }
+
+        let mut words = Vec::new();
+        for word in rest.split_whitespace() {
+            if let Some(due) = word
+                .strip_prefix("due:")
+                .and_then(|due| NaiveDate::parse_from_str(due, DATE_FORMAT).ok())
+            {
+                task.due = Some(due);
+            } else if let Some(id) = word
+                .strip_prefix("id:")
+                .and_then(|id| uuid::Uuid::parse_str(id).ok())
+            {
+                task.id = Some(id);
+            } else if let Some(priority) = word.strip_prefix("pri:").and_then(parse_priority_tag) {
+                // Completed tasks keep their priority as a tag
+                task.priority = Some(priority);
+            } else {
+                words.push(word);
+            }
+        }
+        task.text = words.join(" ");
+        task
+    }
+
+    // Contexts are words of the description starting with @
+    pub fn contexts(&self) -> Vec<&str> {
+        self.tagged('@')
+    }
+
+    // Projects are words of the description starting with +
+    pub fn projects(&self) -> Vec<&str> {
+        self.tagged('+')
+    }
+
+    fn tagged(&self, prefix: char) -> Vec<&str> {
+        self.text
+            .split_whitespace()
+            .filter_map(|word| word.strip_prefix(prefix))
+            .filter(|tag| !tag.is_empty())
+            .collect()
+    }
+}
+
+impl fmt::Display for Task {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.completed {
+            write!(f, "x ")?;
+        } else if let Some(priority) = self.priority {
+            write!(f, "({}) ", priority)?;
+        }
+        let completion_date = self.completion_date.filter(|_| self.completed);
+        if let Some(completion_date) = completion_date {
+            write!(f, "{} ", completion_date.format(DATE_FORMAT))?;
+        }
+        // A lone date after the x is read as the completion date, so a completed task without one
+        // can't have its creation date written either
+        let creation_date = self
+            .creation_date
+            .filter(|_| !self.completed || completion_date.is_some());
+        if let Some(creation_date) = creation_date {
+            write!(f, "{} ", creation_date.format(DATE_FORMAT))?;
+        }
+        write!(f, "{}", self.text)?;
+        if let Some(due) = self.due {
+            write!(f, " due:{}", due.format(DATE_FORMAT))?;
+        }
+        if let Some(priority) = self.priority.filter(|_| self.completed) {
+            write!(f, " pri:{}", priority)?;
+        }
+        match self.id {
+            Some(id) => write!(f, " id:{}", id),
+            None => Ok(()),
+        }
+    }
+}
+
+impl From<&data::Todo> for Task {
+    fn from(todo: &data::Todo) -> Self {
+        Self {
+            completed: todo.completed,
+            priority: todo.priority,
+            completion_date: todo.completed_at.map(|date| date.naive_utc().date()),
+            creation_date: Some(todo.created_at.naive_utc().date()),
+            due: todo.due.map(|date| date.naive_utc().date()),
+            id: Some(todo.id),
+            // A task has to fit on a single line
+            text: todo.name.split_whitespace().collect::<Vec<_>>().join(" "),
+        }
+    }
+}
+
+impl From<Task> for Row {
+    fn from(task: Task) -> Self {
+        let midnight = |date: NaiveDate| Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap());
+        Self {
+            id: task.id,
+            name: task.text,
+            priority: task.priority,
+            timestamp: None,
+            created_at: task.creation_date.map(midnight),
+            completed: Some(task.completed),
+            completed_at: task.completion_date.map(midnight),
+            due: task.due.map(midnight),
+            deleted_at: None,
+        }
+    }
+}
+
+fn parse_priority(text: &str) -> Option<(char, &str)> {
+    let mut characters = text.chars();
+    match (characters.next(), characters.next(), characters.next()) {
+        (Some('('), Some(priority), Some(')')) if priority.is_ascii_uppercase() => {
+            let rest = characters.as_str();
+            match rest.strip_prefix(' ') {
+                Some(rest) => Some((priority, rest.trim_start())),
+                None if rest.is_empty() => Some((priority, rest)),
+                None => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn parse_priority_tag(tag: &str) -> Option<char> {
+    let mut characters = tag.chars();
+    match (characters.next(), characters.next()) {
+        (Some(priority), None) if priority.is_ascii_uppercase() => Some(priority),
+        _ => None,
+    }
+}
+
+fn parse_date(text: &str) -> (Option<NaiveDate>, &str) {
+    let (word, rest) = text.split_once(' ').unwrap_or((text, ""));
+    match NaiveDate::parse_from_str(word, DATE_FORMAT) {
+        Ok(date) => (Some(date), rest.trim_start()),
+        Err(_) => (None, text),
+    }
+}
+
+// Todos are written one per line, trashed todos are left out since the format has no place for them
+pub fn export(export: &Export) -> Result<Vec<u8>> {
+    let mut text = String::new();
+    for todo in &export.todos {
+        text.push_str(&Task::from(todo).to_string());
+        text.push('\n');
+    }
+    Ok(text.into_bytes())
+}
+
+pub fn import(body: &[u8]) -> Result<Import> {
+    let text = std::str::from_utf8(body)
+        .map_err(|_| ValidationError(String::from("todo.txt must be UTF-8 encoded")))?;
+
+    let mut import = Import::default();
+    for (index, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        import.push(index + 1, Task::parse(line).into());
+    }
+    Ok(import)
+}
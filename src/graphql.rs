@@ -0,0 +1,598 @@
+use crate::error::Error::{self, *};
+use crate::events::{self, EventKind, Hub};
+use crate::{data, db, handler};
+use async_graphql::connection::{self, Connection, Edge};
+use async_graphql::http::GraphiQLSource;
+use async_graphql::{
+    Context, ErrorExtensions, InputObject, MaybeUndefined, Object, OneofObject, ResultExt, Schema,
+    SimpleObject, Subscription, ID,
+};
+use chrono::prelude::*;
+use futures::Stream;
+
+pub type TodoSchema = Schema<Query, Mutation, Subscription>;
+
+// The schema only holds on to the database client and event hub, the session is added per request
+pub fn schema(client: db::Client, hub: Hub) -> TodoSchema {
+    Schema::build(Query, Mutation, Subscription)
+        .data(client)
+        .data(hub)
+        .finish()
+}
+
+// The GraphiQL console. Its bundle is served with the static assets from the same paths unpkg has
+// it under, so the console works without reaching out to a CDN
+pub fn explorer() -> String {
+    GraphiQLSource::build()
+        .endpoint("/graphql")
+        .subscription_endpoint("/graphql/ws")
+        .title("GraphQL Explorer")
+        .finish()
+        .replace("https://unpkg.com/", "/graphiql/")
+        .replace("https://graphql.org/favicon.ico", "/favicon.ico")
+}
+
+// Errors carry a code along with the message, mirroring the status codes of the REST API
+impl ErrorExtensions for Error {
+    fn extend(&self) -> async_graphql::Error {
+        tracing::warn!(error = ?self, "Error occurred during span");
+        let (code, message) = match self {
+            NonexistentResourceError => ("NOT_FOUND", self.to_string()),
+            ConcurrentModificationError | TodoLimitError(_) => ("CONFLICT", self.to_string()),
+            ValidationError(_) | MongoOidError(_) => ("BAD_REQUEST", self.to_string()),
+            _ => ("INTERNAL_SERVER_ERROR", String::from("Unhandled Exception")),
+        };
+        async_graphql::Error::new(message).extend_with(|_, extensions| extensions.set("code", code))
+    }
+}
+
+fn parse_id(id: &ID) -> async_graphql::Result<uuid::Uuid> {
+    uuid::Uuid::parse_str(id)
+        .map_err(|_| ValidationError(format!("\"{}\" is not a valid todo id", id.as_str())))
+        .extend()
+}
+
+// Every resolver acts on the todo list of the session that made the request
+fn context<'a>(ctx: &Context<'a>) -> async_graphql::Result<(&'a db::Client, &'a data::Session)> {
+    Ok((ctx.data::<db::Client>()?, ctx.data::<data::Session>()?))
+}
+
+pub struct Todo(data::Todo);
+
+#[Object]
+impl Todo {
+    async fn id(&self) -> ID {
+        self.0.id.into()
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn timestamp(&self) -> DateTime<Utc> {
+        self.0.timestamp
+    }
+
+    async fn completed(&self) -> bool {
+        self.0.completed
+    }
+
+    async fn completed_at(&self) -> Option<DateTime<Utc>> {
+        self.0.completed_at
+    }
+
+    async fn due(&self) -> Option<DateTime<Utc>> {
+        self.0.due
+    }
+
+    async fn priority(&self) -> Option<char> {
+        self.0.priority
+    }
+}
+
+fn todos(todos: Vec<data::Todo>) -> Vec<Todo> {
+    todos.into_iter().map(Todo).collect()
+}
+
+pub struct DeletedTodo(data::TrashedTodo);
+
+#[Object]
+impl DeletedTodo {
+    async fn todo(&self) -> Todo {
+        Todo(self.0.todo.clone())
+    }
+
+    async fn deleted_at(&self) -> DateTime<Utc> {
+        self.0.deleted_at
+    }
+}
+
+pub struct Change(data::Change);
+
+#[Object]
+impl Change {
+    async fn id(&self) -> ID {
+        self.0.id.as_str().into()
+    }
+
+    async fn action(&self) -> data::HistoryAction {
+        self.0.action
+    }
+
+    async fn todo_id(&self) -> Option<ID> {
+        self.0.todo.map(ID::from)
+    }
+
+    async fn timestamp(&self) -> DateTime<Utc> {
+        self.0.timestamp
+    }
+
+    async fn undone(&self) -> bool {
+        self.0.undone
+    }
+}
+
+#[derive(InputObject, Default)]
+pub struct TodoFilter {
+    completed: Option<bool>,
+    // Case insensitive match against the name of the todo
+    search: Option<String>,
+    due_before: Option<DateTime<Utc>>,
+    due_after: Option<DateTime<Utc>>,
+}
+
+impl TodoFilter {
+    fn matches(&self, todo: &data::Todo) -> bool {
+        if let Some(completed) = self.completed {
+            if todo.completed != completed {
+                return false;
+            }
+        }
+        if let Some(search) = &self.search {
+            if !todo.name.to_lowercase().contains(&search.to_lowercase()) {
+                return false;
+            }
+        }
+        // Todos without a due date never match a due date filter
+        match (todo.due, self.due_before, self.due_after) {
+            (None, None, None) => true,
+            (None, _, _) => false,
+            (Some(due), before, after) => {
+                before.iter().all(|&before| due < before) && after.iter().all(|&after| due > after)
+            }
+        }
+    }
+}
+
+pub struct TodoList(data::TodoList);
+
+#[Object]
+impl TodoList {
+    // Todos in the order they were created, paginated with cursors
+    async fn todos(
+        &self,
+        filter: Option<TodoFilter>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> async_graphql::Result<Connection<usize, Todo>> {
+        let filter = filter.unwrap_or_default();
+        let todos: Vec<&data::Todo> = self
+            .0
+            .todos
+            .iter()
+            .filter(|todo| filter.matches(todo))
+            .collect();
+
+        connection::query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let mut end = before.unwrap_or(todos.len()).min(todos.len());
+                let mut start = after.map_or(0, |after: usize| after + 1).min(end);
+                if let Some(first) = first {
+                    end = end.min(start + first);
+                }
+                if let Some(last) = last {
+                    start = start.max(end.saturating_sub(last));
+                }
+
+                let mut connection = Connection::new(start > 0, end < todos.len());
+                connection.edges.extend(
+                    todos[start..end]
+                        .iter()
+                        .enumerate()
+                        .map(|(index, todo)| Edge::new(start + index, Todo((*todo).clone()))),
+                );
+                Ok::<_, async_graphql::Error>(connection)
+            },
+        )
+        .await
+    }
+
+    async fn trash(&self) -> Vec<DeletedTodo> {
+        self.0.trash.iter().cloned().map(DeletedTodo).collect()
+    }
+
+    async fn history(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 20)] limit: i64,
+    ) -> async_graphql::Result<Vec<Change>> {
+        let (client, session) = context(ctx)?;
+        let history = db::get_history(client, session, limit).await.extend()?;
+        Ok(history
+            .into_iter()
+            .map(|entry| Change(entry.into()))
+            .collect())
+    }
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    async fn list(&self, ctx: &Context<'_>) -> async_graphql::Result<TodoList> {
+        let (client, session) = context(ctx)?;
+        tracing::info!("Querying todo list for user");
+        let list = db::get_todo_list(client, session).await.extend()?;
+        Ok(TodoList(list))
+    }
+
+    async fn todo(&self, ctx: &Context<'_>, id: ID) -> async_graphql::Result<Option<Todo>> {
+        let (client, session) = context(ctx)?;
+        let id = parse_id(&id)?;
+        let todos = db::get_todos(client, session).await.extend()?;
+        Ok(todos.into_iter().find(|todo| todo.id == id).map(Todo))
+    }
+}
+
+#[derive(InputObject)]
+pub struct TodoInput {
+    name: String,
+    // Left out keeps the due date a todo has when updating, null clears it
+    due: MaybeUndefined<DateTime<Utc>>,
+}
+
+impl From<TodoInput> for data::TodoRequest {
+    fn from(input: TodoInput) -> Self {
+        Self {
+            name: input.name,
+            due: input.due.into(),
+        }
+    }
+}
+
+#[derive(InputObject)]
+pub struct TodoUpdate {
+    id: ID,
+    name: String,
+    due: MaybeUndefined<DateTime<Utc>>,
+}
+
+#[derive(InputObject)]
+pub struct TodoCompletion {
+    id: ID,
+    #[graphql(default = true)]
+    completed: bool,
+}
+
+// Exactly one of the fields has to be set, like the `op` tag of a batch operation in the REST API
+#[derive(OneofObject)]
+pub enum BatchOperation {
+    Create(TodoInput),
+    Update(TodoUpdate),
+    Delete(ID),
+    Complete(TodoCompletion),
+}
+
+impl BatchOperation {
+    fn into_data(self) -> async_graphql::Result<data::BatchOperation> {
+        Ok(match self {
+            BatchOperation::Create(input) => data::BatchOperation::Create {
+                name: input.name,
+                due: input.due.take(),
+            },
+            BatchOperation::Update(update) => data::BatchOperation::Update {
+                id: parse_id(&update.id)?,
+                name: update.name,
+                due: update.due.into(),
+            },
+            BatchOperation::Delete(id) => data::BatchOperation::Delete { id: parse_id(&id)? },
+            BatchOperation::Complete(completion) => data::BatchOperation::Complete {
+                id: parse_id(&completion.id)?,
+                completed: completion.completed,
+            },
+        })
+    }
+}
+
+pub struct BatchResult(data::BatchResult);
+
+#[Object]
+impl BatchResult {
+    async fn index(&self) -> usize {
+        self.0.index
+    }
+
+    async fn status(&self) -> data::BatchStatus {
+        self.0.status
+    }
+
+    async fn todo(&self) -> Option<Todo> {
+        self.0.todo.clone().map(Todo)
+    }
+
+    async fn error(&self) -> Option<&str> {
+        self.0.error.as_deref()
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct BatchResponse {
+    committed: bool,
+    results: Vec<BatchResult>,
+}
+
+impl From<data::BatchResponse> for BatchResponse {
+    fn from(response: data::BatchResponse) -> Self {
+        Self {
+            committed: response.committed,
+            results: response.results.into_iter().map(BatchResult).collect(),
+        }
+    }
+}
+
+// Mutations publish the same events as the REST handlers, so every client sees the changes
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+    async fn create_todo(
+        &self,
+        ctx: &Context<'_>,
+        input: TodoInput,
+    ) -> async_graphql::Result<Todo> {
+        let (client, session) = context(ctx)?;
+        tracing::info!("Creating new Todo");
+        let todo: data::Todo = data::TodoRequest::from(input).into();
+        db::create_todo(client, session, &todo).await.extend()?;
+        ctx.data::<Hub>()?
+            .publish(session, EventKind::TodoCreated { todo: todo.clone() });
+        Ok(Todo(todo))
+    }
+
+    async fn update_todo(
+        &self,
+        ctx: &Context<'_>,
+        id: ID,
+        input: TodoInput,
+    ) -> async_graphql::Result<Todo> {
+        let (client, session) = context(ctx)?;
+        tracing::info!("Updating Todo");
+        let todo_id = parse_id(&id)?;
+        let update = data::TodoRequest::from(input);
+        db::update_todo(client, session, &todo_id, &update)
+            .await
+            .extend()?;
+        ctx.data::<Hub>()?.publish(
+            session,
+            EventKind::TodoUpdated {
+                todo_id,
+                name: update.name,
+            },
+        );
+
+        let todos = db::get_todos(client, session).await.extend()?;
+        todos
+            .into_iter()
+            .find(|todo| todo.id == todo_id)
+            .map(Todo)
+            .ok_or(NonexistentResourceError)
+            .extend()
+    }
+
+    // Completing has no write of its own, it runs as a batch of one
+    async fn complete_todo(
+        &self,
+        ctx: &Context<'_>,
+        id: ID,
+        #[graphql(default = true)] completed: bool,
+    ) -> async_graphql::Result<Todo> {
+        let (client, session) = context(ctx)?;
+        tracing::info!("Completing Todo");
+        let operation = data::BatchOperation::Complete {
+            id: parse_id(&id)?,
+            completed,
+        };
+        let mut response = db::execute_batch(client, session, &[operation])
+            .await
+            .extend()?;
+        let todo = response
+            .results
+            .pop()
+            .and_then(|result| result.todo)
+            .filter(|_| response.committed)
+            .ok_or(NonexistentResourceError)
+            .extend()?;
+        ctx.data::<Hub>()?
+            .publish(session, EventKind::TodoCompleted { todo: todo.clone() });
+        Ok(Todo(todo))
+    }
+
+    async fn delete_todo(&self, ctx: &Context<'_>, id: ID) -> async_graphql::Result<ID> {
+        let (client, session) = context(ctx)?;
+        tracing::info!("Deleting todo");
+        let todo_id = parse_id(&id)?;
+        db::delete_todo(client, session, &todo_id).await.extend()?;
+        ctx.data::<Hub>()?
+            .publish(session, EventKind::TodoDeleted { todo_id });
+        Ok(id)
+    }
+
+    async fn delete_all_todos(&self, ctx: &Context<'_>) -> async_graphql::Result<bool> {
+        let (client, session) = context(ctx)?;
+        tracing::info!("Delete All todo Items for user");
+        if db::delete_all_todos(client, session).await.extend()? {
+            ctx.data::<Hub>()?.publish(session, EventKind::ListCleared);
+        }
+        Ok(true)
+    }
+
+    async fn batch(
+        &self,
+        ctx: &Context<'_>,
+        operations: Vec<BatchOperation>,
+    ) -> async_graphql::Result<BatchResponse> {
+        let (client, session) = context(ctx)?;
+        tracing::info!(
+            operations = operations.len(),
+            "Executing batch of todo operations"
+        );
+        let operations = operations
+            .into_iter()
+            .map(BatchOperation::into_data)
+            .collect::<async_graphql::Result<Vec<_>>>()?;
+        let response = db::execute_batch(client, session, &operations)
+            .await
+            .extend()?;
+        if response.committed {
+            let hub = ctx.data::<Hub>()?;
+            for event in handler::todos::batch_events(&operations, &response) {
+                hub.publish(session, event);
+            }
+        }
+        Ok(response.into())
+    }
+
+    async fn undo(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Todo>> {
+        let (client, session) = context(ctx)?;
+        tracing::info!("Undoing last change to todo list");
+        let reply = db::undo(client, session).await.extend()?;
+        ctx.data::<Hub>()?.publish(
+            session,
+            EventKind::ListChanged {
+                todos: reply.clone(),
+            },
+        );
+        Ok(todos(reply))
+    }
+
+    async fn redo(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Todo>> {
+        let (client, session) = context(ctx)?;
+        tracing::info!("Redoing last undone change to todo list");
+        let reply = db::redo(client, session).await.extend()?;
+        ctx.data::<Hub>()?.publish(
+            session,
+            EventKind::ListChanged {
+                todos: reply.clone(),
+            },
+        );
+        Ok(todos(reply))
+    }
+
+    async fn restore_todo(&self, ctx: &Context<'_>, id: ID) -> async_graphql::Result<ID> {
+        let (client, session) = context(ctx)?;
+        tracing::info!("Restoring todo from trash");
+        let todo_id = parse_id(&id)?;
+        db::restore_todo(client, session, &todo_id).await.extend()?;
+        ctx.data::<Hub>()?
+            .publish(session, EventKind::TodoRestored { todo_id });
+        Ok(id)
+    }
+
+    async fn purge_todo(&self, ctx: &Context<'_>, id: ID) -> async_graphql::Result<ID> {
+        let (client, session) = context(ctx)?;
+        tracing::info!("Purging todo from trash");
+        db::purge_todo(client, session, &parse_id(&id)?)
+            .await
+            .extend()?;
+        Ok(id)
+    }
+
+    async fn empty_trash(&self, ctx: &Context<'_>) -> async_graphql::Result<bool> {
+        let (client, session) = context(ctx)?;
+        tracing::info!("Emptying trash for user");
+        db::empty_trash(client, session).await.extend()?;
+        Ok(true)
+    }
+}
+
+pub struct Event(events::Event);
+
+#[Object]
+impl Event {
+    // Ids outgrow the integers GraphQL can represent, so they're sent as strings
+    async fn id(&self) -> ID {
+        self.0.id.into()
+    }
+
+    #[graphql(name = "type")]
+    async fn kind(&self) -> &str {
+        self.0.kind.name()
+    }
+
+    async fn timestamp(&self) -> DateTime<Utc> {
+        self.0.timestamp
+    }
+
+    // The todo that was created or completed
+    async fn todo(&self) -> Option<Todo> {
+        match &self.0.kind {
+            EventKind::TodoCreated { todo } | EventKind::TodoCompleted { todo } => {
+                Some(Todo(todo.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    // The todo that was updated, deleted or restored
+    async fn todo_id(&self) -> Option<ID> {
+        match &self.0.kind {
+            EventKind::TodoUpdated { todo_id, .. }
+            | EventKind::TodoDeleted { todo_id }
+            | EventKind::TodoRestored { todo_id } => Some((*todo_id).into()),
+            _ => None,
+        }
+    }
+
+    async fn name(&self) -> Option<&str> {
+        match &self.0.kind {
+            EventKind::TodoUpdated { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+
+    // The whole list, after it was replaced
+    async fn todos(&self) -> Option<Vec<Todo>> {
+        match &self.0.kind {
+            EventKind::ListChanged { todos: list } => Some(todos(list.clone())),
+            _ => None,
+        }
+    }
+}
+
+pub struct Subscription;
+
+#[Subscription]
+impl Subscription {
+    // The same feed of change events as the websocket and event stream endpoints
+    async fn events(
+        &self,
+        ctx: &Context<'_>,
+        last_id: Option<ID>,
+    ) -> async_graphql::Result<impl Stream<Item = Event>> {
+        let session = ctx.data::<data::Session>()?;
+        let last_id = last_id
+            .map(|id| id.parse::<u64>())
+            .transpose()
+            .map_err(|_| ValidationError(String::from("lastId is not a valid event id")))
+            .extend()?;
+        tracing::info!(last_id, "Client subscribed to todo events");
+        let events = ctx.data::<Hub>()?.feed(session, last_id);
+        Ok(futures::StreamExt::map(events, Event))
+    }
+}
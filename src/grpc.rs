@@ -0,0 +1,452 @@
+// tonic returns its Status by value everywhere, there is no boxing it at the service boundary
+#![allow(clippy::result_large_err)]
+
+use crate::error::Error::{self, *};
+use crate::events::{self, EventKind, Hub};
+use crate::{data, db, handler};
+use chrono::prelude::*;
+use futures::{Stream, StreamExt};
+use proto::todos_server::{Todos, TodosServer};
+use std::pin::Pin;
+use tonic::service::interceptor::InterceptedService;
+use tonic::{Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("todos.v1");
+}
+
+type Result<T> = std::result::Result<Response<T>, Status>;
+
+pub type TodoService = InterceptedService<
+    TodosServer<Service>,
+    fn(Request<()>) -> std::result::Result<Request<()>, Status>,
+>;
+
+pub fn service(client: db::Client, hub: Hub) -> TodoService {
+    TodosServer::with_interceptor(Service { client, hub }, authenticate)
+}
+
+// Read the session from the request metadata, calls that need one fail when it's missing
+fn authenticate(mut request: Request<()>) -> std::result::Result<Request<()>, Status> {
+    let metadata = request.metadata();
+    let session = match metadata.get("session") {
+        Some(session) => Some(session.to_str().map_err(|_| invalid_session())?),
+        None => match metadata.get("authorization") {
+            Some(authorization) => Some(
+                authorization
+                    .to_str()
+                    .ok()
+                    .and_then(|authorization| authorization.strip_prefix("Bearer "))
+                    .ok_or_else(invalid_session)?,
+            ),
+            None => None,
+        },
+    };
+
+    if let Some(session) = session {
+        let session = uuid::Uuid::parse_str(session.trim()).map_err(|_| invalid_session())?;
+        request
+            .extensions_mut()
+            .insert(data::Session::from(session));
+    }
+    Ok(request)
+}
+
+fn invalid_session() -> Status {
+    Status::unauthenticated("Invalid session")
+}
+
+fn session<T>(request: &Request<T>) -> std::result::Result<data::Session, Status> {
+    request
+        .extensions()
+        .get::<data::Session>()
+        .cloned()
+        .ok_or_else(|| Status::unauthenticated("No session provided"))
+}
+
+// Errors map to the status codes closest to the ones the REST API responds with
+impl From<Error> for Status {
+    fn from(error: Error) -> Self {
+        tracing::warn!(error = ?error, "Error occurred during span");
+        match error {
+            NonexistentResourceError => Status::not_found(error.to_string()),
+            ConcurrentModificationError => Status::aborted(error.to_string()),
+            TodoLimitError(_) => Status::resource_exhausted(error.to_string()),
+            ValidationError(_) | MongoOidError(_) => Status::invalid_argument(error.to_string()),
+            _ => Status::internal("Unhandled Exception"),
+        }
+    }
+}
+
+fn parse_id(id: &str) -> std::result::Result<uuid::Uuid, Status> {
+    uuid::Uuid::parse_str(id)
+        .map_err(|_| Status::invalid_argument(format!("\"{}\" is not a valid todo id", id)))
+}
+
+fn to_timestamp(time: DateTime<Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: time.timestamp(),
+        nanos: time.timestamp_subsec_nanos() as i32,
+    }
+}
+
+fn from_timestamp(
+    timestamp: Option<prost_types::Timestamp>,
+) -> std::result::Result<Option<DateTime<Utc>>, Status> {
+    timestamp
+        .map(|timestamp| {
+            Utc.timestamp_opt(timestamp.seconds, timestamp.nanos as u32)
+                .single()
+                .ok_or_else(|| Status::invalid_argument("Timestamp is out of range"))
+        })
+        .transpose()
+}
+
+// Proto fields can't be null, so clearing the due date has a field of its own
+fn due_update(
+    update: &proto::UpdateTodoRequest,
+) -> std::result::Result<Option<Option<DateTime<Utc>>>, Status> {
+    if update.clear_due {
+        return Ok(Some(None));
+    }
+    Ok(from_timestamp(update.due)?.map(Some))
+}
+
+impl From<data::Todo> for proto::Todo {
+    fn from(todo: data::Todo) -> Self {
+        Self {
+            id: todo.id.to_string(),
+            name: todo.name,
+            timestamp: Some(to_timestamp(todo.timestamp)),
+            completed: todo.completed,
+            completed_at: todo.completed_at.map(to_timestamp),
+            due: todo.due.map(to_timestamp),
+            priority: todo.priority.map(String::from).unwrap_or_default(),
+        }
+    }
+}
+
+fn todos(todos: Vec<data::Todo>) -> Vec<proto::Todo> {
+    todos.into_iter().map(Into::into).collect()
+}
+
+impl From<data::TodoList> for proto::TodoList {
+    fn from(list: data::TodoList) -> Self {
+        Self {
+            session: list.session.id().to_simple().to_string(),
+            todos: todos(list.todos),
+            trash: list
+                .trash
+                .into_iter()
+                .map(|trashed| proto::DeletedTodo {
+                    todo: Some(trashed.todo.into()),
+                    deleted_at: Some(to_timestamp(trashed.deleted_at)),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<data::BatchResponse> for proto::BatchResponse {
+    fn from(response: data::BatchResponse) -> Self {
+        Self {
+            committed: response.committed,
+            results: response
+                .results
+                .into_iter()
+                .map(|result| proto::BatchResult {
+                    index: result.index as u32,
+                    status: match result.status {
+                        data::BatchStatus::Ok => proto::BatchStatus::Ok,
+                        data::BatchStatus::Failed => proto::BatchStatus::Failed,
+                        data::BatchStatus::Skipped => proto::BatchStatus::Skipped,
+                    } as i32,
+                    todo: result.todo.map(Into::into),
+                    error: result.error.unwrap_or_default(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<data::HistoryEntry> for proto::Change {
+    fn from(entry: data::HistoryEntry) -> Self {
+        let change = data::Change::from(entry);
+        let action = match change.action {
+            data::HistoryAction::CreateTodo => proto::HistoryAction::CreateTodo,
+            data::HistoryAction::UpdateTodo => proto::HistoryAction::UpdateTodo,
+            data::HistoryAction::DeleteTodo => proto::HistoryAction::DeleteTodo,
+            data::HistoryAction::DeleteAllTodos => proto::HistoryAction::DeleteAllTodos,
+            data::HistoryAction::RestoreTodo => proto::HistoryAction::RestoreTodo,
+            data::HistoryAction::Batch => proto::HistoryAction::Batch,
+            data::HistoryAction::Import => proto::HistoryAction::Import,
+        };
+        Self {
+            id: change.id,
+            action: action as i32,
+            todo_id: change.todo.map(|id| id.to_string()).unwrap_or_default(),
+            timestamp: Some(to_timestamp(change.timestamp)),
+            undone: change.undone,
+        }
+    }
+}
+
+impl From<events::Event> for proto::Event {
+    fn from(event: events::Event) -> Self {
+        let mut message = Self {
+            id: event.id,
+            r#type: event.kind.name().to_owned(),
+            timestamp: Some(to_timestamp(event.timestamp)),
+            ..Default::default()
+        };
+        match event.kind {
+            EventKind::TodoCreated { todo } | EventKind::TodoCompleted { todo } => {
+                message.todo = Some(todo.into())
+            }
+            EventKind::TodoUpdated { todo_id, name } => {
+                message.todo_id = todo_id.to_string();
+                message.name = name;
+            }
+            EventKind::TodoDeleted { todo_id } | EventKind::TodoRestored { todo_id } => {
+                message.todo_id = todo_id.to_string()
+            }
+            EventKind::ListChanged { todos: list } => message.todos = todos(list),
+            EventKind::ListCleared | EventKind::Resync => {}
+        }
+        message
+    }
+}
+
+fn batch_operation(
+    operation: proto::BatchOperation,
+) -> std::result::Result<data::BatchOperation, Status> {
+    use proto::batch_operation::Operation;
+    Ok(match operation.operation {
+        Some(Operation::Create(create)) => data::BatchOperation::Create {
+            name: create.name,
+            due: from_timestamp(create.due)?,
+        },
+        Some(Operation::Update(update)) => data::BatchOperation::Update {
+            id: parse_id(&update.id)?,
+            due: due_update(&update)?,
+            name: update.name,
+        },
+        Some(Operation::Delete(delete)) => data::BatchOperation::Delete {
+            id: parse_id(&delete.id)?,
+        },
+        Some(Operation::Complete(complete)) => data::BatchOperation::Complete {
+            id: parse_id(&complete.id)?,
+            completed: complete.completed,
+        },
+        None => return Err(Status::invalid_argument("Batch operation is empty")),
+    })
+}
+
+// Calls publish the same events as the REST handlers, so every client sees the changes
+pub struct Service {
+    client: db::Client,
+    hub: Hub,
+}
+
+#[tonic::async_trait]
+impl Todos for Service {
+    async fn create_list(&self, _: Request<()>) -> Result<proto::TodoList> {
+        tracing::info!("Creating new Todo List");
+        let list = db::create_todo_list(&self.client).await?;
+        Ok(Response::new(list.into()))
+    }
+
+    async fn get_list(&self, request: Request<()>) -> Result<proto::TodoList> {
+        let session = session(&request)?;
+        tracing::info!("Querying todo list for user");
+        let list = db::get_todo_list(&self.client, &session).await?;
+        Ok(Response::new(list.into()))
+    }
+
+    async fn create_todo(&self, request: Request<proto::CreateTodoRequest>) -> Result<proto::Todo> {
+        let session = session(&request)?;
+        tracing::info!("Creating new Todo");
+        let request = request.into_inner();
+        let todo: data::Todo = data::TodoRequest {
+            name: request.name,
+            due: Some(from_timestamp(request.due)?),
+        }
+        .into();
+        db::create_todo(&self.client, &session, &todo).await?;
+        self.hub
+            .publish(&session, EventKind::TodoCreated { todo: todo.clone() });
+        Ok(Response::new(todo.into()))
+    }
+
+    async fn update_todo(&self, request: Request<proto::UpdateTodoRequest>) -> Result<proto::Todo> {
+        let session = session(&request)?;
+        tracing::info!("Updating Todo");
+        let request = request.into_inner();
+        let todo_id = parse_id(&request.id)?;
+        let update = data::TodoRequest {
+            due: due_update(&request)?,
+            name: request.name,
+        };
+        db::update_todo(&self.client, &session, &todo_id, &update).await?;
+        self.hub.publish(
+            &session,
+            EventKind::TodoUpdated {
+                todo_id,
+                name: update.name,
+            },
+        );
+
+        let todo = db::get_todos(&self.client, &session)
+            .await?
+            .into_iter()
+            .find(|todo| todo.id == todo_id)
+            .ok_or(NonexistentResourceError)?;
+        Ok(Response::new(todo.into()))
+    }
+
+    // Completing has no write of its own, it runs as a batch of one
+    async fn complete_todo(
+        &self,
+        request: Request<proto::CompleteTodoRequest>,
+    ) -> Result<proto::Todo> {
+        let session = session(&request)?;
+        tracing::info!("Completing Todo");
+        let request = request.into_inner();
+        let operation = data::BatchOperation::Complete {
+            id: parse_id(&request.id)?,
+            completed: request.completed,
+        };
+        let mut response = db::execute_batch(&self.client, &session, &[operation]).await?;
+        let todo = response
+            .results
+            .pop()
+            .and_then(|result| result.todo)
+            .filter(|_| response.committed)
+            .ok_or(NonexistentResourceError)?;
+        self.hub
+            .publish(&session, EventKind::TodoCompleted { todo: todo.clone() });
+        Ok(Response::new(todo.into()))
+    }
+
+    async fn delete_todo(&self, request: Request<proto::TodoId>) -> Result<()> {
+        let session = session(&request)?;
+        tracing::info!("Deleting todo");
+        let todo_id = parse_id(&request.get_ref().id)?;
+        db::delete_todo(&self.client, &session, &todo_id).await?;
+        self.hub
+            .publish(&session, EventKind::TodoDeleted { todo_id });
+        Ok(Response::new(()))
+    }
+
+    async fn delete_all_todos(&self, request: Request<()>) -> Result<()> {
+        let session = session(&request)?;
+        tracing::info!("Delete All todo Items for user");
+        if db::delete_all_todos(&self.client, &session).await? {
+            self.hub.publish(&session, EventKind::ListCleared);
+        }
+        Ok(Response::new(()))
+    }
+
+    async fn batch(&self, request: Request<proto::BatchRequest>) -> Result<proto::BatchResponse> {
+        let session = session(&request)?;
+        let operations = request
+            .into_inner()
+            .operations
+            .into_iter()
+            .map(batch_operation)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        tracing::info!(
+            operations = operations.len(),
+            "Executing batch of todo operations"
+        );
+        let response = db::execute_batch(&self.client, &session, &operations).await?;
+        if response.committed {
+            for event in handler::todos::batch_events(&operations, &response) {
+                self.hub.publish(&session, event);
+            }
+        }
+        Ok(Response::new(response.into()))
+    }
+
+    async fn undo(&self, request: Request<()>) -> Result<proto::Todos> {
+        let session = session(&request)?;
+        tracing::info!("Undoing last change to todo list");
+        let reply = db::undo(&self.client, &session).await?;
+        self.hub.publish(
+            &session,
+            EventKind::ListChanged {
+                todos: reply.clone(),
+            },
+        );
+        Ok(Response::new(proto::Todos {
+            todos: todos(reply),
+        }))
+    }
+
+    async fn redo(&self, request: Request<()>) -> Result<proto::Todos> {
+        let session = session(&request)?;
+        tracing::info!("Redoing last undone change to todo list");
+        let reply = db::redo(&self.client, &session).await?;
+        self.hub.publish(
+            &session,
+            EventKind::ListChanged {
+                todos: reply.clone(),
+            },
+        );
+        Ok(Response::new(proto::Todos {
+            todos: todos(reply),
+        }))
+    }
+
+    async fn get_history(&self, request: Request<proto::HistoryRequest>) -> Result<proto::History> {
+        let session = session(&request)?;
+        tracing::info!("Querying change history for todo list");
+        let limit = request.get_ref().limit.unwrap_or(20);
+        let history = db::get_history(&self.client, &session, limit).await?;
+        Ok(Response::new(proto::History {
+            changes: history.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn restore_todo(&self, request: Request<proto::TodoId>) -> Result<()> {
+        let session = session(&request)?;
+        tracing::info!("Restoring todo from trash");
+        let todo_id = parse_id(&request.get_ref().id)?;
+        db::restore_todo(&self.client, &session, &todo_id).await?;
+        self.hub
+            .publish(&session, EventKind::TodoRestored { todo_id });
+        Ok(Response::new(()))
+    }
+
+    async fn purge_todo(&self, request: Request<proto::TodoId>) -> Result<()> {
+        let session = session(&request)?;
+        tracing::info!("Purging todo from trash");
+        let todo_id = parse_id(&request.get_ref().id)?;
+        db::purge_todo(&self.client, &session, &todo_id).await?;
+        Ok(Response::new(()))
+    }
+
+    async fn empty_trash(&self, request: Request<()>) -> Result<()> {
+        let session = session(&request)?;
+        tracing::info!("Emptying trash for user");
+        db::empty_trash(&self.client, &session).await?;
+        Ok(Response::new(()))
+    }
+
+    type WatchEventsStream =
+        Pin<Box<dyn Stream<Item = std::result::Result<proto::Event, Status>> + Send>>;
+
+    async fn watch_events(
+        &self,
+        request: Request<proto::WatchEventsRequest>,
+    ) -> Result<Self::WatchEventsStream> {
+        let session = session(&request)?;
+        let last_id = request.get_ref().last_id;
+        tracing::info!(last_id, "Client subscribed to todo events");
+        let events = self
+            .hub
+            .feed(&session, last_id)
+            .map(|event| Ok(event.into()));
+        Ok(Response::new(Box::pin(events)))
+    }
+}
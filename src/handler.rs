@@ -2,8 +2,11 @@ use warp::http::StatusCode;
 use warp::Reply;
 
 use crate::error::Error::*;
-use crate::{data, db};
+use crate::events::{EventKind, Hub};
+use crate::{config, data, db, formats};
+use sha2::{Digest, Sha256};
 use std::convert::Infallible;
+use std::future::Future;
 
 macro_rules! warp_handle {
     // handles errors generated during the handler that should generate http responses
@@ -42,6 +45,46 @@ pub fn recover(error: crate::error::Error) -> Result<Box<dyn Reply>, Infallible>
                     .body("404: Not Found"),
             ))
         }
+        ConcurrentModificationError => {
+            tracing::warn!("Resource was modified while serving the request");
+            Ok(Box::new(
+                warp::http::Response::builder()
+                    .status(409)
+                    .body("409: Conflict"),
+            ))
+        }
+        ValidationError(_) => {
+            tracing::warn!("Request failed validation");
+            Ok(Box::new(
+                warp::http::Response::builder()
+                    .status(400)
+                    .body(format!("400: {}", error)),
+            ))
+        }
+        TodoLimitError(_) => {
+            tracing::warn!("Todo list is full");
+            Ok(Box::new(
+                warp::http::Response::builder()
+                    .status(409)
+                    .body(format!("409: {}", error)),
+            ))
+        }
+        WebhookLimitError(_) => {
+            tracing::warn!("Session has too many webhooks");
+            Ok(Box::new(
+                warp::http::Response::builder()
+                    .status(409)
+                    .body(format!("409: {}", error)),
+            ))
+        }
+        IdempotencyKeyMismatchError | IdempotentRequestInProgressError => {
+            tracing::warn!("Idempotency key cannot be used for this request");
+            Ok(Box::new(
+                warp::http::Response::builder()
+                    .status(409)
+                    .body(format!("409: {}", error)),
+            ))
+        }
         _ => {
             tracing::warn!("Unhandled Exception occurred");
             Ok(Box::new(
@@ -53,14 +96,180 @@ pub fn recover(error: crate::error::Error) -> Result<Box<dyn Reply>, Infallible>
     }
 }
 
-pub async fn health(client: db::Client) -> Result<Box<dyn Reply>, Infallible> {
-    tracing::debug!("Pinging Database");
-    warp_handle!(db::ping(&client).await);
-    Ok(Box::new(StatusCode::OK))
+// Hash the route and request body so a reused idempotency key can be matched to its request
+fn fingerprint<T: serde::Serialize>(route: &str, request: &T) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(route.as_bytes());
+    hasher.update(serde_json::to_vec(request).unwrap_or_default());
+    hex::encode(hasher.finalize())
+}
+
+// Execute a request at most once per idempotency key, retries with the same key get the stored
+// response of the first request replayed back to them
+async fn idempotent<F>(
+    client: &db::Client,
+    settings: &config::IdempotencySettings,
+    session: &data::Session,
+    key: Option<String>,
+    fingerprint: String,
+    request: F,
+) -> crate::Result<data::StoredResponse>
+where
+    F: Future<Output = crate::Result<data::StoredResponse>>,
+{
+    let key = match key {
+        Some(key) => key,
+        None => return request.await,
+    };
+
+    let record = data::IdempotencyRecord::new(session, key, fingerprint, settings);
+    if let Some(existing) = db::claim_idempotency_key(client, &record).await? {
+        if existing.fingerprint != record.fingerprint {
+            return Err(IdempotencyKeyMismatchError);
+        }
+        tracing::info!("Replaying stored response for idempotency key");
+        let mut response = existing.response.ok_or(IdempotentRequestInProgressError)?;
+        response.replayed = true;
+        return Ok(response);
+    }
+
+    match request.await {
+        Ok(response) => {
+            db::complete_idempotency_key(client, &record, &response).await?;
+            Ok(response)
+        }
+        Err(error) => {
+            // The request's own error is what the client needs to see, not the cleanup's
+            if let Err(release_error) = db::release_idempotency_key(client, &record).await {
+                tracing::warn!(error = ?release_error, "Could not release idempotency key");
+            }
+            Err(error)
+        }
+    }
+}
+
+fn stored_reply(response: data::StoredResponse) -> Box<dyn Reply> {
+    let mut builder = warp::http::Response::builder().status(response.status);
+    if !response.body.is_empty() {
+        builder = builder.header("content-type", "application/json");
+    }
+    if response.replayed {
+        builder = builder.header("idempotent-replayed", "true");
+    }
+    Box::new(builder.body(response.body))
+}
+
+// Tokens are stored hashed so they can't be read back out of the database
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+pub mod health {
+    use super::*;
+    use crate::data::{HealthCheck, HealthReport, HealthStatus};
+    use crate::readiness::Readiness;
+    use std::time::{Duration, Instant};
+
+    // The process is up and serving requests, whatever state its dependencies are in
+    pub async fn live() -> Result<Box<dyn Reply>, Infallible> {
+        Ok(report(Vec::new()))
+    }
+
+    // The server can do useful work: its database answers, its collections are set up and it
+    // isn't shutting down
+    pub async fn ready(
+        client: db::Client,
+        readiness: Readiness,
+        settings: config::HealthSettings,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        let checks = vec![
+            database(&client, Duration::from_millis(settings.database_timeout)).await,
+            flag(
+                "migrations",
+                readiness.is_initialized(),
+                "collections have not been set up",
+            ),
+            flag("shutdown", !readiness.is_draining(), "server is draining"),
+        ];
+        Ok(report(checks))
+    }
+
+    async fn database(client: &db::Client, timeout: Duration) -> HealthCheck {
+        tracing::debug!("Pinging Database");
+        let start = Instant::now();
+        let error = match tokio::time::timeout(timeout, db::ping(client)).await {
+            Ok(Ok(_)) => None,
+            Ok(Err(error)) => Some(error.to_string()),
+            Err(_) => Some(format!("no answer within {}ms", timeout.as_millis())),
+        };
+        HealthCheck {
+            name: String::from("database"),
+            status: if error.is_none() {
+                HealthStatus::Pass
+            } else {
+                HealthStatus::Fail
+            },
+            latency_ms: Some(start.elapsed().as_secs_f64() * 1000.0),
+            error,
+        }
+    }
+
+    fn flag(name: &str, pass: bool, error: &str) -> HealthCheck {
+        HealthCheck {
+            name: String::from(name),
+            status: if pass {
+                HealthStatus::Pass
+            } else {
+                HealthStatus::Fail
+            },
+            latency_ms: None,
+            error: if pass {
+                None
+            } else {
+                Some(String::from(error))
+            },
+        }
+    }
+
+    // Probes only look at the status code, so any failed check makes the whole report a 503
+    fn report(checks: Vec<HealthCheck>) -> Box<dyn Reply> {
+        let status = if checks
+            .iter()
+            .all(|check| check.status == HealthStatus::Pass)
+        {
+            HealthStatus::Pass
+        } else {
+            for check in checks
+                .iter()
+                .filter(|check| check.status == HealthStatus::Fail)
+            {
+                tracing::warn!(check = %check.name, error = ?check.error, "Health check failed");
+            }
+            HealthStatus::Fail
+        };
+        let code = match status {
+            HealthStatus::Pass => StatusCode::OK,
+            HealthStatus::Fail => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        Box::new(warp::reply::with_status(
+            warp::reply::json(&HealthReport { status, checks }),
+            code,
+        ))
+    }
+}
+
+pub async fn metrics() -> Result<Box<dyn Reply>, Infallible> {
+    let metrics = warp_handle!(crate::metrics::gather());
+    Ok(Box::new(warp::reply::with_header(
+        metrics,
+        "content-type",
+        "text/plain; version=0.0.4",
+    )))
 }
 
 pub mod todos {
     use super::*;
+    use futures::{SinkExt, StreamExt};
 
     pub async fn get_todos(
         client: db::Client,
@@ -89,41 +298,569 @@ pub mod todos {
 
     pub async fn create_todo(
         client: db::Client,
+        hub: Hub,
+        settings: config::IdempotencySettings,
         session: data::Session,
-        todo: data::Todo,
+        key: Option<String>,
+        request: data::TodoRequest,
     ) -> Result<Box<dyn Reply>, Infallible> {
         tracing::info!("Creating new Todo");
-        warp_handle!(db::create_todo(&client, &session, &todo).await);
-        Ok(Box::new(warp::reply()))
+        let fingerprint = fingerprint("create_todo", &request);
+        let reply = warp_handle!(
+            idempotent(&client, &settings, &session, key, fingerprint, async {
+                let todo: data::Todo = request.into();
+                db::create_todo(&client, &session, &todo).await?;
+                hub.publish(&session, EventKind::TodoCreated { todo });
+                Ok(data::StoredResponse::new(200, String::new()))
+            })
+            .await
+        );
+        Ok(stored_reply(reply))
     }
 
     pub async fn delete_todo(
         client: db::Client,
+        hub: Hub,
         session: data::Session,
         todo_id: uuid::Uuid,
     ) -> Result<Box<dyn Reply>, Infallible> {
         tracing::info!("Deleting todo");
         warp_handle!(db::delete_todo(&client, &session, &todo_id).await);
+        hub.publish(&session, EventKind::TodoDeleted { todo_id });
         Ok(Box::new(warp::reply()))
     }
 
     pub async fn update_todo(
         client: db::Client,
+        hub: Hub,
         session: data::Session,
         todo_id: uuid::Uuid,
         update: data::TodoRequest,
     ) -> Result<Box<dyn Reply>, Infallible> {
         tracing::info!("Updating Todo");
         warp_handle!(db::update_todo(&client, &session, &todo_id, &update).await);
+        hub.publish(
+            &session,
+            EventKind::TodoUpdated {
+                todo_id,
+                name: update.name,
+            },
+        );
         Ok(Box::new(warp::reply()))
     }
 
     pub async fn delete_all_todos(
         client: db::Client,
+        hub: Hub,
         session: data::Session,
     ) -> Result<Box<dyn Reply>, Infallible> {
         tracing::info!("Delete All todo Items for user");
-        warp_handle!(db::delete_all_todos(&client, &session).await);
+        if warp_handle!(db::delete_all_todos(&client, &session).await) {
+            hub.publish(&session, EventKind::ListCleared);
+        }
+        Ok(Box::new(warp::reply()))
+    }
+
+    pub async fn batch(
+        client: db::Client,
+        hub: Hub,
+        settings: config::IdempotencySettings,
+        session: data::Session,
+        key: Option<String>,
+        operations: Vec<data::BatchOperation>,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        tracing::info!(
+            operations = operations.len(),
+            "Executing batch of todo operations"
+        );
+        let fingerprint = fingerprint("batch", &operations);
+        let reply = warp_handle!(
+            idempotent(&client, &settings, &session, key, fingerprint, async {
+                let reply = db::execute_batch(&client, &session, &operations).await?;
+                let status = if reply.committed {
+                    for event in batch_events(&operations, &reply) {
+                        hub.publish(&session, event);
+                    }
+                    StatusCode::OK
+                } else {
+                    tracing::info!("Batch rejected, no operations were applied");
+                    StatusCode::UNPROCESSABLE_ENTITY
+                };
+                let body = serde_json::to_string(&reply).map_err(JsonSerializationError)?;
+                Ok(data::StoredResponse::new(status.as_u16(), body))
+            })
+            .await
+        );
+        Ok(stored_reply(reply))
+    }
+
+    // Translate the results of a committed batch into the events they correspond to
+    pub(crate) fn batch_events(
+        operations: &[data::BatchOperation],
+        response: &data::BatchResponse,
+    ) -> Vec<EventKind> {
+        operations
+            .iter()
+            .zip(&response.results)
+            .filter_map(|(operation, result)| {
+                let todo = result.todo.clone()?;
+                Some(match operation {
+                    data::BatchOperation::Create { .. } => EventKind::TodoCreated { todo },
+                    data::BatchOperation::Update { .. } => EventKind::TodoUpdated {
+                        todo_id: todo.id,
+                        name: todo.name,
+                    },
+                    data::BatchOperation::Delete { .. } => {
+                        EventKind::TodoDeleted { todo_id: todo.id }
+                    }
+                    data::BatchOperation::Complete { .. } => EventKind::TodoCompleted { todo },
+                })
+            })
+            .collect()
+    }
+
+    pub async fn undo(
+        client: db::Client,
+        hub: Hub,
+        session: data::Session,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        tracing::info!("Undoing last change to todo list");
+        let reply = warp_handle!(db::undo(&client, &session).await);
+        hub.publish(
+            &session,
+            EventKind::ListChanged {
+                todos: reply.clone(),
+            },
+        );
+        Ok(Box::new(warp::reply::json(&reply)))
+    }
+
+    pub async fn redo(
+        client: db::Client,
+        hub: Hub,
+        session: data::Session,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        tracing::info!("Redoing last undone change to todo list");
+        let reply = warp_handle!(db::redo(&client, &session).await);
+        hub.publish(
+            &session,
+            EventKind::ListChanged {
+                todos: reply.clone(),
+            },
+        );
+        Ok(Box::new(warp::reply::json(&reply)))
+    }
+
+    pub async fn history(
+        client: db::Client,
+        session: data::Session,
+        query: data::HistoryQuery,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        tracing::info!("Querying change history for todo list");
+        let reply = warp_handle!(db::get_history(&client, &session, query.limit).await);
+        let reply: Vec<data::Change> = reply.into_iter().map(data::Change::from).collect();
+        Ok(Box::new(warp::reply::json(&reply)))
+    }
+
+    pub async fn get_trash(
+        client: db::Client,
+        session: data::Session,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        tracing::info!("Querying trashed todo items for user");
+        let reply = warp_handle!(db::get_trash(&client, &session).await);
+        let reply: Vec<data::DeletedTodo> =
+            reply.into_iter().map(data::DeletedTodo::from).collect();
+        Ok(Box::new(warp::reply::json(&reply)))
+    }
+
+    pub async fn export_todos(
+        client: db::Client,
+        session: data::Session,
+        query: formats::ExportQuery,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        tracing::info!("Exporting todo list");
+        let list = warp_handle!(db::get_todo_list(&client, &session).await);
+        let body = warp_handle!(formats::export(&list.into(), query.format));
+        Ok(Box::new(
+            warp::http::Response::builder()
+                .header("content-type", query.format.content_type())
+                .header(
+                    "content-disposition",
+                    format!(
+                        "attachment; filename=\"todos.{}\"",
+                        query.format.extension()
+                    ),
+                )
+                .body(body),
+        ))
+    }
+
+    pub async fn import_todos(
+        client: db::Client,
+        hub: Hub,
+        session: data::Session,
+        query: formats::ImportQuery,
+        body: warp::hyper::body::Bytes,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        tracing::info!("Importing todos into list");
+        let import = warp_handle!(formats::import(&body, query.format));
+        let (reply, todos) =
+            warp_handle!(db::import_todos(&client, &session, query.mode, &import).await);
+        tracing::info!(
+            imported = reply.imported,
+            rejected = reply.rejected.len(),
+            "Import Successful"
+        );
+        hub.publish(&session, EventKind::ListChanged { todos });
+        Ok(Box::new(warp::reply::json(&reply)))
+    }
+
+    pub async fn create_calendar_feed(
+        client: db::Client,
+        session: data::Session,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        tracing::info!("Creating calendar feed for todo list");
+        let token = hex::encode(rand::random::<[u8; 32]>());
+        warp_handle!(db::set_calendar_token(&client, &session, Some(&hash_token(&token))).await);
+
+        let reply = data::CalendarFeed {
+            path: format!("/api/v1/todos/calendar/{}.ics", token),
+            token,
+        };
+        Ok(Box::new(warp::reply::with_status(
+            warp::reply::json(&reply),
+            StatusCode::CREATED,
+        )))
+    }
+
+    pub async fn delete_calendar_feed(
+        client: db::Client,
+        session: data::Session,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        tracing::info!("Revoking calendar feed for todo list");
+        warp_handle!(db::set_calendar_token(&client, &session, None).await);
+        Ok(Box::new(warp::reply()))
+    }
+
+    pub async fn calendar_feed(
+        client: db::Client,
+        token: String,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        tracing::info!("Serving calendar feed");
+        let token = token.strip_suffix(".ics").unwrap_or(&token);
+        let list =
+            warp_handle!(db::get_todo_list_by_calendar_token(&client, &hash_token(token)).await);
+        let body = warp_handle!(formats::export(&list.into(), formats::Format::Ical));
+        Ok(Box::new(
+            warp::http::Response::builder()
+                .header("content-type", formats::Format::Ical.content_type())
+                .body(body),
+        ))
+    }
+
+    pub async fn restore_todo(
+        client: db::Client,
+        hub: Hub,
+        session: data::Session,
+        todo_id: uuid::Uuid,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        tracing::info!("Restoring todo from trash");
+        warp_handle!(db::restore_todo(&client, &session, &todo_id).await);
+        hub.publish(&session, EventKind::TodoRestored { todo_id });
         Ok(Box::new(warp::reply()))
     }
+
+    pub async fn purge_todo(
+        client: db::Client,
+        session: data::Session,
+        todo_id: uuid::Uuid,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        tracing::info!("Purging todo from trash");
+        warp_handle!(db::purge_todo(&client, &session, &todo_id).await);
+        Ok(Box::new(warp::reply()))
+    }
+
+    pub async fn empty_trash(
+        client: db::Client,
+        session: data::Session,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        tracing::info!("Emptying trash for user");
+        warp_handle!(db::empty_trash(&client, &session).await);
+        Ok(Box::new(warp::reply()))
+    }
+
+    pub fn websocket(hub: Hub, session: data::Session, ws: warp::ws::Ws) -> impl Reply {
+        ws.on_upgrade(move |socket| stream_events(socket, hub, session))
+    }
+
+    // Forward every event for the session to the socket until the client disconnects
+    async fn stream_events(socket: warp::ws::WebSocket, hub: Hub, session: data::Session) {
+        tracing::info!("Client subscribed to todo events");
+        let (mut sender, mut receiver) = socket.split();
+        let events = hub.feed(&session, None);
+        futures::pin_mut!(events);
+
+        loop {
+            let event = tokio::select! {
+                event = events.next() => match event {
+                    Some(event) => event,
+                    None => break,
+                },
+                message = receiver.next() => match message {
+                    Some(Ok(message)) if !message.is_close() => continue,
+                    _ => break,
+                },
+            };
+
+            let message = match serde_json::to_string(&event) {
+                Ok(message) => message,
+                Err(error) => {
+                    tracing::warn!(error = ?error, "Could not serialize todo event");
+                    continue;
+                }
+            };
+            if sender.send(warp::ws::Message::text(message)).await.is_err() {
+                break;
+            }
+        }
+        tracing::info!("Client unsubscribed from todo events");
+    }
+
+    pub fn server_sent_events(
+        hub: Hub,
+        session: data::Session,
+        last_event_id: Option<u64>,
+    ) -> impl Reply {
+        tracing::info!(last_event_id, "Client subscribed to todo event stream");
+        let events = hub.feed(&session, last_event_id).map(|event| {
+            let message = warp::sse::Event::default().event(event.kind.name());
+            // Resyncs are not part of the feed, so they don't move the client's position in it
+            let message = match event.kind {
+                EventKind::Resync => message,
+                _ => message.id(event.id.to_string()),
+            };
+            message.json_data(&event)
+        });
+        warp::sse::reply(warp::sse::keep_alive().stream(events))
+    }
+}
+
+pub mod webhooks {
+    use super::*;
+
+    pub async fn create_webhook(
+        client: db::Client,
+        settings: config::WebhookSettings,
+        session: data::Session,
+        request: data::WebhookRequest,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        tracing::info!("Registering webhook");
+        warp_handle!(
+            crate::webhooks::Receivers::new(&settings)
+                .check(&request.url)
+                .await
+        );
+        if request.events.is_empty() {
+            return recover(ValidationError(String::from(
+                "webhook must subscribe to at least one event",
+            )));
+        }
+        if warp_handle!(db::count_webhooks(&client, &session).await) >= settings.max_per_session {
+            return recover(WebhookLimitError(settings.max_per_session));
+        }
+
+        let webhook = data::Webhook::new(&session, request);
+        warp_handle!(db::create_webhook(&client, &webhook).await);
+
+        // This is the only time the secret is handed out
+        let secret = webhook.secret.clone();
+        let mut reply = data::WebhookView::from(webhook);
+        reply.secret = Some(secret);
+        Ok(Box::new(warp::reply::with_status(
+            warp::reply::json(&reply),
+            StatusCode::CREATED,
+        )))
+    }
+
+    pub async fn get_webhooks(
+        client: db::Client,
+        session: data::Session,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        tracing::info!("Querying webhooks for user");
+        let reply = warp_handle!(db::get_webhooks(&client, &session).await);
+        let reply: Vec<data::WebhookView> =
+            reply.into_iter().map(data::WebhookView::from).collect();
+        Ok(Box::new(warp::reply::json(&reply)))
+    }
+
+    pub async fn delete_webhook(
+        client: db::Client,
+        session: data::Session,
+        webhook_id: uuid::Uuid,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        tracing::info!("Deleting webhook");
+        warp_handle!(db::delete_webhook(&client, &session, &webhook_id).await);
+        Ok(Box::new(warp::reply()))
+    }
+
+    pub async fn get_deliveries(
+        client: db::Client,
+        session: data::Session,
+        query: data::DeliveryQuery,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        tracing::info!("Querying webhook deliveries for user");
+        let reply = warp_handle!(
+            db::get_deliveries(&client, &session, query.webhook.as_ref(), query.limit).await
+        );
+        let reply: Vec<data::DeliveryView> =
+            reply.into_iter().map(data::DeliveryView::from).collect();
+        Ok(Box::new(warp::reply::json(&reply)))
+    }
+}
+
+pub mod v2 {
+    use super::*;
+
+    pub async fn get_todos(
+        client: db::Client,
+        session: Option<data::Session>,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        if let Some(session) = session {
+            tracing::info!("Querying all todo items for user");
+            let reply = warp_handle!(db::get_todos(&client, &session).await);
+            let reply: Vec<data::v2::Todo> = reply.into_iter().map(Into::into).collect();
+            Ok(Box::new(warp::reply::json(&reply)))
+        } else {
+            tracing::info!("No Session Provided, Creating new Todo List");
+            let list = warp_handle!(db::create_todo_list(&client).await);
+            let reply: Vec<data::v2::Todo> = list.todos.into_iter().map(Into::into).collect();
+            Ok(Box::new(warp::reply::with_header(
+                warp::reply::json(&reply),
+                "set-cookie",
+                format!("session={}", list.session.id().to_simple()),
+            )))
+        }
+    }
+
+    pub async fn create_todo(
+        client: db::Client,
+        hub: Hub,
+        settings: config::IdempotencySettings,
+        session: data::Session,
+        key: Option<String>,
+        request: data::v2::TodoRequest,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        tracing::info!("Creating new Todo");
+        let fingerprint = fingerprint("v2_create_todo", &request);
+        let reply = warp_handle!(
+            idempotent(&client, &settings, &session, key, fingerprint, async {
+                let todo: data::Todo = data::TodoRequest::from(request).into();
+                db::create_todo(&client, &session, &todo).await?;
+                hub.publish(&session, EventKind::TodoCreated { todo: todo.clone() });
+
+                // Unlike v1 the created todo is sent back, so clients learn its id
+                let body = serde_json::to_string(&data::v2::Todo::from(todo))
+                    .map_err(JsonSerializationError)?;
+                Ok(data::StoredResponse::new(201, body))
+            })
+            .await
+        );
+        Ok(stored_reply(reply))
+    }
+
+    pub async fn update_todo(
+        client: db::Client,
+        hub: Hub,
+        session: data::Session,
+        todo_id: uuid::Uuid,
+        update: data::v2::TodoRequest,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        super::todos::update_todo(client, hub, session, todo_id, update.into()).await
+    }
+}
+
+pub mod graphql {
+    use super::*;
+    use crate::graphql::TodoSchema;
+    use async_graphql::http::{WebSocket, WebSocketProtocols as Protocols, WsMessage};
+    use futures::{SinkExt, StreamExt};
+
+    pub async fn execute(
+        schema: TodoSchema,
+        session: data::Session,
+        request: async_graphql::Request,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        tracing::info!("Executing GraphQL request");
+        let response = schema.execute(request.data(session)).await;
+        Ok(Box::new(warp::reply::json(&response)))
+    }
+
+    // Subscriptions speak either of the GraphQL over WebSocket protocols, picked by the client
+    pub async fn subscribe(
+        schema: TodoSchema,
+        session: data::Session,
+        protocols: Option<String>,
+        ws: warp::ws::Ws,
+    ) -> Result<Box<dyn Reply>, Infallible> {
+        let protocol = warp_handle!(protocols
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .find_map(|protocol| protocol.trim().parse::<Protocols>().ok())
+            .ok_or_else(|| ValidationError(String::from("Unsupported websocket protocol"))));
+        let reply =
+            ws.on_upgrade(move |socket| stream_subscriptions(socket, schema, session, protocol));
+        Ok(Box::new(warp::reply::with_header(
+            reply,
+            "sec-websocket-protocol",
+            protocol.sec_websocket_protocol(),
+        )))
+    }
+
+    async fn stream_subscriptions(
+        socket: warp::ws::WebSocket,
+        schema: TodoSchema,
+        session: data::Session,
+        protocol: Protocols,
+    ) {
+        tracing::info!("Client connected to GraphQL subscriptions");
+        let (mut sender, receiver) = socket.split();
+        let receiver = receiver
+            .take_while(|message| futures::future::ready(message.is_ok()))
+            .filter_map(|message| async move {
+                let message = message.ok()?;
+                (message.is_text() || message.is_binary()).then(|| message.into_bytes())
+            });
+
+        let mut data = async_graphql::Data::default();
+        data.insert(session);
+        let messages = WebSocket::new(schema, receiver, protocol).connection_data(data);
+        futures::pin_mut!(messages);
+
+        while let Some(message) = messages.next().await {
+            let message = match message {
+                WsMessage::Text(text) => warp::ws::Message::text(text),
+                WsMessage::Close(code, reason) => warp::ws::Message::close_with(code, reason),
+            };
+            if sender.send(message).await.is_err() {
+                break;
+            }
+        }
+        tracing::info!("Client disconnected from GraphQL subscriptions");
+    }
+}
+
+pub mod admin {
+    use super::*;
+
+    pub async fn get_log_filter() -> Result<Box<dyn Reply>, Infallible> {
+        let filter = warp_handle!(crate::telemetry::log_filter()
+            .ok_or_else(|| { LogFilterError(String::from("logging is not initialized")) }));
+        Ok(Box::new(warp::reply::json(&data::LogFilter { filter })))
+    }
+
+    pub async fn set_log_filter(request: data::LogFilter) -> Result<Box<dyn Reply>, Infallible> {
+        let filter = warp_handle!(crate::telemetry::set_log_filter(&request.filter));
+        Ok(Box::new(warp::reply::json(&data::LogFilter { filter })))
+    }
 }
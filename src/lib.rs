@@ -1,8 +1,19 @@
 pub mod config;
 pub mod db;
 pub mod error;
+pub mod events;
+pub mod formats;
+pub mod graphql;
+pub mod grpc;
+pub mod listen;
+pub mod metrics;
+pub mod openapi;
+pub mod readiness;
 pub mod routes;
 pub mod startup;
+pub mod telemetry;
+pub mod tls;
+pub mod webhooks;
 
 pub mod data;
 mod handler;
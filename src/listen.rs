@@ -0,0 +1,204 @@
+use crate::error::Error::ListenError;
+use crate::{config, Result};
+use std::fmt;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+// A socket the HTTP server accepts connections on
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+// Where a listener accepts connections, printed the way it is configured
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Address {
+    Tcp(SocketAddr),
+    Unix(String),
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Address::Tcp(address) => write!(f, "{}", address),
+            Address::Unix(path) => write!(f, "unix:{}", path),
+        }
+    }
+}
+
+impl Listener {
+    pub fn address(&self) -> std::io::Result<Address> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(Address::Tcp),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.local_addr().map(|address| {
+                Address::Unix(
+                    address
+                        .as_pathname()
+                        .map(|path| path.display().to_string())
+                        .unwrap_or_default(),
+                )
+            }),
+        }
+    }
+}
+
+// Bind every configured target, or take the sockets systemd passed in when started by socket
+// activation
+pub async fn bind(settings: &config::ServerSettings) -> Result<Vec<Listener>> {
+    let inherited = inherited()?;
+    if !inherited.is_empty() {
+        tracing::info!(count = inherited.len(), "Listening on sockets from systemd");
+        return Ok(inherited);
+    }
+
+    let mut listeners = Vec::new();
+    for target in targets(settings) {
+        listeners.extend(bind_target(&target).await?);
+    }
+    Ok(listeners)
+}
+
+// A host and port in the form addresses are resolved from
+pub fn socket_target(host: &str, port: u16) -> String {
+    // IPv6 addresses need brackets before a port
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+fn targets(settings: &config::ServerSettings) -> Vec<String> {
+    if !settings.listen.is_empty() {
+        return settings.listen.clone();
+    }
+    vec![socket_target(&settings.address, settings.application_port)]
+}
+
+async fn bind_target(target: &str) -> Result<Vec<Listener>> {
+    let error = |reason: String| ListenError {
+        address: String::from(target),
+        reason,
+    };
+
+    if let Some(path) = target.strip_prefix("unix:") {
+        #[cfg(unix)]
+        return bind_unix(path)
+            .map(|listener| vec![listener])
+            .map_err(error);
+        #[cfg(not(unix))]
+        return Err(error(format!(
+            "Unix sockets are not supported on this platform, not listening on {}",
+            path
+        )));
+    }
+
+    bind_tcp(target)
+        .await
+        .map(|listeners| listeners.into_iter().map(Listener::Tcp).collect())
+}
+
+async fn bind_tcp(target: &str) -> Result<Vec<TcpListener>> {
+    let error = |reason: String| ListenError {
+        address: String::from(target),
+        reason,
+    };
+
+    // Hostnames can resolve to several addresses, e.g. both 127.0.0.1 and ::1 for localhost
+    let addresses = tokio::net::lookup_host(target)
+        .await
+        .map_err(|resolve| error(resolve.to_string()))?;
+    let mut listeners = Vec::new();
+    for address in addresses {
+        let listener = TcpListener::bind(address)
+            .await
+            .map_err(|bind| error(format!("{}: {}", address, bind)))?;
+        listeners.push(listener);
+    }
+    if listeners.is_empty() {
+        return Err(error(String::from("no addresses found")));
+    }
+    Ok(listeners)
+}
+
+// Bind the gRPC port on every IP address the HTTP server listens on, which covers the listen
+// targets, every address a hostname resolved to and sockets from systemd alike. Unix sockets have
+// no port to go with, when HTTP is only served on those gRPC listens on the configured address
+pub async fn bind_grpc(
+    settings: &config::ServerSettings,
+    http: &[Address],
+) -> Result<Vec<TcpListener>> {
+    let mut ips = Vec::new();
+    for address in http {
+        if let Address::Tcp(socket) = address {
+            if !ips.contains(&socket.ip()) {
+                ips.push(socket.ip());
+            }
+        }
+    }
+    if ips.is_empty() {
+        return bind_tcp(&socket_target(&settings.address, settings.grpc_port)).await;
+    }
+
+    let mut listeners = Vec::new();
+    for ip in ips {
+        let address = SocketAddr::new(ip, settings.grpc_port);
+        let listener = TcpListener::bind(address)
+            .await
+            .map_err(|bind| ListenError {
+                address: address.to_string(),
+                reason: bind.to_string(),
+            })?;
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &str) -> std::result::Result<Listener, String> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // A socket left behind by an earlier run would fail the bind, other files are left alone
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            std::fs::remove_file(path).map_err(|error| error.to_string())?;
+        }
+    }
+    UnixListener::bind(path)
+        .map(Listener::Unix)
+        .map_err(|error| error.to_string())
+}
+
+fn inherited() -> Result<Vec<Listener>> {
+    let mut fds = listenfd::ListenFd::from_env();
+    let error = |index: usize, error: std::io::Error| ListenError {
+        address: format!("systemd socket {}", index),
+        reason: error.to_string(),
+    };
+
+    let mut listeners = Vec::new();
+    for index in 0..fds.len() {
+        if let Ok(Some(listener)) = fds.take_tcp_listener(index) {
+            listener
+                .set_nonblocking(true)
+                .map_err(|e| error(index, e))?;
+            let listener = TcpListener::from_std(listener).map_err(|e| error(index, e))?;
+            listeners.push(Listener::Tcp(listener));
+            continue;
+        }
+        #[cfg(unix)]
+        if let Some(listener) = fds.take_unix_listener(index).map_err(|e| error(index, e))? {
+            listener
+                .set_nonblocking(true)
+                .map_err(|e| error(index, e))?;
+            let listener = UnixListener::from_std(listener).map_err(|e| error(index, e))?;
+            listeners.push(Listener::Unix(listener));
+        }
+    }
+    Ok(listeners)
+}
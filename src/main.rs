@@ -1,5 +1,4 @@
-use tracing_subscriber::fmt::format::FmtSpan;
-use warp_crud::{config, startup};
+use warp_crud::{config, startup, telemetry};
 
 #[tokio::main]
 async fn main() {
@@ -10,20 +9,15 @@ async fn main() {
         server_config.environment
     );
 
-    // Load the Trace filter settings from the Configuration
-    // We're slicing the struct apart but that should be fine since we don't need that field anymore
-    let filter = server_config.log.join(",");
-
-    tracing_subscriber::fmt()
-        .with_env_filter(filter) // Use the filter from the Config file
-        // Record an event when each span closes. Used to time duration of spans
-        .with_span_events(FmtSpan::CLOSE)
-        .init(); // initialize the subscriber
+    // Log with the filters from the Configuration, and export traces if a collector is configured
+    let _telemetry = telemetry::init(&server_config).expect("Could not Initialize Tracing");
 
     // Start the Server
-    let (address, server) = startup::run(server_config)
+    let (addresses, _shutdown, server) = startup::run(server_config)
         .await
         .expect("Could not Initialize Server");
-    println!("Server running on Address: {:?}", address);
+    for address in addresses {
+        println!("Server running on Address: {}", address);
+    }
     server.await;
 }
@@ -0,0 +1,183 @@
+use crate::{error::Error::MetricsError, Result};
+use lazy_static::lazy_static;
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    static ref REGISTRY: Registry = Registry::new();
+    static ref HTTP_REQUESTS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("http_requests_total", "Number of HTTP requests served"),
+        &["route", "method", "status"],
+    ));
+    static ref HTTP_REQUEST_DURATION: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new(
+            "http_request_duration_seconds",
+            "Time taken to reply to HTTP requests"
+        ),
+        &["route", "method", "status"],
+    ));
+    static ref DB_OPERATION_DURATION: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new(
+            "db_operation_duration_seconds",
+            "Time taken by database operations"
+        ),
+        &["operation"],
+    ));
+    static ref DB_OPERATION_ERRORS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new(
+            "db_operation_errors_total",
+            "Number of failed database operations"
+        ),
+        &["operation"],
+    ));
+    static ref WEBHOOK_EVENTS_SKIPPED: IntCounter = register(IntCounter::new(
+        "webhook_events_skipped_total",
+        "Number of events the webhook worker fell too far behind to deliver"
+    ));
+    static ref ACTIVE_SESSIONS: IntGauge = register(IntGauge::new(
+        "active_sessions",
+        "Number of sessions that made a request within the session window"
+    ));
+    static ref BUILD_INFO: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new("build_info", "Version of the running build, always 1"),
+        &["version", "profile"],
+    ));
+    static ref SESSIONS: Mutex<ActiveSessions> = Mutex::new(ActiveSessions::default());
+}
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(
+    collector: prometheus::Result<T>,
+) -> T {
+    let collector = collector.expect("Invalid metric");
+    REGISTRY
+        .register(Box::new(collector.clone()))
+        .expect("Metric registered twice");
+    collector
+}
+
+// When each session last made a request
+#[derive(Default)]
+struct ActiveSessions {
+    seen: HashMap<String, Instant>,
+    window: Duration,
+    pruned_at: Option<Instant>,
+}
+
+impl ActiveSessions {
+    fn see(&mut self, session: &str) {
+        let now = Instant::now();
+        self.seen.insert(session.to_owned(), now);
+        // Forget sessions once a window, so the map doesn't grow when nothing scrapes it
+        match self.pruned_at {
+            Some(pruned_at) if now.duration_since(pruned_at) < self.window => {}
+            _ => self.prune(now),
+        }
+    }
+
+    fn prune(&mut self, now: Instant) {
+        let window = self.window;
+        self.seen
+            .retain(|_, seen| now.duration_since(*seen) < window);
+        self.pruned_at = Some(now);
+    }
+}
+
+// Set how long a session counts as active after its last request, in seconds
+pub fn init(session_window: u64) {
+    SESSIONS.lock().unwrap().window = Duration::from_secs(session_window);
+    let profile = if cfg!(debug_assertions) {
+        "debug"
+    } else {
+        "release"
+    };
+    BUILD_INFO
+        .with_label_values(&[env!("CARGO_PKG_VERSION"), profile])
+        .set(1);
+}
+
+// Record a served request, used as a warp::log::custom callback
+pub fn record_request(info: warp::log::Info) {
+    let status = info.status();
+    let route = route(info.path(), status);
+    let labels = [route.as_str(), info.method().as_str(), status.as_str()];
+    HTTP_REQUESTS.with_label_values(&labels).inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&labels)
+        .observe(info.elapsed().as_secs_f64());
+
+    let session = info
+        .request_headers()
+        .get_all(warp::http::header::COOKIE)
+        .iter()
+        .filter_map(|cookie| cookie.to_str().ok())
+        .flat_map(|cookie| cookie.split(';'))
+        .find_map(|cookie| cookie.trim().strip_prefix("session="));
+    if let Some(session) = session {
+        SESSIONS.lock().unwrap().see(session);
+    }
+}
+
+// The route of a path, with ids and tokens replaced so every todo doesn't get series of its own.
+// Unmatched paths are lumped together for the same reason
+fn route(path: &str, status: warp::http::StatusCode) -> String {
+    if status == warp::http::StatusCode::NOT_FOUND {
+        return String::from("unmatched");
+    }
+    let mut route = String::new();
+    let mut previous = "";
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        route.push('/');
+        if previous == "calendar" {
+            route.push_str("{token}");
+        } else if uuid::Uuid::parse_str(segment).is_ok() {
+            route.push_str("{id}");
+        } else {
+            route.push_str(segment);
+        }
+        previous = segment;
+    }
+    if route.is_empty() {
+        route.push('/');
+    }
+    route
+}
+
+// Time a database operation and count it when it fails
+pub async fn observe_db<T, F>(operation: &'static str, future: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    let timer = DB_OPERATION_DURATION
+        .with_label_values(&[operation])
+        .start_timer();
+    let result = future.await;
+    timer.observe_duration();
+    if result.is_err() {
+        DB_OPERATION_ERRORS.with_label_values(&[operation]).inc();
+    }
+    result
+}
+
+// Count events the webhook worker skipped because it fell behind the hub
+pub fn skip_webhook_events(skipped: u64) {
+    WEBHOOK_EVENTS_SKIPPED.inc_by(skipped);
+}
+
+// Every metric in the Prometheus text format
+pub fn gather() -> Result<String> {
+    {
+        let mut sessions = SESSIONS.lock().unwrap();
+        sessions.prune(Instant::now());
+        ACTIVE_SESSIONS.set(sessions.seen.len() as i64);
+    }
+
+    TextEncoder::new()
+        .encode_to_string(&REGISTRY.gather())
+        .map_err(MetricsError)
+}
@@ -0,0 +1,610 @@
+use crate::{data, events, formats};
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+
+pub const OPENAPI_VERSION: &str = "3.0.3";
+
+// An operation of the API, built up and then added to the paths of the document
+pub struct Operation {
+    pub method: &'static str,
+    pub path: &'static str,
+    object: Map<String, Value>,
+}
+
+impl Operation {
+    fn new(method: &'static str, path: &'static str, tag: &str, summary: &str) -> Self {
+        let mut object = Map::new();
+        object.insert("tags".into(), json!([tag]));
+        object.insert("summary".into(), json!(summary));
+        object.insert("responses".into(), json!({}));
+        Self {
+            method,
+            path,
+            object,
+        }
+    }
+
+    // The operation needs the session cookie handed out by `GET /api/todos`
+    fn session(mut self) -> Self {
+        self.object
+            .insert("security".into(), json!([{ "session": [] }]));
+        self.error(400, "The session cookie is missing or malformed")
+    }
+
+    // The operation needs the admin token from the configuration
+    fn admin(mut self) -> Self {
+        self.object
+            .insert("security".into(), json!([{ "admin": [] }]));
+        self.error(401, "The admin token is missing or wrong")
+    }
+
+    fn parameter(mut self, name: &str, location: &str, required: bool, schema: Schema) -> Self {
+        let parameters = self.object.entry("parameters").or_insert_with(|| json!([]));
+        if let Value::Array(parameters) = parameters {
+            parameters.push(json!({
+                "name": name,
+                "in": location,
+                "required": required,
+                "schema": schema,
+            }));
+        }
+        self
+    }
+
+    fn path_parameter(self, name: &str) -> Self {
+        self.parameter(name, "path", true, schema_of::<uuid::Uuid>())
+    }
+
+    // JSON bodies of the versioned API can also be sent as MessagePack or CBOR, see
+    // `routes::negotiation`
+    fn content(&self, content_type: &str, schema: Schema) -> Value {
+        let mut content = json!({content_type: {"schema": schema}});
+        if content_type == "application/json" && self.path.starts_with("/api/v") {
+            for encoding in ["application/msgpack", "application/cbor"] {
+                content[encoding] = content[content_type].clone();
+            }
+        }
+        content
+    }
+
+    fn body(mut self, content_type: &str, schema: Schema) -> Self {
+        let content = self.content(content_type, schema);
+        let negotiated = content.get("application/cbor").is_some();
+        self.object.insert(
+            "requestBody".into(),
+            json!({"required": true, "content": content}),
+        );
+        if negotiated {
+            self.error(415, "The body is not JSON, MessagePack or CBOR")
+        } else {
+            self
+        }
+    }
+
+    fn response(mut self, status: u16, description: &str, content: Option<(&str, Schema)>) -> Self {
+        let mut response = json!({ "description": description });
+        match content {
+            // The socket itself isn't negotiated, only replies over HTTP are
+            Some((content_type, schema)) if status == 101 => {
+                response["content"] = json!({content_type: {"schema": schema}});
+            }
+            Some((content_type, schema)) => {
+                response["content"] = self.content(content_type, schema)
+            }
+            None => {}
+        }
+        self.object["responses"][status.to_string()] = response;
+        self
+    }
+
+    // Errors are replied as plain text, see `handler::recover`
+    fn error(self, status: u16, description: &str) -> Self {
+        self.response(
+            status,
+            description,
+            Some(("text/plain", schema_of::<String>())),
+        )
+    }
+
+    fn list_errors(self) -> Self {
+        self.error(404, "The todo list or todo does not exist")
+            .error(500, "The database could not be reached")
+    }
+}
+
+fn schema_of<T: JsonSchema>() -> Schema {
+    SchemaGenerator::new(SchemaSettings::openapi3()).subschema_for::<T>()
+}
+
+// Every route of the API, the drift test makes sure each of them is actually served
+pub fn operations(generator: &mut SchemaGenerator) -> Vec<Operation> {
+    let json = "application/json";
+    let todos = generator.subschema_for::<Vec<data::Todo>>();
+    let todo_request = generator.subschema_for::<data::TodoRequest>();
+    let batch = generator.subschema_for::<Vec<data::BatchOperation>>();
+    let batch_response = generator.subschema_for::<data::BatchResponse>();
+    let deleted = generator.subschema_for::<Vec<data::DeletedTodo>>();
+    let changes = generator.subschema_for::<Vec<data::Change>>();
+    let calendar = generator.subschema_for::<data::CalendarFeed>();
+    let import_mode = generator.subschema_for::<formats::ImportMode>();
+    let import_report = generator.subschema_for::<formats::ImportReport>();
+    let format = generator.subschema_for::<formats::Format>();
+    let event = generator.subschema_for::<events::Event>();
+    let webhook_request = generator.subschema_for::<data::WebhookRequest>();
+    let webhook = generator.subschema_for::<data::WebhookView>();
+    let webhooks = generator.subschema_for::<Vec<data::WebhookView>>();
+    let deliveries = generator.subschema_for::<Vec<data::DeliveryView>>();
+    let limit = generator.subschema_for::<i64>();
+    let string = generator.subschema_for::<String>();
+    let id = generator.subschema_for::<uuid::Uuid>();
+    let todo_v2 = generator.subschema_for::<data::v2::Todo>();
+    let todos_v2 = generator.subschema_for::<Vec<data::v2::Todo>>();
+    let todo_request_v2 = generator.subschema_for::<data::v2::TodoRequest>();
+    let log_filter = generator.subschema_for::<data::LogFilter>();
+    let health_report = generator.subschema_for::<data::HealthReport>();
+
+    vec![
+        Operation::new(
+            "get",
+            "/health",
+            "health",
+            "Readiness probe, kept for probes set up before it was split",
+        )
+        .response(
+            200,
+            "Every check passed",
+            Some((json, health_report.clone())),
+        )
+        .response(503, "A check failed", Some((json, health_report.clone()))),
+        Operation::new(
+            "get",
+            "/health/live",
+            "health",
+            "Liveness probe, passes whenever the process serves requests",
+        )
+        .response(
+            200,
+            "The process is up",
+            Some((json, health_report.clone())),
+        ),
+        Operation::new(
+            "get",
+            "/health/ready",
+            "health",
+            "Readiness probe, checks the database, its indexes and whether the server is draining",
+        )
+        .response(
+            200,
+            "Every check passed",
+            Some((json, health_report.clone())),
+        )
+        .response(503, "A check failed", Some((json, health_report))),
+        Operation::new(
+            "get",
+            "/metrics",
+            "health",
+            "Request, database and session metrics for Prometheus",
+        )
+        .response(
+            200,
+            "Metrics in the Prometheus text format",
+            Some(("text/plain; version=0.0.4", schema_of::<String>())),
+        ),
+        Operation::new(
+            "get",
+            "/admin/log",
+            "admin",
+            "The directives logs are filtered with",
+        )
+        .admin()
+        .response(200, "The current filter", Some((json, log_filter.clone()))),
+        Operation::new(
+            "put",
+            "/admin/log",
+            "admin",
+            "Change the directives logs are filtered with",
+        )
+        .admin()
+        .body(json, log_filter.clone())
+        .response(200, "The filter now in use", Some((json, log_filter)))
+        .error(400, "The directives could not be parsed"),
+        Operation::new("get", "/api/openapi.json", "meta", "This document").response(
+            200,
+            "The OpenAPI document",
+            Some((json, Schema::Bool(true))),
+        ),
+        Operation::new(
+            "get",
+            "/graphql",
+            "graphql",
+            "A query console to explore the schema with",
+        )
+        .response(
+            200,
+            "The console page",
+            Some(("text/html; charset=utf-8", schema_of::<String>())),
+        ),
+        Operation::new(
+            "post",
+            "/graphql",
+            "graphql",
+            "Run a GraphQL query or mutation",
+        )
+        .session()
+        .body(json, Schema::Bool(true))
+        .response(
+            200,
+            "The GraphQL response, errors are reported in it",
+            Some((json, Schema::Bool(true))),
+        ),
+        Operation::new(
+            "get",
+            "/graphql/ws",
+            "graphql",
+            "Run GraphQL subscriptions over a WebSocket",
+        )
+        .session()
+        .response(
+            101,
+            "Switching to a WebSocket speaking graphql-transport-ws or graphql-ws",
+            None,
+        ),
+        Operation::new(
+            "get",
+            "/api/v1/todos",
+            "todos",
+            "List the todos of the session",
+        )
+        .response(
+            200,
+            "The todos of the list. Without a session cookie a new list is created and its \
+             session is set as a cookie",
+            Some((json, todos.clone())),
+        )
+        .list_errors(),
+        Operation::new("post", "/api/v1/todos", "todos", "Create a todo")
+            .session()
+            .parameter("idempotency-key", "header", false, string.clone())
+            .body(json, todo_request.clone())
+            .response(200, "The todo was created", None)
+            .error(409, "The list is full or the idempotency key was reused")
+            .list_errors(),
+        Operation::new(
+            "delete",
+            "/api/v1/todos",
+            "todos",
+            "Move every todo into the trash",
+        )
+        .session()
+        .response(200, "The list was cleared", None)
+        .list_errors(),
+        Operation::new(
+            "put",
+            "/api/v1/todos/{id}",
+            "todos",
+            "Rename a todo or change its due date",
+        )
+        .session()
+        .path_parameter("id")
+        .body(json, todo_request)
+        .response(200, "The todo was updated", None)
+        .list_errors(),
+        Operation::new(
+            "delete",
+            "/api/v1/todos/{id}",
+            "todos",
+            "Move a todo into the trash",
+        )
+        .session()
+        .path_parameter("id")
+        .response(200, "The todo was deleted", None)
+        .list_errors(),
+        Operation::new(
+            "post",
+            "/api/v1/todos/batch",
+            "todos",
+            "Apply operations atomically",
+        )
+        .session()
+        .parameter("idempotency-key", "header", false, string.clone())
+        .body(json, batch)
+        .response(
+            200,
+            "Every operation was applied",
+            Some((json, batch_response.clone())),
+        )
+        .response(
+            422,
+            "An operation failed, nothing was applied",
+            Some((json, batch_response)),
+        )
+        .error(
+            409,
+            "The list kept changing or the idempotency key was reused",
+        )
+        .list_errors(),
+        Operation::new(
+            "get",
+            "/api/v1/todos/history",
+            "history",
+            "List recent changes",
+        )
+        .session()
+        .parameter("limit", "query", false, limit.clone())
+        .response(200, "The most recent changes first", Some((json, changes)))
+        .list_errors(),
+        Operation::new(
+            "post",
+            "/api/v1/todos/undo",
+            "history",
+            "Undo the last change",
+        )
+        .session()
+        .response(200, "The todos after the undo", Some((json, todos.clone())))
+        .error(409, "The list changed while undoing")
+        .list_errors(),
+        Operation::new(
+            "post",
+            "/api/v1/todos/redo",
+            "history",
+            "Redo the last undone change",
+        )
+        .session()
+        .response(200, "The todos after the redo", Some((json, todos)))
+        .error(409, "The list changed while redoing")
+        .list_errors(),
+        Operation::new("get", "/api/v1/todos/trash", "trash", "List deleted todos")
+            .session()
+            .response(200, "The todos in the trash", Some((json, deleted)))
+            .list_errors(),
+        Operation::new("delete", "/api/v1/todos/trash", "trash", "Empty the trash")
+            .session()
+            .response(200, "The trash was emptied", None)
+            .list_errors(),
+        Operation::new(
+            "post",
+            "/api/v1/todos/trash/{id}/restore",
+            "trash",
+            "Restore a deleted todo",
+        )
+        .session()
+        .path_parameter("id")
+        .response(200, "The todo is back in the list", None)
+        .error(409, "The list is full")
+        .list_errors(),
+        Operation::new(
+            "delete",
+            "/api/v1/todos/trash/{id}",
+            "trash",
+            "Delete a todo for good",
+        )
+        .session()
+        .path_parameter("id")
+        .response(200, "The todo was purged", None)
+        .list_errors(),
+        Operation::new(
+            "get",
+            "/api/v1/todos/export",
+            "transfer",
+            "Download the list",
+        )
+        .session()
+        .parameter("format", "query", false, format.clone())
+        .response(
+            200,
+            "The list in the requested format",
+            Some(("*/*", string.clone())),
+        )
+        .list_errors(),
+        Operation::new(
+            "post",
+            "/api/v1/todos/import",
+            "transfer",
+            "Upload todos into the list",
+        )
+        .session()
+        .parameter("format", "query", false, format)
+        .parameter("mode", "query", false, import_mode)
+        .body("*/*", string.clone())
+        .response(
+            200,
+            "What was imported and rejected",
+            Some((json, import_report)),
+        )
+        .error(409, "The list kept changing during the import")
+        .list_errors(),
+        Operation::new(
+            "post",
+            "/api/v1/todos/calendar",
+            "calendar",
+            "Create a calendar feed",
+        )
+        .session()
+        .response(
+            201,
+            "The feed, its token replaces any previous one",
+            Some((json, calendar)),
+        )
+        .list_errors(),
+        Operation::new(
+            "delete",
+            "/api/v1/todos/calendar",
+            "calendar",
+            "Revoke the calendar feed",
+        )
+        .session()
+        .response(200, "The feed was revoked", None)
+        .list_errors(),
+        Operation::new(
+            "get",
+            "/api/v1/todos/calendar/{token}",
+            "calendar",
+            "Read the calendar feed",
+        )
+        .parameter("token", "path", true, string.clone())
+        .response(
+            200,
+            "The todos as iCalendar VTODOs",
+            Some(("text/calendar", string)),
+        )
+        .list_errors(),
+        Operation::new(
+            "get",
+            "/api/v1/todos/events",
+            "events",
+            "Stream changes as server-sent events",
+        )
+        .session()
+        .parameter("last-event-id", "header", false, limit.clone())
+        .response(
+            200,
+            "A stream of events",
+            Some(("text/event-stream", event.clone())),
+        )
+        .list_errors(),
+        Operation::new(
+            "get",
+            "/api/v1/todos/ws",
+            "events",
+            "Stream changes over a WebSocket",
+        )
+        .session()
+        .response(
+            101,
+            "Switching to a WebSocket that sends events as JSON",
+            Some((json, event)),
+        )
+        .list_errors(),
+        Operation::new(
+            "get",
+            "/api/v1/webhooks",
+            "webhooks",
+            "List registered webhooks",
+        )
+        .session()
+        .response(
+            200,
+            "The webhooks, without their secrets",
+            Some((json, webhooks)),
+        )
+        .list_errors(),
+        Operation::new("post", "/api/v1/webhooks", "webhooks", "Register a webhook")
+            .session()
+            .body(json, webhook_request)
+            .response(
+                201,
+                "The webhook along with its signing secret",
+                Some((json, webhook)),
+            )
+            .error(409, "The session has registered as many webhooks as it can")
+            .list_errors(),
+        Operation::new(
+            "delete",
+            "/api/v1/webhooks/{id}",
+            "webhooks",
+            "Remove a webhook",
+        )
+        .session()
+        .path_parameter("id")
+        .response(200, "The webhook was removed", None)
+        .list_errors(),
+        Operation::new(
+            "get",
+            "/api/v1/webhooks/deliveries",
+            "webhooks",
+            "List webhook deliveries",
+        )
+        .session()
+        .parameter("webhook", "query", false, id)
+        .parameter("limit", "query", false, limit)
+        .response(
+            200,
+            "The most recent deliveries first",
+            Some((json, deliveries)),
+        )
+        .list_errors(),
+        Operation::new(
+            "get",
+            "/api/v2/todos",
+            "v2",
+            "List the todos of the session",
+        )
+        .response(200, "The todos of the list", Some((json, todos_v2)))
+        .list_errors(),
+        Operation::new("post", "/api/v2/todos", "v2", "Create a todo")
+            .session()
+            .parameter("idempotency-key", "header", false, schema_of::<String>())
+            .body(json, todo_request_v2.clone())
+            .response(201, "The created todo", Some((json, todo_v2)))
+            .error(409, "The list is full or the idempotency key was reused")
+            .list_errors(),
+        Operation::new(
+            "delete",
+            "/api/v2/todos",
+            "v2",
+            "Move every todo into the trash",
+        )
+        .session()
+        .response(200, "The list was cleared", None)
+        .list_errors(),
+        Operation::new("put", "/api/v2/todos/{id}", "v2", "Change a todo")
+            .session()
+            .path_parameter("id")
+            .body(json, todo_request_v2)
+            .response(200, "The todo was updated", None)
+            .list_errors(),
+        Operation::new(
+            "delete",
+            "/api/v2/todos/{id}",
+            "v2",
+            "Move a todo into the trash",
+        )
+        .session()
+        .path_parameter("id")
+        .response(200, "The todo was deleted", None)
+        .list_errors(),
+    ]
+}
+
+// Paths of version 1 are also served without the version, for clients from before versioning
+pub fn unversioned_alias(path: &str) -> Option<String> {
+    path.strip_prefix("/api/v1/")
+        .map(|rest| format!("/api/{}", rest))
+}
+
+// Build the OpenAPI document for the API, schemas are generated from the types in `data`
+pub fn spec() -> Value {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+    let mut paths = Map::new();
+    for operation in operations(&mut generator) {
+        if let Some(alias) = unversioned_alias(operation.path) {
+            let mut object = operation.object.clone();
+            object.insert(
+                "description".into(),
+                json!(format!("Alias of `{}`", operation.path)),
+            );
+            let path = paths.entry(alias).or_insert_with(|| json!({}));
+            path[operation.method] = Value::Object(object);
+        }
+        let path = paths.entry(operation.path).or_insert_with(|| json!({}));
+        path[operation.method] = Value::Object(operation.object);
+    }
+
+    json!({
+        "openapi": OPENAPI_VERSION,
+        "info": {
+            "title": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": generator.take_definitions(),
+            "securitySchemes": {
+                "session": {"type": "apiKey", "in": "cookie", "name": "session"},
+                "admin": {"type": "http", "scheme": "bearer"},
+            },
+        },
+    })
+}
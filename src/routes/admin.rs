@@ -0,0 +1,49 @@
+use sha2::{Digest, Sha256};
+use warp::Filter;
+
+use super::{negotiation, rejection, Route};
+use crate::{config, data, handler};
+
+pub fn admin_routes(settings: config::AdminSettings) -> Vec<Route> {
+    let log = warp::path!("admin" / "log");
+    let admin = with_admin(settings.token);
+
+    vec![
+        Route::new(
+            "get",
+            "/admin/log",
+            log.and(warp::get())
+                .and(admin.clone())
+                .and_then(handler::admin::get_log_filter),
+        ),
+        Route::new(
+            "put",
+            "/admin/log",
+            log.and(warp::put())
+                .and(admin)
+                .and(negotiation::body::<data::LogFilter>(4096))
+                .and_then(handler::admin::set_log_filter),
+        ),
+    ]
+}
+
+// Only let through requests with the configured bearer token. Without one every request is refused
+fn with_admin(token: Option<String>) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    // Tokens are compared by hash, so the time taken doesn't give away how much of one matched
+    let token = token.map(|token| Sha256::digest(token.as_bytes()));
+    warp::header::optional::<String>("authorization")
+        .and_then(move |authorization: Option<String>| async move {
+            let presented = authorization
+                .as_deref()
+                .and_then(|authorization| authorization.strip_prefix("Bearer "))
+                .map(|presented| Sha256::digest(presented.as_bytes()));
+            match (token, presented) {
+                (Some(token), Some(presented)) if token == presented => Ok(()),
+                _ => {
+                    tracing::warn!("Refusing admin request without a valid token");
+                    Err(warp::reject::custom(rejection::Unauthorized))
+                }
+            }
+        })
+        .untuple_one()
+}
@@ -0,0 +1,82 @@
+use super::compression;
+use crate::config;
+use warp::filters::BoxedFilter;
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+// The frontend, built into the executable so it runs from any directory
+#[cfg(feature = "embedded-assets")]
+#[derive(rust_embed::RustEmbed)]
+#[folder = "static/"]
+struct Assets;
+
+// Serve the frontend from the directory in the settings when there is one, so it can be edited
+// without rebuilding, and from the files built into the executable otherwise
+pub fn asset_routes(settings: &config::HttpSettings) -> BoxedFilter<(Response,)> {
+    match &settings.static_dir {
+        Some(dir) => directory(dir),
+        #[cfg(feature = "embedded-assets")]
+        None => embedded(),
+        #[cfg(not(feature = "embedded-assets"))]
+        None => directory("static"),
+    }
+}
+
+fn directory(dir: &str) -> BoxedFilter<(Response,)> {
+    compression::precompressed(dir.to_owned())
+        .or(warp::fs::dir(dir.to_owned()).map(|file: warp::fs::File| file.into_response()))
+        .unify()
+        .boxed()
+}
+
+#[cfg(feature = "embedded-assets")]
+fn embedded() -> BoxedFilter<(Response,)> {
+    // HEAD gets the headers of GET, the server leaves out the body
+    warp::get()
+        .or(warp::head())
+        .unify()
+        .and(warp::path::tail())
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .and_then(
+            |tail: warp::path::Tail, accept_encoding: Option<String>| async move {
+                embedded_file(tail.as_str(), accept_encoding.as_deref())
+                    .ok_or_else(warp::reject::not_found)
+            },
+        )
+        .boxed()
+}
+
+#[cfg(feature = "embedded-assets")]
+fn embedded_file(tail: &str, accept_encoding: Option<&str>) -> Option<Response> {
+    use warp::http::header::{HeaderValue, CONTENT_ENCODING, CONTENT_TYPE, ETAG, VARY};
+
+    let path = if tail.is_empty() || tail.ends_with('/') {
+        format!("{}index.html", tail)
+    } else {
+        tail.to_owned()
+    };
+
+    // Precompressed siblings the client accepts are preferred over the file itself
+    let (file, coding) = compression::Coding::accepted(accept_encoding)
+        .into_iter()
+        .find_map(|coding| {
+            Assets::get(&format!("{}.{}", path, coding.extension()))
+                .map(|file| (file, Some(coding)))
+        })
+        .or_else(|| Assets::get(&path).map(|file| (file, None)))?;
+
+    // The hash of the contents is computed when the file is embedded, it doubles as the ETag
+    let etag = super::caching::etag(&file.metadata.sha256_hash());
+    let content_type = mime_guess::from_path(&path).first_or_octet_stream();
+    let mut response = Response::new(file.data.into());
+    let headers = response.headers_mut();
+    if let Ok(content_type) = HeaderValue::from_str(content_type.as_ref()) {
+        headers.insert(CONTENT_TYPE, content_type);
+    }
+    headers.insert(ETAG, etag);
+    if let Some(coding) = coding {
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static(coding.name()));
+        headers.insert(VARY, HeaderValue::from_static("accept-encoding"));
+    }
+    Some(response)
+}
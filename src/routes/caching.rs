@@ -0,0 +1,121 @@
+use crate::config;
+use sha2::{Digest, Sha256};
+use std::convert::Infallible;
+use warp::http::header::{HeaderValue, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, ETAG};
+use warp::http::{Method, StatusCode};
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+// The policy for a path, the one with the longest matching prefix
+fn policy_for<'a>(
+    policies: &'a [config::CachePolicy],
+    path: &str,
+) -> Option<&'a config::CachePolicy> {
+    policies
+        .iter()
+        .filter(|policy| path.starts_with(&policy.path))
+        .max_by_key(|policy| policy.path.len())
+}
+
+// Whether an If-None-Match header matches an ETag, ignoring weakness as RFC 7232 asks for GETs
+fn matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.split(',').any(|candidate| {
+        let candidate = candidate.trim();
+        candidate == "*" || candidate.trim_start_matches("W/") == etag.trim_start_matches("W/")
+    })
+}
+
+// Tag a response with the hash of its body
+pub(super) fn etag(hash: &[u8]) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{}\"", hex::encode(&hash[..16]))).unwrap()
+}
+
+// Add the Cache-Control header and ETag of the configured policies to the replies of a filter, and
+// answer requests for tagged responses the client already has with 304 Not Modified
+pub fn cached<F, R>(
+    policies: Vec<config::CachePolicy>,
+    filter: F,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    warp::method()
+        .and(warp::path::full())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(filter)
+        .and_then(
+            move |method: Method, path: FullPath, if_none_match: Option<String>, reply: R| {
+                let policy = policy_for(&policies, path.as_str()).cloned();
+                async move {
+                    Ok::<_, Infallible>(
+                        apply(policy, method, if_none_match, reply.into_response()).await,
+                    )
+                }
+            },
+        )
+}
+
+async fn apply(
+    policy: Option<config::CachePolicy>,
+    method: Method,
+    if_none_match: Option<String>,
+    response: Response,
+) -> Response {
+    let (mut parts, body) = response.into_parts();
+    if let Some(config::CachePolicy {
+        path,
+        cache_control: Some(cache_control),
+        ..
+    }) = &policy
+    {
+        if !parts.headers.contains_key(CACHE_CONTROL) {
+            match HeaderValue::from_str(cache_control) {
+                Ok(value) => {
+                    parts.headers.insert(CACHE_CONTROL, value);
+                }
+                Err(_) => tracing::warn!(path = %path, "Ignoring invalid Cache-Control"),
+            }
+        }
+    }
+
+    // Event streams are never tagged, they have to be read to the end to be hashed
+    let is_stream = matches!(
+        parts.headers.get(CONTENT_TYPE),
+        Some(content_type) if content_type == "text/event-stream"
+    );
+    if !(method == Method::GET || method == Method::HEAD)
+        || parts.status != StatusCode::OK
+        || is_stream
+    {
+        return Response::from_parts(parts, body);
+    }
+
+    let (etag, body) = match parts.headers.get(ETAG) {
+        Some(etag) => (etag.clone(), body),
+        None if matches!(&policy, Some(policy) if policy.etag) => {
+            let body = match warp::hyper::body::to_bytes(body).await {
+                Ok(body) => body,
+                Err(error) => {
+                    tracing::warn!(error = %error, "Could not read response body");
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+            };
+            // The body is hashed as it is sent, so every content coding gets an ETag of its own
+            let etag = etag(&Sha256::digest(&body));
+            parts.headers.insert(ETAG, etag.clone());
+            (etag, body.into())
+        }
+        None => return Response::from_parts(parts, body),
+    };
+
+    match (if_none_match, etag.to_str()) {
+        (Some(if_none_match), Ok(etag)) if matches(&if_none_match, etag) => {
+            parts.status = StatusCode::NOT_MODIFIED;
+            parts.headers.remove(CONTENT_LENGTH);
+            Response::from_parts(parts, warp::hyper::Body::empty())
+        }
+        _ => Response::from_parts(parts, body),
+    }
+}
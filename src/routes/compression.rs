@@ -0,0 +1,258 @@
+use crate::config;
+use std::convert::Infallible;
+use std::io::Write;
+use std::path::PathBuf;
+use warp::http::header::{HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG, VARY};
+use warp::http::StatusCode;
+use warp::hyper::body::HttpBody;
+use warp::path::Tail;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+// The content codings responses can be compressed with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum Coding {
+    Brotli,
+    Gzip,
+}
+
+impl Coding {
+    pub(super) fn name(&self) -> &'static str {
+        match self {
+            Coding::Brotli => "br",
+            Coding::Gzip => "gzip",
+        }
+    }
+
+    // Extension of the precompressed siblings of static files
+    pub(super) fn extension(&self) -> &'static str {
+        match self {
+            Coding::Brotli => "br",
+            Coding::Gzip => "gz",
+        }
+    }
+
+    fn compress(&self, body: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            // A middling quality, bodies are compressed on every request
+            Coding::Brotli => {
+                let mut compressed = Vec::new();
+                {
+                    let mut writer = brotli::CompressorWriter::new(&mut compressed, 4096, 5, 22);
+                    writer.write_all(body)?;
+                }
+                Ok(compressed)
+            }
+            Coding::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    // The codings a client accepts from its Accept-Encoding header, best first. Brotli wins ties
+    pub(super) fn accepted(accept_encoding: Option<&str>) -> Vec<Self> {
+        let mut brotli = None;
+        let mut gzip = None;
+        let mut wildcard = None;
+        for coding in accept_encoding.unwrap_or_default().split(',') {
+            let mut parameters = coding.split(';');
+            let name = parameters.next().unwrap_or_default().trim();
+            let quality = parameters
+                .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+                .find_map(|quality| quality.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            match name.to_ascii_lowercase().as_str() {
+                "br" => brotli = Some(quality),
+                "gzip" | "x-gzip" => gzip = Some(quality),
+                "*" => wildcard = Some(quality),
+                _ => {}
+            }
+        }
+
+        let mut accepted: Vec<(Coding, f32)> = vec![
+            (Coding::Brotli, brotli.or(wildcard).unwrap_or(0.0)),
+            (Coding::Gzip, gzip.or(wildcard).unwrap_or(0.0)),
+        ];
+        accepted.retain(|(_, quality)| *quality > 0.0);
+        accepted.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+        accepted.into_iter().map(|(coding, _)| coding).collect()
+    }
+}
+
+// Whether a response is worth compressing. Event streams are left alone, they never end
+fn is_compressible(response: &Response) -> bool {
+    if response.status() == StatusCode::SWITCHING_PROTOCOLS
+        || response.status() == StatusCode::NO_CONTENT
+        || response.status() == StatusCode::NOT_MODIFIED
+        || response.headers().contains_key(CONTENT_ENCODING)
+    {
+        return false;
+    }
+    let content_type = match response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+    {
+        Some(content_type) => content_type,
+        None => return false,
+    };
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    match media_type.as_str() {
+        "text/event-stream" => false,
+        "application/json" | "application/javascript" | "application/xml" | "image/svg+xml" => true,
+        media_type => {
+            media_type.starts_with("text/")
+                || media_type.ends_with("+json")
+                || media_type.ends_with("+xml")
+        }
+    }
+}
+
+// Compress the replies of a filter with the best coding the client accepts, leaving out bodies
+// smaller than the threshold where the savings don't pay for the work
+pub fn compressed<F, R>(
+    settings: config::CompressionSettings,
+    filter: F,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    let threshold = settings.threshold;
+    warp::header::optional::<String>("accept-encoding")
+        .map(|accept_encoding: Option<String>| {
+            Coding::accepted(accept_encoding.as_deref())
+                .first()
+                .copied()
+        })
+        .and(filter)
+        .and_then(move |coding: Option<Coding>, reply: R| async move {
+            Ok::<_, Infallible>(compress_reply(coding, threshold, reply.into_response()).await)
+        })
+}
+
+async fn compress_reply(coding: Option<Coding>, threshold: usize, response: Response) -> Response {
+    if !is_compressible(&response) {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    // Caches have to keep the compressed and uncompressed versions apart
+    parts
+        .headers
+        .append(VARY, HeaderValue::from_static("accept-encoding"));
+
+    let coding = match coding {
+        Some(coding) => coding,
+        None => return Response::from_parts(parts, body),
+    };
+    // Bodies of unknown length are streamed, like file downloads, and could be of any size. They
+    // are passed on as they are instead of being read into memory to compress them
+    let known_length = parts
+        .headers
+        .get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse::<u64>().ok())
+        .or_else(|| body.size_hint().exact());
+    match known_length {
+        Some(length) if length >= threshold as u64 => {}
+        _ => return Response::from_parts(parts, body),
+    }
+
+    let body = match warp::hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(error) => {
+            tracing::warn!(error = %error, "Could not read response body");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    match coding.compress(&body) {
+        Ok(compressed) => {
+            parts.headers.remove(CONTENT_LENGTH);
+            parts
+                .headers
+                .insert(CONTENT_ENCODING, HeaderValue::from_static(coding.name()));
+            // The compressed body is a different representation, so it can't share its ETag
+            if let Some(etag) = parts.headers.get(ETAG).and_then(|etag| etag.to_str().ok()) {
+                let etag = format!("{}-{}\"", etag.trim_end_matches('"'), coding.extension());
+                match HeaderValue::from_str(&etag) {
+                    Ok(etag) => {
+                        parts.headers.insert(ETAG, etag);
+                    }
+                    Err(_) => {
+                        parts.headers.remove(ETAG);
+                    }
+                }
+            }
+            Response::from_parts(parts, compressed.into())
+        }
+        Err(error) => {
+            tracing::warn!(error = %error, "Could not compress response, sending it uncompressed");
+            Response::from_parts(parts, body.into())
+        }
+    }
+}
+
+// Serve the .br or .gz sibling of a static file when one exists and the client accepts it. Rejects
+// with not found otherwise, so the files themselves are served by the filters after it
+pub fn precompressed(
+    root: String,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::get()
+        .or(warp::head())
+        .unify()
+        .and(warp::path::tail())
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .and_then(move |tail: Tail, accept_encoding: Option<String>| {
+            let root = root.clone();
+            async move {
+                match precompressed_file(&root, tail.as_str(), accept_encoding.as_deref()).await {
+                    Some(response) => Ok(response),
+                    None => Err(warp::reject::not_found()),
+                }
+            }
+        })
+}
+
+async fn precompressed_file(
+    root: &str,
+    tail: &str,
+    accept_encoding: Option<&str>,
+) -> Option<Response> {
+    // Keep requests inside the root, like warp::fs::dir does
+    let mut path = PathBuf::from(root);
+    for segment in tail.split('/').filter(|segment| !segment.is_empty()) {
+        if segment == ".." || segment.starts_with('.') || segment.contains('\\') {
+            return None;
+        }
+        path.push(segment);
+    }
+    if tail.is_empty() || tail.ends_with('/') {
+        path.push("index.html");
+    }
+
+    for coding in Coding::accepted(accept_encoding) {
+        let mut file = path.clone().into_os_string();
+        file.push(".");
+        file.push(coding.extension());
+        if let Ok(body) = tokio::fs::read(&file).await {
+            let content_type = mime_guess::from_path(&path).first_or_octet_stream();
+            let mut response = Response::new(body.into());
+            let headers = response.headers_mut();
+            if let Ok(content_type) = HeaderValue::from_str(content_type.as_ref()) {
+                headers.insert(CONTENT_TYPE, content_type);
+            }
+            headers.insert(CONTENT_ENCODING, HeaderValue::from_static(coding.name()));
+            headers.insert(VARY, HeaderValue::from_static("accept-encoding"));
+            return Some(response);
+        }
+    }
+    None
+}
@@ -0,0 +1,48 @@
+use warp::filters::body;
+use warp::Filter;
+
+use super::{with_required_session, with_settings, Route};
+use crate::{db, events, graphql, handler};
+
+pub fn graphql_routes(client: db::Client, hub: events::Hub) -> Vec<Route> {
+    // The schema and the console only depend on the code, so they're built once up front
+    let schema = graphql::schema(client, hub);
+    let explorer = graphql::explorer();
+
+    vec![
+        Route::new(
+            "post",
+            "/graphql",
+            warp::path("graphql")
+                .and(warp::path::end())
+                .and(warp::post())
+                .and(with_settings(schema.clone()))
+                .and(with_required_session())
+                .and(body::content_length_limit(65536))
+                .and(body::json::<async_graphql::Request>())
+                .and_then(handler::graphql::execute),
+        ),
+        Route::new(
+            "get",
+            "/graphql/ws",
+            warp::path("graphql")
+                .and(warp::path("ws"))
+                .and(warp::path::end())
+                .and(warp::get())
+                .and(with_settings(schema))
+                .and(with_required_session())
+                .and(warp::header::optional::<String>("sec-websocket-protocol"))
+                .and(warp::ws())
+                .and_then(handler::graphql::subscribe),
+        ),
+        // Browsing to the endpoint brings up a console to explore the schema with
+        Route::new(
+            "get",
+            "/graphql",
+            warp::path("graphql")
+                .and(warp::path::end())
+                .and(warp::get())
+                .map(move || warp::reply::html(explorer.clone())),
+        ),
+    ]
+}
@@ -2,9 +2,40 @@ use super::*;
 
 pub fn health_routes(
     client: db::Client,
-) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    // Create the Health Check route
-    warp::path!("health")
+    readiness: readiness::Readiness,
+    settings: config::HealthSettings,
+) -> Vec<Route> {
+    // Liveness doesn't depend on anything, so a database outage doesn't get the process restarted
+    let live = warp::path!("health" / "live")
+        .and(warp::get())
+        .and_then(handler::health::live);
+
+    // Readiness takes the server out of rotation while it can't serve
+    let ready = warp::get()
         .and(with_db(client))
-        .and_then(handler::health)
+        .and(with_settings(readiness))
+        .and(with_settings(settings))
+        .and_then(handler::health::ready);
+
+    vec![
+        Route::new("get", "/health/live", live),
+        Route::new(
+            "get",
+            "/health/ready",
+            warp::path!("health" / "ready").and(ready.clone()),
+        ),
+        // The bare path is kept for the probes set up before the split
+        Route::new("get", "/health", warp::path!("health").and(ready)),
+    ]
+}
+
+pub fn metrics_routes() -> Vec<Route> {
+    // Scraped by Prometheus
+    vec![Route::new(
+        "get",
+        "/metrics",
+        warp::path!("metrics")
+            .and(warp::get())
+            .and_then(handler::metrics),
+    )]
 }
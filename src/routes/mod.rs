@@ -1,50 +1,219 @@
-use crate::{data, db, handler};
+use crate::{config, data, db, events, handler, metrics, readiness, telemetry};
 use std::convert::Infallible;
 use tracing::field::{display, Empty};
-use warp::filters::cookie;
+use warp::filters::{cookie, BoxedFilter};
+use warp::http::header::{HeaderMap, HeaderValue};
 use warp::Filter;
 
+mod admin;
+mod assets;
+mod caching;
+mod compression;
+mod graphql;
 mod health;
+mod negotiation;
+mod openapi;
+mod redirect;
+mod rejection;
+mod request_id;
 mod todos;
+mod v2;
+mod webhooks;
+
+pub use redirect::https_redirect;
+
+// A route the router serves, with its method and path the way the OpenAPI document writes them,
+// parameters in braces. The router is built from these, so the OpenAPI tests can check the document
+// against every route that is served
+pub struct Route {
+    pub method: &'static str,
+    pub path: &'static str,
+    filter: BoxedFilter<(Box<dyn warp::Reply>,)>,
+}
+
+impl Route {
+    fn new<F, R>(method: &'static str, path: &'static str, filter: F) -> Self
+    where
+        F: Filter<Extract = (R,), Error = warp::Rejection> + Clone + Send + Sync + 'static,
+        R: warp::Reply + 'static,
+    {
+        Self {
+            method,
+            path,
+            filter: filter
+                .map(|reply| Box::new(reply) as Box<dyn warp::Reply>)
+                .boxed(),
+        }
+    }
+}
+
+// Try the routes in order, the first one that matches serves the request
+fn join(routes: Vec<Route>) -> BoxedFilter<(Box<dyn warp::Reply>,)> {
+    routes
+        .into_iter()
+        .map(|route| route.filter)
+        .reduce(|joined, filter| joined.or(filter).unify().boxed())
+        .expect("a group has at least one route")
+}
+
+// The routes of the API grouped by where they are mounted
+struct Groups {
+    root: Vec<Route>,
+    v1: Vec<Route>,
+    v2: Vec<Route>,
+}
+
+fn groups(
+    client: db::Client,
+    hub: events::Hub,
+    readiness: readiness::Readiness,
+    settings: &config::Settings,
+) -> Groups {
+    let mut root = health::health_routes(client.clone(), readiness, settings.health.clone());
+    root.extend(health::metrics_routes());
+    root.extend(admin::admin_routes(settings.admin.clone()));
+    root.extend(openapi::openapi_routes());
+    root.extend(graphql::graphql_routes(client.clone(), hub.clone()));
+
+    let mut v1 = todos::todo_routes(client.clone(), hub.clone(), settings.idempotency.clone());
+    v1.extend(webhooks::webhook_routes(
+        client.clone(),
+        settings.webhooks.clone(),
+    ));
+    let v2 = v2::v2_routes(client, hub, settings.idempotency.clone());
+    Groups { root, v1, v2 }
+}
+
+// Every route `routes` serves, in the order they are tried. Unversioned aliases of v1 are left out,
+// they follow from the v1 paths. See `openapi::unversioned_alias`
+pub fn table(
+    client: db::Client,
+    hub: events::Hub,
+    readiness: readiness::Readiness,
+    settings: &config::Settings,
+) -> Vec<Route> {
+    let groups = groups(client, hub, readiness, settings);
+    groups
+        .root
+        .into_iter()
+        .chain(groups.v1)
+        .chain(groups.v2)
+        .collect()
+}
 
 pub fn routes(
     client: db::Client,
+    hub: events::Hub,
+    readiness: readiness::Readiness,
+    settings: &config::Settings,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    let base_route = warp::fs::dir("static");
+    metrics::init(settings.metrics.session_window);
+    let base_route = assets::asset_routes(&settings.http);
+
+    let groups = groups(client, hub, readiness, settings);
+    let v1 = join(groups.v1)
+        .with(version_headers(&settings.api, "v1"))
+        .boxed();
+    let v2 = join(groups.v2)
+        .with(version_headers(&settings.api, "v2"))
+        .boxed();
 
-    health::health_routes(client.clone())
-        .or(todos::todo_routes(client))
+    let routes = join(groups.root)
+        .or(negotiation::negotiated(
+            warp::path("api").and(
+                warp::path("v1")
+                    .and(v1.clone())
+                    .or(warp::path("v2").and(v2))
+                    // Unversioned paths are an alias of v1, which clients from before versioning rely
+                    // on. See `openapi::unversioned_alias`
+                    .or(v1),
+            ),
+        ))
         .or(base_route)
-        .with(warp::trace(|info| {
-            let span = tracing::info_span!(
-                "request",
-                id = rand::random::<u32>(),
-                method = %info.method(),
-                path = %info.path(),
-                version = ?info.version(),
-                remote.addr = Empty,
-                referer = Empty,
-            );
-
-            // Record optional fields.
-            if let Some(remote_addr) = info.remote_addr() {
-                span.record("remote.addr", &display(remote_addr));
-            }
+        .recover(negotiation::recover)
+        .recover(rejection::recover);
 
-            if let Some(referer) = info.referer() {
-                span.record("referer", &display(referer));
-            }
+    caching::cached(
+        settings.http.caching.clone(),
+        compression::compressed(
+            settings.http.compression.clone(),
+            request_id::tagged(routes),
+        ),
+    )
+    .with(warp::trace(|info| {
+        let span = tracing::info_span!(
+            "request",
+            request_id = Empty,
+            method = %info.method(),
+            path = %info.path(),
+            version = ?info.version(),
+            remote.addr = Empty,
+            referer = Empty,
+            otel.kind = "server",
+        );
+
+        // Record optional fields.
+        if let Some(remote_addr) = info.remote_addr() {
+            span.record("remote.addr", display(remote_addr));
+        }
+
+        if let Some(referer) = info.referer() {
+            span.record("referer", display(referer));
+        }
+
+        // Join the trace of the caller, so the request shows up in it
+        telemetry::join_remote_trace(&span, info.request_headers());
 
-            tracing::debug!(parent: &span, "received request");
+        tracing::debug!(parent: &span, "received request");
 
-            span
-        }))
+        span
+    }))
+    // Count and time every request for the metrics endpoint
+    .with(warp::log::custom(metrics::record_request))
+}
+
+// Headers announcing the lifecycle of an API version, empty for current versions
+fn version_headers(
+    settings: &config::ApiSettings,
+    version: &str,
+) -> warp::reply::with::WithHeaders {
+    let mut headers = HeaderMap::new();
+    if let Some(lifecycle) = settings.versions.get(version) {
+        if let Some(deprecated) = lifecycle.deprecated {
+            let value = format!("@{}", deprecated.timestamp());
+            headers.insert("deprecation", HeaderValue::from_str(&value).unwrap());
+        }
+        if let Some(sunset) = lifecycle.sunset {
+            let value = sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+            headers.insert("sunset", HeaderValue::from_str(&value).unwrap());
+        }
+        if let Some(successor) = &lifecycle.successor {
+            let value = format!("<{}>; rel=\"successor-version\"", successor);
+            match HeaderValue::from_str(&value) {
+                Ok(value) => {
+                    headers.insert("link", value);
+                }
+                Err(_) => tracing::warn!(version, "Ignoring invalid successor path"),
+            }
+        }
+    }
+    warp::reply::with::headers(headers)
 }
 
 fn with_db(client: db::Client) -> impl Filter<Extract = (db::Client,), Error = Infallible> + Clone {
     warp::any().map(move || client.clone())
 }
 
+fn with_hub(hub: events::Hub) -> impl Filter<Extract = (events::Hub,), Error = Infallible> + Clone {
+    warp::any().map(move || hub.clone())
+}
+
+fn with_settings<T: Clone + Send>(
+    settings: T,
+) -> impl Filter<Extract = (T,), Error = Infallible> + Clone {
+    warp::any().map(move || settings.clone())
+}
+
 fn with_optional_session(
 ) -> impl Filter<Extract = (Option<data::Session>,), Error = Infallible> + Copy {
     cookie::optional::<uuid::Uuid>("session")
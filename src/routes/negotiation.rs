@@ -0,0 +1,217 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::convert::Infallible;
+use warp::filters::body;
+use warp::http::header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE};
+use warp::http::StatusCode;
+use warp::hyper::body::Bytes;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+// The encodings request and response bodies of the API can be sent in
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Encoding {
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Encoding {
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type.to_ascii_lowercase().as_str() {
+            "application/json" => Some(Encoding::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Encoding::MessagePack)
+            }
+            "application/cbor" => Some(Encoding::Cbor),
+            _ => None,
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            Encoding::Json => "application/json",
+            Encoding::MessagePack => "application/msgpack",
+            Encoding::Cbor => "application/cbor",
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(&self, body: &[u8]) -> Result<T, String> {
+        match self {
+            Encoding::Json => serde_json::from_slice(body).map_err(|error| error.to_string()),
+            Encoding::MessagePack => rmp_serde::from_slice(body).map_err(|error| error.to_string()),
+            Encoding::Cbor => ciborium::de::from_reader(body).map_err(|error| error.to_string()),
+        }
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Encoding::Json => serde_json::to_vec(value).map_err(|error| error.to_string()),
+            // Structs are written as maps so fields are named, like they are in JSON
+            Encoding::MessagePack => {
+                rmp_serde::to_vec_named(value).map_err(|error| error.to_string())
+            }
+            Encoding::Cbor => {
+                let mut body = Vec::new();
+                ciborium::ser::into_writer(value, &mut body).map_err(|error| error.to_string())?;
+                Ok(body)
+            }
+        }
+    }
+
+    // Pick the encoding the client prefers from an Accept header, None if it accepts none of them.
+    // JSON wins ties, so wildcards and clients that don't send the header keep getting JSON
+    fn from_accept(accept: Option<&str>) -> Option<Self> {
+        let accept = match accept {
+            Some(accept) if !accept.trim().is_empty() => accept,
+            _ => return Some(Encoding::Json),
+        };
+
+        let mut preferred: Option<(Encoding, f32)> = None;
+        for range in accept.split(',') {
+            let mut parameters = range.split(';');
+            let media_type = parameters.next().unwrap_or_default().trim();
+            let quality = parameters
+                .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+                .find_map(|quality| quality.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            let encoding = match media_type {
+                "*/*" | "application/*" => Encoding::Json,
+                media_type => match Encoding::from_media_type(media_type) {
+                    Some(encoding) => encoding,
+                    None => continue,
+                },
+            };
+            let better = match preferred {
+                Some((_, best)) => quality > best,
+                None => true,
+            };
+            if quality > 0.0 && better {
+                preferred = Some((encoding, quality));
+            }
+        }
+        preferred.map(|(encoding, _)| encoding)
+    }
+}
+
+#[derive(Debug)]
+struct UnsupportedMediaType;
+impl warp::reject::Reject for UnsupportedMediaType {}
+
+#[derive(Debug)]
+struct InvalidBody(String);
+impl warp::reject::Reject for InvalidBody {}
+
+#[derive(Debug)]
+struct NotAcceptable;
+impl warp::reject::Reject for NotAcceptable {}
+
+// Refuse clients that accept none of the encodings. Routes that answer in JSON put this before their
+// handler, so nothing is written for a reply that can't be sent
+pub fn accepted() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("accept")
+        .and_then(|accept: Option<String>| async move {
+            match Encoding::from_accept(accept.as_deref()) {
+                Some(_) => Ok(()),
+                None => Err(warp::reject::custom(NotAcceptable)),
+            }
+        })
+        .untuple_one()
+}
+
+// Decode a request body according to its Content-Type, bodies without one are read as JSON
+pub fn body<T: DeserializeOwned + Send>(
+    limit: u64,
+) -> impl Filter<Extract = (T,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("content-type")
+        .and(body::content_length_limit(limit))
+        .and(body::bytes())
+        .and_then(|content_type: Option<String>, body: Bytes| async move {
+            let encoding = match content_type {
+                Some(content_type) => {
+                    let media_type = content_type.split(';').next().unwrap_or_default();
+                    Encoding::from_media_type(media_type.trim())
+                        .ok_or_else(|| warp::reject::custom(UnsupportedMediaType))?
+                }
+                None => Encoding::Json,
+            };
+            encoding
+                .decode(&body)
+                .map_err(|error| warp::reject::custom(InvalidBody(error)))
+        })
+}
+
+// Re-encode the JSON replies of a filter in the encoding asked for by the Accept header. Only JSON
+// replies are touched, so exports and calendar feeds keep their own content types. Clients that
+// accept none of the encodings were already turned away by `accepted`
+pub fn negotiated<F, R>(filter: F) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    warp::header::optional::<String>("accept")
+        .map(|accept: Option<String>| Encoding::from_accept(accept.as_deref()))
+        .and(filter)
+        .and_then(|encoding: Option<Encoding>, reply: R| async move {
+            Ok::<_, Infallible>(encode_reply(encoding, reply.into_response()).await)
+        })
+}
+
+async fn encode_reply(encoding: Option<Encoding>, response: Response) -> Response {
+    let is_json = matches!(
+        response.headers().get(CONTENT_TYPE),
+        Some(content_type) if content_type == "application/json"
+    );
+    let encoding = match encoding {
+        Some(encoding) if is_json && encoding != Encoding::Json => encoding,
+        _ => return response,
+    };
+
+    let (mut parts, body) = response.into_parts();
+    let encoded = warp::hyper::body::to_bytes(body)
+        .await
+        .map_err(|error| error.to_string())
+        .and_then(|body| {
+            serde_json::from_slice::<serde_json::Value>(&body).map_err(|error| error.to_string())
+        })
+        .and_then(|value| encoding.encode(&value));
+    match encoded {
+        Ok(body) => {
+            parts.headers.remove(CONTENT_LENGTH);
+            parts.headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static(encoding.content_type()),
+            );
+            Response::from_parts(parts, body.into())
+        }
+        Err(error) => {
+            tracing::warn!(error = %error, "Could not encode response");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+// Turn the rejections of the body and Accept filters into responses, everything else is passed on
+pub async fn recover(rejection: Rejection) -> Result<Box<dyn Reply>, Rejection> {
+    if rejection.find::<NotAcceptable>().is_some() {
+        tracing::warn!("Client does not accept any of the supported encodings");
+        Ok(Box::new(warp::reply::with_status(
+            "406: Not Acceptable",
+            StatusCode::NOT_ACCEPTABLE,
+        )))
+    } else if rejection.find::<UnsupportedMediaType>().is_some() {
+        tracing::warn!("Request body is in an unsupported encoding");
+        Ok(Box::new(warp::reply::with_status(
+            "415: Unsupported Media Type",
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        )))
+    } else if let Some(InvalidBody(error)) = rejection.find() {
+        tracing::warn!("Request body could not be decoded");
+        Ok(Box::new(warp::reply::with_status(
+            format!("Request body deserialize error: {}", error),
+            StatusCode::BAD_REQUEST,
+        )))
+    } else {
+        Err(rejection)
+    }
+}
@@ -0,0 +1,14 @@
+use super::*;
+use crate::openapi;
+
+pub fn openapi_routes() -> Vec<Route> {
+    // The document only depends on the code, so it's built once up front
+    let spec = openapi::spec();
+    vec![Route::new(
+        "get",
+        "/api/openapi.json",
+        warp::path!("api" / "openapi.json")
+            .and(warp::get())
+            .map(move || warp::reply::json(&spec)),
+    )]
+}
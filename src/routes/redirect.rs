@@ -0,0 +1,40 @@
+use warp::filters::path::FullPath;
+use warp::http::uri::Authority;
+use warp::http::{header, StatusCode};
+use warp::{Filter, Reply};
+
+// Send plain HTTP requests to the same path on the HTTPS port. Permanent redirects keep the method
+// and body, so API clients follow them too
+pub fn https_redirect(
+    port: u16,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("host")
+        .and(warp::path::full())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .map(move |host: Option<String>, path: FullPath, query: String| {
+            match location(host.as_deref(), port, path.as_str(), &query) {
+                Some(location) => warp::reply::with_header(
+                    StatusCode::PERMANENT_REDIRECT,
+                    header::LOCATION,
+                    location,
+                )
+                .into_response(),
+                None => warp::reply::with_status("Missing Host header", StatusCode::BAD_REQUEST)
+                    .into_response(),
+            }
+        })
+}
+
+fn location(host: Option<&str>, port: u16, path: &str, query: &str) -> Option<String> {
+    // The host keeps the port of the plain listener, which isn't the one to go to
+    let host = host?.parse::<Authority>().ok()?;
+    let mut location = match port {
+        443 => format!("https://{}{}", host.host(), path),
+        port => format!("https://{}:{}{}", host.host(), port, path),
+    };
+    if !query.is_empty() {
+        location.push('?');
+        location.push_str(query);
+    }
+    Some(location)
+}
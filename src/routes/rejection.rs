@@ -0,0 +1,49 @@
+use warp::http::header::WWW_AUTHENTICATE;
+use warp::http::StatusCode;
+use warp::reject::{
+    InvalidHeader, InvalidQuery, LengthRequired, MethodNotAllowed, MissingCookie, MissingHeader,
+    PayloadTooLarge, UnsupportedMediaType,
+};
+use warp::{Rejection, Reply};
+
+// Requests to the admin endpoints without a valid token
+#[derive(Debug)]
+pub struct Unauthorized;
+impl warp::reject::Reject for Unauthorized {}
+
+// Answer the rejections warp would otherwise answer itself, so their responses pass through the
+// filters wrapping the routes too. Like warp, the most specific rejection wins over 405 and 404
+pub async fn recover(rejection: Rejection) -> Result<Box<dyn Reply>, Rejection> {
+    if rejection.find::<Unauthorized>().is_some() {
+        return Ok(Box::new(warp::reply::with_header(
+            warp::reply::with_status("401: Unauthorized", StatusCode::UNAUTHORIZED),
+            WWW_AUTHENTICATE,
+            "Bearer",
+        )));
+    }
+
+    let (status, body) = if let Some(error) = rejection.find::<PayloadTooLarge>() {
+        (StatusCode::PAYLOAD_TOO_LARGE, error.to_string())
+    } else if let Some(error) = rejection.find::<UnsupportedMediaType>() {
+        (StatusCode::UNSUPPORTED_MEDIA_TYPE, error.to_string())
+    } else if let Some(error) = rejection.find::<LengthRequired>() {
+        (StatusCode::LENGTH_REQUIRED, error.to_string())
+    } else if let Some(error) = rejection.find::<MissingCookie>() {
+        (StatusCode::BAD_REQUEST, error.to_string())
+    } else if let Some(error) = rejection.find::<MissingHeader>() {
+        (StatusCode::BAD_REQUEST, error.to_string())
+    } else if let Some(error) = rejection.find::<InvalidHeader>() {
+        (StatusCode::BAD_REQUEST, error.to_string())
+    } else if let Some(error) = rejection.find::<InvalidQuery>() {
+        (StatusCode::BAD_REQUEST, error.to_string())
+    } else if let Some(error) = rejection.find::<warp::body::BodyDeserializeError>() {
+        (StatusCode::BAD_REQUEST, error.to_string())
+    } else if let Some(error) = rejection.find::<MethodNotAllowed>() {
+        (StatusCode::METHOD_NOT_ALLOWED, error.to_string())
+    } else if rejection.is_not_found() {
+        (StatusCode::NOT_FOUND, String::new())
+    } else {
+        return Err(rejection);
+    };
+    Ok(Box::new(warp::reply::with_status(body, status)))
+}
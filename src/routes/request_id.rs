@@ -0,0 +1,87 @@
+use std::convert::Infallible;
+use tracing::field::display;
+use warp::http::header::{HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE};
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+const REQUEST_ID: &str = "x-request-id";
+
+// Ids from upstream proxies are kept when they are short enough to log and safe to echo back
+fn request_id(upstream: Option<String>) -> String {
+    match upstream {
+        Some(id)
+            if !id.is_empty() && id.len() <= 128 && id.bytes().all(|b| b.is_ascii_graphic()) =>
+        {
+            id
+        }
+        _ => uuid::Uuid::new_v4().to_hyphenated().to_string(),
+    }
+}
+
+// Give every request an id, taken from its X-Request-Id header or generated. The id is recorded on
+// the request span and sent back in the X-Request-Id header and in the bodies of errors, as a line
+// of text or a field of JSON, so reports from users can be matched to the logs
+pub fn tagged<F, R>(filter: F) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    warp::header::optional::<String>(REQUEST_ID)
+        .map(|upstream: Option<String>| {
+            let id = request_id(upstream);
+            tracing::Span::current().record("request_id", display(&id));
+            id
+        })
+        .and(filter)
+        .and_then(|id: String, reply: R| async move {
+            Ok::<_, Infallible>(tag_reply(&id, reply.into_response()).await)
+        })
+}
+
+async fn tag_reply(id: &str, response: Response) -> Response {
+    let (mut parts, body) = response.into_parts();
+    parts.headers.insert(
+        HeaderName::from_static(REQUEST_ID),
+        HeaderValue::from_str(id).unwrap(),
+    );
+
+    if !(parts.status.is_client_error() || parts.status.is_server_error()) {
+        return Response::from_parts(parts, body);
+    }
+
+    // Errors are sent as plain text or without a content type, or as JSON when they carry details
+    let is_json = match parts.headers.get(CONTENT_TYPE) {
+        Some(content_type) => match content_type.to_str() {
+            Ok(content_type) if content_type.starts_with("text/plain") => false,
+            Ok(content_type) if content_type.starts_with("application/json") => true,
+            _ => return Response::from_parts(parts, body),
+        },
+        None => false,
+    };
+
+    let body = match warp::hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(error) => {
+            tracing::warn!(error = %error, "Could not read error body");
+            return Response::from_parts(parts, warp::hyper::Body::empty());
+        }
+    };
+    let body = if is_json {
+        // The id is added as a field of the error object, anything else is left as it is
+        match serde_json::from_slice::<serde_json::Value>(&body) {
+            Ok(serde_json::Value::Object(mut error)) => {
+                error.insert("request_id".to_owned(), id.into());
+                serde_json::to_vec(&error).unwrap_or_else(|_| body.to_vec())
+            }
+            _ => body.to_vec(),
+        }
+    } else {
+        match String::from_utf8_lossy(&body).trim_end() {
+            "" => format!("Request ID: {}", id),
+            message => format!("{}\nRequest ID: {}", message, id),
+        }
+        .into_bytes()
+    };
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, body.into())
+}
@@ -1,46 +1,270 @@
 use warp::filters::body;
 use warp::Filter;
 
-use super::{with_db, with_optional_session, with_required_session};
-use crate::{data, db, handler};
+use super::{
+    negotiation, with_db, with_hub, with_optional_session, with_required_session, with_settings,
+    Route,
+};
+use crate::{config, data, db, events, formats, handler};
 
 pub fn todo_routes(
     client: db::Client,
-) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    let todo = warp::path("api")
-        .and(warp::path("todos"))
-        .and(with_db(client));
+    hub: events::Hub,
+    idempotency: config::IdempotencySettings,
+) -> Vec<Route> {
+    let todo = warp::path("todos").and(with_db(client));
 
-    todo.clone()
-        .and(with_optional_session())
-        .and(warp::get())
-        .and_then(handler::todos::get_todos)
-        .or(todo
-            .clone()
-            .and(with_required_session())
-            .and(warp::post())
-            .and(todo_request().map(|request: data::TodoRequest| request.into()))
-            .and_then(handler::todos::create_todo))
-        .or(todo
-            .clone()
-            .and(with_required_session())
-            .and(warp::delete())
-            .and(warp::path::param::<uuid::Uuid>())
-            .and_then(handler::todos::delete_todo))
-        .or(todo
-            .clone()
-            .and(with_required_session())
-            .and(warp::path::param::<uuid::Uuid>())
-            .and(warp::put())
-            .and(todo_request())
-            .and_then(handler::todos::update_todo))
-        .or(todo
-            .clone()
-            .and(with_required_session())
-            .and(warp::delete())
-            .and_then(handler::todos::delete_all_todos))
+    // Routes with a fixed path segment go first so the catch-all list routes don't swallow them
+    vec![
+        Route::new(
+            "get",
+            "/api/v1/todos/ws",
+            warp::path("todos")
+                .and(warp::path("ws"))
+                .and(warp::path::end())
+                .and(warp::get())
+                .and(with_hub(hub.clone()))
+                .and(with_required_session())
+                .and(warp::ws())
+                .map(handler::todos::websocket),
+        ),
+        Route::new(
+            "get",
+            "/api/v1/todos/events",
+            warp::path("todos")
+                .and(warp::path("events"))
+                .and(warp::path::end())
+                .and(warp::get())
+                .and(with_hub(hub.clone()))
+                .and(with_required_session())
+                .and(warp::header::optional::<u64>("last-event-id"))
+                .map(handler::todos::server_sent_events),
+        ),
+        Route::new(
+            "get",
+            "/api/v1/todos/history",
+            todo.clone()
+                .and(with_required_session())
+                .and(warp::path("history"))
+                .and(warp::path::end())
+                .and(warp::get())
+                .and(warp::query::<data::HistoryQuery>())
+                .and(negotiation::accepted())
+                .and_then(handler::todos::history),
+        ),
+        Route::new(
+            "post",
+            "/api/v1/todos/undo",
+            todo.clone()
+                .and(with_hub(hub.clone()))
+                .and(with_required_session())
+                .and(warp::path("undo"))
+                .and(warp::path::end())
+                .and(warp::post())
+                .and(negotiation::accepted())
+                .and_then(handler::todos::undo),
+        ),
+        Route::new(
+            "post",
+            "/api/v1/todos/redo",
+            todo.clone()
+                .and(with_hub(hub.clone()))
+                .and(with_required_session())
+                .and(warp::path("redo"))
+                .and(warp::path::end())
+                .and(warp::post())
+                .and(negotiation::accepted())
+                .and_then(handler::todos::redo),
+        ),
+        Route::new(
+            "get",
+            "/api/v1/todos/trash",
+            todo.clone()
+                .and(with_required_session())
+                .and(warp::path("trash"))
+                .and(warp::path::end())
+                .and(warp::get())
+                .and(negotiation::accepted())
+                .and_then(handler::todos::get_trash),
+        ),
+        Route::new(
+            "post",
+            "/api/v1/todos/trash/{id}/restore",
+            todo.clone()
+                .and(with_hub(hub.clone()))
+                .and(with_required_session())
+                .and(warp::path("trash"))
+                .and(warp::path::param::<uuid::Uuid>())
+                .and(warp::path("restore"))
+                .and(warp::path::end())
+                .and(warp::post())
+                .and_then(handler::todos::restore_todo),
+        ),
+        Route::new(
+            "delete",
+            "/api/v1/todos/trash/{id}",
+            todo.clone()
+                .and(with_required_session())
+                .and(warp::path("trash"))
+                .and(warp::path::param::<uuid::Uuid>())
+                .and(warp::path::end())
+                .and(warp::delete())
+                .and_then(handler::todos::purge_todo),
+        ),
+        Route::new(
+            "delete",
+            "/api/v1/todos/trash",
+            todo.clone()
+                .and(with_required_session())
+                .and(warp::path("trash"))
+                .and(warp::path::end())
+                .and(warp::delete())
+                .and_then(handler::todos::empty_trash),
+        ),
+        Route::new(
+            "get",
+            "/api/v1/todos/calendar/{token}",
+            todo.clone()
+                .and(warp::path("calendar"))
+                .and(warp::path::param::<String>())
+                .and(warp::path::end())
+                .and(warp::get())
+                .and_then(handler::todos::calendar_feed),
+        ),
+        Route::new(
+            "post",
+            "/api/v1/todos/calendar",
+            todo.clone()
+                .and(with_required_session())
+                .and(warp::path("calendar"))
+                .and(warp::path::end())
+                .and(warp::post())
+                .and(negotiation::accepted())
+                .and_then(handler::todos::create_calendar_feed),
+        ),
+        Route::new(
+            "delete",
+            "/api/v1/todos/calendar",
+            todo.clone()
+                .and(with_required_session())
+                .and(warp::path("calendar"))
+                .and(warp::path::end())
+                .and(warp::delete())
+                .and_then(handler::todos::delete_calendar_feed),
+        ),
+        Route::new(
+            "get",
+            "/api/v1/todos/export",
+            todo.clone()
+                .and(with_required_session())
+                .and(warp::path("export"))
+                .and(warp::path::end())
+                .and(warp::get())
+                .and(warp::query::<formats::ExportQuery>())
+                .and_then(handler::todos::export_todos),
+        ),
+        Route::new(
+            "post",
+            "/api/v1/todos/import",
+            todo.clone()
+                .and(with_hub(hub.clone()))
+                .and(with_required_session())
+                .and(warp::path("import"))
+                .and(warp::path::end())
+                .and(warp::post())
+                .and(warp::query::<formats::ImportQuery>())
+                .and(import_body())
+                .and(negotiation::accepted())
+                .and_then(handler::todos::import_todos),
+        ),
+        Route::new(
+            "get",
+            "/api/v1/todos",
+            todo.clone()
+                .and(with_optional_session())
+                .and(warp::path::end())
+                .and(warp::get())
+                .and(negotiation::accepted())
+                .and_then(handler::todos::get_todos),
+        ),
+        Route::new(
+            "post",
+            "/api/v1/todos/batch",
+            todo.clone()
+                .and(with_hub(hub.clone()))
+                .and(with_settings(idempotency.clone()))
+                .and(with_required_session())
+                .and(warp::path("batch"))
+                .and(warp::path::end())
+                .and(warp::post())
+                .and(idempotency_key())
+                .and(batch_request())
+                .and(negotiation::accepted())
+                .and_then(handler::todos::batch),
+        ),
+        Route::new(
+            "post",
+            "/api/v1/todos",
+            todo.clone()
+                .and(with_hub(hub.clone()))
+                .and(with_settings(idempotency))
+                .and(with_required_session())
+                .and(warp::path::end())
+                .and(warp::post())
+                .and(idempotency_key())
+                .and(todo_request())
+                .and(negotiation::accepted())
+                .and_then(handler::todos::create_todo),
+        ),
+        Route::new(
+            "delete",
+            "/api/v1/todos/{id}",
+            todo.clone()
+                .and(with_hub(hub.clone()))
+                .and(with_required_session())
+                .and(warp::delete())
+                .and(warp::path::param::<uuid::Uuid>())
+                .and(warp::path::end())
+                .and_then(handler::todos::delete_todo),
+        ),
+        Route::new(
+            "put",
+            "/api/v1/todos/{id}",
+            todo.clone()
+                .and(with_hub(hub.clone()))
+                .and(with_required_session())
+                .and(warp::path::param::<uuid::Uuid>())
+                .and(warp::path::end())
+                .and(warp::put())
+                .and(todo_request())
+                .and_then(handler::todos::update_todo),
+        ),
+        Route::new(
+            "delete",
+            "/api/v1/todos",
+            todo.and(with_hub(hub))
+                .and(with_required_session())
+                .and(warp::path::end())
+                .and(warp::delete())
+                .and_then(handler::todos::delete_all_todos),
+        ),
+    ]
 }
 
 fn todo_request() -> impl Filter<Extract = (data::TodoRequest,), Error = warp::Rejection> + Clone {
-    body::content_length_limit(4096).and(body::json::<data::TodoRequest>())
+    negotiation::body::<data::TodoRequest>(4096)
+}
+
+fn batch_request(
+) -> impl Filter<Extract = (Vec<data::BatchOperation>,), Error = warp::Rejection> + Clone {
+    negotiation::body::<Vec<data::BatchOperation>>(65536)
+}
+
+fn import_body(
+) -> impl Filter<Extract = (warp::hyper::body::Bytes,), Error = warp::Rejection> + Clone {
+    body::content_length_limit(1 << 20).and(body::bytes())
+}
+
+fn idempotency_key() -> impl Filter<Extract = (Option<String>,), Error = warp::Rejection> + Copy {
+    warp::header::optional::<String>("idempotency-key")
 }
@@ -0,0 +1,82 @@
+use warp::Filter;
+
+use super::{
+    negotiation, with_db, with_hub, with_optional_session, with_required_session, with_settings,
+    Route,
+};
+use crate::{config, data, db, events, handler};
+
+// Version 2 only changes the shape of todos and so far only covers the todo list itself. History,
+// trash, batches, exports, calendars, events and webhooks are only served under version 1
+pub fn v2_routes(
+    client: db::Client,
+    hub: events::Hub,
+    idempotency: config::IdempotencySettings,
+) -> Vec<Route> {
+    let todo = warp::path("todos").and(with_db(client));
+
+    vec![
+        Route::new(
+            "get",
+            "/api/v2/todos",
+            todo.clone()
+                .and(with_optional_session())
+                .and(warp::path::end())
+                .and(warp::get())
+                .and(negotiation::accepted())
+                .and_then(handler::v2::get_todos),
+        ),
+        Route::new(
+            "post",
+            "/api/v2/todos",
+            todo.clone()
+                .and(with_hub(hub.clone()))
+                .and(with_settings(idempotency))
+                .and(with_required_session())
+                .and(warp::path::end())
+                .and(warp::post())
+                .and(warp::header::optional::<String>("idempotency-key"))
+                .and(todo_request())
+                .and(negotiation::accepted())
+                .and_then(handler::v2::create_todo),
+        ),
+        Route::new(
+            "put",
+            "/api/v2/todos/{id}",
+            todo.clone()
+                .and(with_hub(hub.clone()))
+                .and(with_required_session())
+                .and(warp::path::param::<uuid::Uuid>())
+                .and(warp::path::end())
+                .and(warp::put())
+                .and(todo_request())
+                .and(negotiation::accepted())
+                .and_then(handler::v2::update_todo),
+        ),
+        Route::new(
+            "delete",
+            "/api/v2/todos/{id}",
+            todo.clone()
+                .and(with_hub(hub.clone()))
+                .and(with_required_session())
+                .and(warp::delete())
+                .and(warp::path::param::<uuid::Uuid>())
+                .and(warp::path::end())
+                .and_then(handler::todos::delete_todo),
+        ),
+        Route::new(
+            "delete",
+            "/api/v2/todos",
+            todo.and(with_hub(hub))
+                .and(with_required_session())
+                .and(warp::path::end())
+                .and(warp::delete())
+                .and_then(handler::todos::delete_all_todos),
+        ),
+    ]
+}
+
+fn todo_request() -> impl Filter<Extract = (data::v2::TodoRequest,), Error = warp::Rejection> + Clone
+{
+    negotiation::body::<data::v2::TodoRequest>(4096)
+}
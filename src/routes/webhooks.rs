@@ -0,0 +1,62 @@
+use warp::Filter;
+
+use super::{negotiation, with_db, with_required_session, with_settings, Route};
+use crate::{config, data, db, handler};
+
+pub fn webhook_routes(client: db::Client, settings: config::WebhookSettings) -> Vec<Route> {
+    let webhook = warp::path("webhooks")
+        .and(with_db(client.clone()))
+        .and(with_required_session());
+
+    vec![
+        Route::new(
+            "get",
+            "/api/v1/webhooks/deliveries",
+            webhook
+                .clone()
+                .and(warp::path("deliveries"))
+                .and(warp::path::end())
+                .and(warp::get())
+                .and(warp::query::<data::DeliveryQuery>())
+                .and(negotiation::accepted())
+                .and_then(handler::webhooks::get_deliveries),
+        ),
+        Route::new(
+            "get",
+            "/api/v1/webhooks",
+            webhook
+                .clone()
+                .and(warp::path::end())
+                .and(warp::get())
+                .and(negotiation::accepted())
+                .and_then(handler::webhooks::get_webhooks),
+        ),
+        Route::new(
+            "post",
+            "/api/v1/webhooks",
+            warp::path("webhooks")
+                .and(with_db(client))
+                .and(with_settings(settings))
+                .and(with_required_session())
+                .and(warp::path::end())
+                .and(warp::post())
+                .and(webhook_request())
+                .and(negotiation::accepted())
+                .and_then(handler::webhooks::create_webhook),
+        ),
+        Route::new(
+            "delete",
+            "/api/v1/webhooks/{id}",
+            webhook
+                .and(warp::path::param::<uuid::Uuid>())
+                .and(warp::path::end())
+                .and(warp::delete())
+                .and_then(handler::webhooks::delete_webhook),
+        ),
+    ]
+}
+
+fn webhook_request(
+) -> impl Filter<Extract = (data::WebhookRequest,), Error = warp::Rejection> + Clone {
+    negotiation::body::<data::WebhookRequest>(4096)
+}
@@ -1,37 +1,365 @@
-use crate::{config, db, error, routes};
+use crate::{config, db, error, events, grpc, listen, readiness, routes, tls, webhooks};
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt};
+use std::collections::HashSet;
 use std::future::Future;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use std::str::FromStr;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::signal;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio_stream::wrappers::TcpListenerStream;
+#[cfg(unix)]
+use tokio_stream::wrappers::UnixListenerStream;
+use warp::{Filter, Rejection, Reply};
+
+// Stops a running server the same way SIGTERM and SIGINT do. Clones stop the same server
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Shutdown {
+    fn new() -> Shutdown {
+        Shutdown {
+            sender: Arc::new(watch::channel(false).0),
+        }
+    }
+
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    // Resolves once the shutdown was triggered
+    pub async fn triggered(&self) {
+        self.sender
+            .subscribe()
+            .wait_for(|triggered| *triggered)
+            .await
+            .ok();
+    }
+}
 
 // Run is in its own function so it can be started as a separate task for Integration Tests
 pub async fn run(
     settings: config::Settings,
-) -> Result<(SocketAddr, impl Future<Output = ()> + 'static), error::Error> {
+) -> Result<
+    (
+        Vec<listen::Address>,
+        Shutdown,
+        impl Future<Output = ()> + 'static,
+    ),
+    error::Error,
+> {
     // Create a Database Connection from the URI
-    let client = db::Client::with_uri_str(settings.database.uri)
+    let client = db::Client::with_uri_str(&settings.database.uri)
         .await
         .map_err(|source| error::Error::ClientInitializationError { source })?;
 
+    let shutdown = Shutdown::new();
+    tokio::spawn(shutdown_on_signal(shutdown.clone()));
+
+    // Background tasks stop when the shutdown starts, the server waits for them before it stops
+    let mut tasks = JoinSet::new();
+
+    // Set up the collections while the server starts, so it comes up even when the database doesn't
+    // answer yet. It isn't ready until they are
+    let readiness = readiness::Readiness::new();
+    tasks.spawn(until_shutdown(
+        shutdown.clone(),
+        initialize(client.clone(), readiness.clone()),
+    ));
+
+    // Periodically clear out todos that have been in the trash for too long
+    tasks.spawn(until_shutdown(
+        shutdown.clone(),
+        purge_trash(client.clone(), settings.trash.clone()),
+    ));
+
+    // Handlers publish change events here for connected clients to pick up
+    let hub = events::Hub::new(&settings.events);
+    if settings.events.source == config::EventSource::ChangeStream {
+        tasks.spawn(until_shutdown(
+            shutdown.clone(),
+            watch_changes(client.clone(), hub.clone()),
+        ));
+    }
+
+    // Deliver events to the webhooks registered for them, deliveries under way are finished when the
+    // shutdown starts
+    tasks.spawn(webhooks::run(
+        client.clone(),
+        hub.clone(),
+        settings.webhooks.clone(),
+        shutdown.clone(),
+    ));
+
     // Add all our routes
-    let routes = routes::routes(client);
+    let routes = routes::routes(client.clone(), hub.clone(), readiness.clone(), &settings);
 
-    // Create a Socket to bind the server to
-    let socket = SocketAddr::new(
-        IpAddr::V4(
-            Ipv4Addr::from_str(&settings.server.address)
-                .expect("Could Not Parse IP Address from Configuration"),
-        ),
-        settings.server.application_port,
-    );
+    let certificates = match &settings.server.tls {
+        Some(tls) => {
+            // Renewed certificates are picked up while the server runs
+            let certificates = tls::Certificates::load(tls.clone())?;
+            tasks.spawn(until_shutdown(
+                shutdown.clone(),
+                certificates.clone().watch(),
+            ));
+            Some(certificates)
+        }
+        None => None,
+    };
 
     //Start the Server
-    Ok(
-        warp::serve(routes).bind_with_graceful_shutdown(socket, async {
-            match signal::ctrl_c().await {
-                Ok(_) => println!("💀 Shutting Down Server"),
-                Err(_) => println!("Error handling SIGINT"),
+    let drain_period = Duration::from_secs(settings.server.shutdown.drain_period);
+    let timeout = Duration::from_secs(settings.server.shutdown.timeout);
+    let drained = {
+        let shutdown = shutdown.clone();
+        move || drained(shutdown.clone(), readiness.clone(), drain_period)
+    };
+
+    let redirect_port = settings
+        .server
+        .tls
+        .as_ref()
+        .and_then(|tls| tls.redirect_port);
+
+    let mut addresses = Vec::new();
+    let mut servers = Vec::new();
+    let mut redirected = HashSet::new();
+    for listener in listen::bind(&settings.server).await? {
+        let address = listener.address().map_err(error::Error::ServerBindError)?;
+        tracing::info!(address = %address, tls = certificates.is_some(), "Serving HTTP");
+
+        // Plain HTTP on the redirect port of each address served over TLS
+        if let (Some(port), listen::Address::Tcp(socket)) = (redirect_port, &address) {
+            if redirected.insert(socket.ip()) {
+                let plain = tokio::net::TcpListener::bind(SocketAddr::new(socket.ip(), port))
+                    .await
+                    .map_err(error::Error::ServerBindError)?;
+                let redirect = plain.local_addr().ok();
+                tracing::info!(address = ?redirect, "Redirecting HTTP to HTTPS");
+                tasks.spawn(
+                    warp::serve(routes::https_redirect(socket.port()))
+                        .serve_incoming_with_graceful_shutdown(
+                            TcpListenerStream::new(plain),
+                            drained(),
+                        ),
+                );
+            }
+        }
+
+        servers.push(serve(
+            routes.clone(),
+            listener,
+            certificates.clone(),
+            drained(),
+        ));
+        addresses.push(address);
+    }
+    let server = futures::future::join_all(servers);
+
+    // The gRPC service runs next to the HTTP server, on its own port and with the same certificates
+    for listener in listen::bind_grpc(&settings.server, &addresses).await? {
+        tracing::info!(
+            address = ?listener.local_addr().ok(),
+            tls = certificates.is_some(),
+            "Serving gRPC"
+        );
+        tasks.spawn(serve_grpc(
+            listener,
+            certificates.clone(),
+            client.clone(),
+            hub.clone(),
+            drained(),
+        ));
+    }
+
+    let handle = shutdown.clone();
+    let server = async move {
+        let stopped = async move {
+            server.await;
+            while tasks.join_next().await.is_some() {}
+        };
+        let deadline = async {
+            shutdown.triggered().await;
+            tokio::time::sleep(drain_period + timeout).await;
+        };
+        // Requests and tasks still going at the deadline are dropped with the futures
+        tokio::select! {
+            _ = stopped => {}
+            _ = deadline => tracing::warn!("Shutdown timed out, dropping open requests"),
+        }
+        client.shutdown().await;
+        tracing::info!("Server stopped");
+    };
+    Ok((addresses, handle, server))
+}
+
+// Serve the routes on a listener, over TLS when certificates are configured
+fn serve<F>(
+    routes: F,
+    listener: listen::Listener,
+    certificates: Option<Arc<tls::Certificates>>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> BoxFuture<'static, ()>
+where
+    F: Filter<Error = Rejection> + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    match (listener, certificates) {
+        (listen::Listener::Tcp(listener), None) => warp::serve(routes)
+            .serve_incoming_with_graceful_shutdown(TcpListenerStream::new(listener), shutdown)
+            .boxed(),
+        (listen::Listener::Tcp(listener), Some(certificates)) => tls::serve(
+            routes,
+            TcpListenerStream::new(listener),
+            certificates,
+            shutdown,
+        )
+        .boxed(),
+        #[cfg(unix)]
+        (listen::Listener::Unix(listener), None) => warp::serve(routes)
+            .serve_incoming_with_graceful_shutdown(UnixListenerStream::new(listener), shutdown)
+            .boxed(),
+        #[cfg(unix)]
+        (listen::Listener::Unix(listener), Some(certificates)) => tls::serve(
+            routes,
+            UnixListenerStream::new(listener),
+            certificates,
+            shutdown,
+        )
+        .boxed(),
+    }
+}
+
+// Resolves when a listener should close: once the shutdown started, readiness failed and load
+// balancers had the drain period to stop sending requests
+async fn drained(shutdown: Shutdown, readiness: readiness::Readiness, drain_period: Duration) {
+    shutdown.triggered().await;
+    readiness.start_draining();
+    tokio::time::sleep(drain_period).await;
+    tracing::info!("Closing the listener, waiting for open requests to finish");
+}
+
+// Orchestrators ask to stop with SIGTERM, terminals with SIGINT
+async fn shutdown_on_signal(shutdown: Shutdown) {
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(_) => {
+                println!("Error handling SIGTERM");
+                futures::future::pending::<()>().await
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = futures::future::pending::<()>();
+
+    tokio::select! {
+        _ = shutdown.triggered() => return,
+        result = signal::ctrl_c() => {
+            if result.is_err() {
+                println!("Error handling SIGINT");
+                return;
+            }
+        }
+        _ = terminate => {}
+    }
+    println!("💀 Shutting Down Server");
+    shutdown.trigger();
+}
+
+async fn until_shutdown(shutdown: Shutdown, task: impl Future<Output = ()>) {
+    tokio::select! {
+        _ = task => {}
+        _ = shutdown.triggered() => {}
+    }
+}
+
+async fn serve_grpc(
+    listener: tokio::net::TcpListener,
+    certificates: Option<Arc<tls::Certificates>>,
+    client: db::Client,
+    hub: events::Hub,
+    shutdown: impl Future<Output = ()>,
+) {
+    // Like the HTTP listeners, calls under way when it closes are finished before it stops
+    let server = tonic::transport::Server::builder().add_service(grpc::service(client, hub));
+    let incoming = TcpListenerStream::new(listener);
+    let result = match certificates {
+        Some(certificates) => {
+            server
+                .serve_with_incoming_shutdown(tls::grpc_incoming(incoming, certificates), shutdown)
+                .await
+        }
+        None => {
+            server
+                .serve_with_incoming_shutdown(incoming, shutdown)
+                .await
+        }
+    };
+    if let Err(error) = result {
+        tracing::error!(error = ?error, "gRPC server failed");
+    }
+}
+
+// Keep trying until the collections are set up, then mark the server as ready
+async fn initialize(client: db::Client, readiness: readiness::Readiness) {
+    loop {
+        match db::initialize(&client).await {
+            Ok(()) => {
+                tracing::info!("Collections are set up");
+                readiness.set_initialized();
+                return;
+            }
+            Err(error) => tracing::warn!(error = ?error, "Could not set up the collections"),
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+    }
+}
+
+async fn purge_trash(client: db::Client, settings: config::TrashSettings) {
+    let mut interval =
+        tokio::time::interval(std::time::Duration::from_secs(settings.purge_interval));
+    loop {
+        interval.tick().await;
+        let cutoff = chrono::Utc::now() - chrono::Duration::seconds(settings.retention);
+        match db::purge_expired_trash(&client, cutoff).await {
+            Ok(purged) => tracing::debug!(lists = purged, "Purged expired todos from trash"),
+            Err(error) => tracing::warn!(error = ?error, "Could not purge expired todos"),
+        }
+    }
+}
+
+// Feed changes made by every instance into the hub, reconnecting whenever the stream drops
+async fn watch_changes(client: db::Client, hub: events::Hub) {
+    let mut resume_token = None;
+    loop {
+        match db::watch_todo_lists(&client, resume_token.clone()).await {
+            Ok(mut stream) => {
+                tracing::info!("Watching todo lists for changes");
+                while let Some(change) = stream.next().await {
+                    match change {
+                        Ok(change) => {
+                            resume_token = stream.resume_token();
+                            if let Some(event) = events::Event::from_change(change) {
+                                hub.publish_change(event);
+                            }
+                        }
+                        Err(error) => {
+                            tracing::warn!(error = ?error, "Todo list change stream failed");
+                            break;
+                        }
+                    }
+                }
             }
-        }),
-    )
+            Err(error) => tracing::warn!(error = ?error, "Could not watch todo lists for changes"),
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+    }
 }
@@ -0,0 +1,168 @@
+use crate::error::Error::{LogFileError, LogFilterError, TelemetryError, ValidationError};
+use crate::{config, Result};
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Sampler, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use std::sync::OnceLock;
+use tracing::Subscriber;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+use warp::http::HeaderMap;
+
+// Handle to the filter of the global subscriber, so it can be changed while the server runs
+static LOG_FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+// Exports the spans and writes the log lines still buffered when dropped, keep it alive until the
+// server stops
+pub struct Guard {
+    provider: Option<TracerProvider>,
+    _log_file: Option<WorkerGuard>,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take() {
+            if let Err(error) = provider.shutdown() {
+                eprintln!("Could not export remaining spans: {}", error);
+            }
+        }
+    }
+}
+
+// Install the global subscriber, logging with the filters and format of the config and exporting
+// spans to a collector when an OTLP endpoint is configured. Has to be called from within the tokio
+// runtime
+pub fn init(settings: &config::Settings) -> Result<Guard> {
+    let provider = match &settings.telemetry.otlp_endpoint {
+        Some(endpoint) => Some(tracer_provider(endpoint, &settings.telemetry)?),
+        None => None,
+    };
+    let exporter = provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer("warp_crud")));
+
+    let (log_file, log_file_guard) = match &settings.log.file {
+        Some(file) => {
+            let appender = RollingFileAppender::builder()
+                .rotation(match file.rotation {
+                    config::LogRotation::Minutely => Rotation::MINUTELY,
+                    config::LogRotation::Hourly => Rotation::HOURLY,
+                    config::LogRotation::Daily => Rotation::DAILY,
+                    config::LogRotation::Never => Rotation::NEVER,
+                })
+                .filename_prefix(&file.prefix)
+                .build(&file.directory)
+                .map_err(LogFileError)?;
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            (
+                Some(log_layer(settings.log.format, writer, false)),
+                Some(guard),
+            )
+        }
+        None => (None, None),
+    };
+
+    let (filter, handle) = reload::Layer::new(EnvFilter::new(settings.log.rules.join(",")));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(log_layer(settings.log.format, std::io::stdout, true))
+        .with(log_file)
+        .with(exporter)
+        .init();
+    LOG_FILTER.set(handle).ok();
+
+    Ok(Guard {
+        provider,
+        _log_file: log_file_guard,
+    })
+}
+
+fn log_layer<S, W>(
+    format: config::LogFormat,
+    writer: W,
+    ansi: bool,
+) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi)
+        // Record an event when each span closes. Used to time duration of spans
+        .with_span_events(FmtSpan::CLOSE);
+    match format {
+        config::LogFormat::Pretty => layer.boxed(),
+        config::LogFormat::Json => layer.json().boxed(),
+    }
+}
+
+// The directives logs are filtered with, None until the subscriber is installed
+pub fn log_filter() -> Option<String> {
+    LOG_FILTER
+        .get()
+        .and_then(|handle| handle.with_current(|filter| filter.to_string()).ok())
+}
+
+// Filter logs with new EnvFilter directives, returning them as they were parsed
+pub fn set_log_filter(directives: &str) -> Result<String> {
+    let filter =
+        EnvFilter::try_new(directives).map_err(|error| ValidationError(error.to_string()))?;
+    let handle = LOG_FILTER
+        .get()
+        .ok_or_else(|| LogFilterError(String::from("logging is not initialized")))?;
+    let directives = filter.to_string();
+    handle
+        .reload(filter)
+        .map_err(|error| LogFilterError(error.to_string()))?;
+    tracing::info!(filter = %directives, "Changed log filter");
+    Ok(directives)
+}
+
+fn tracer_provider(endpoint: &str, settings: &config::TelemetrySettings) -> Result<TracerProvider> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(TelemetryError)?;
+
+    // Traces started upstream keep the sampling decision of their caller
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+        settings.sampling_ratio,
+    )));
+    Ok(TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_sampler(sampler)
+        .with_resource(Resource::new(vec![KeyValue::new(
+            "service.name",
+            settings.service_name.clone(),
+        )]))
+        .build())
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+// Make a span part of the trace of the caller when the request carries a W3C traceparent header
+pub fn join_remote_trace(span: &tracing::Span, headers: &HeaderMap) {
+    span.set_parent(TraceContextPropagator::new().extract(&HeaderExtractor(headers)));
+}
@@ -0,0 +1,255 @@
+use crate::error::Error::TlsError;
+use crate::{config, Result};
+use futures::{future, Stream, StreamExt};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use std::convert::Infallible;
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, BufReader};
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tonic::transport::server::{Connected, TcpConnectInfo};
+use warp::hyper::server::accept;
+use warp::hyper::service::make_service_fn;
+use warp::{Filter, Rejection, Reply};
+
+// Connections that haven't finished their handshake by then are dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Handshakes done at the same time, more connections wait in the listen queue
+const CONCURRENT_HANDSHAKES: usize = 256;
+
+struct Loaded {
+    key: Arc<CertifiedKey>,
+    modified: (Option<SystemTime>, Option<SystemTime>),
+}
+
+// The certificate last loaded from the configured files, so renewed certificates are served
+// without a restart
+pub struct Certificates {
+    settings: config::TlsSettings,
+    loaded: RwLock<Loaded>,
+}
+
+impl Certificates {
+    pub fn load(settings: config::TlsSettings) -> Result<Arc<Certificates>> {
+        let loaded = Loaded {
+            modified: modified(&settings),
+            key: Arc::new(read(&settings)?),
+        };
+        Ok(Arc::new(Certificates {
+            settings,
+            loaded: RwLock::new(loaded),
+        }))
+    }
+
+    // Load the files again when either changed since they were last loaded, returning whether
+    // they did. The old certificate is kept when the new one can't be loaded
+    pub fn reload(&self) -> Result<bool> {
+        let modified = modified(&self.settings);
+        if self.loaded.read().unwrap().modified == modified {
+            return Ok(false);
+        }
+        let key = Arc::new(read(&self.settings)?);
+        *self.loaded.write().unwrap() = Loaded { key, modified };
+        tracing::info!(certificate = %self.settings.certificate, "Reloaded TLS certificate");
+        Ok(true)
+    }
+
+    pub async fn watch(self: Arc<Self>) {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(self.settings.reload_interval));
+        loop {
+            interval.tick().await;
+            if let Err(error) = self.reload() {
+                tracing::warn!(error = %error, "Could not reload TLS certificate");
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for Certificates {
+    fn resolve(&self, _: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.loaded.read().unwrap().key.clone())
+    }
+}
+
+fn modified(settings: &config::TlsSettings) -> (Option<SystemTime>, Option<SystemTime>) {
+    let modified = |path: &str| {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+    (modified(&settings.certificate), modified(&settings.key))
+}
+
+fn read(settings: &config::TlsSettings) -> Result<CertifiedKey> {
+    let open = |path: &str| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|error| TlsError(format!("{}: {}", path, error)))
+    };
+
+    let certificates = rustls_pemfile::certs(&mut open(&settings.certificate)?)
+        .map_err(|error| TlsError(format!("{}: {}", settings.certificate, error)))?;
+    if certificates.is_empty() {
+        return Err(TlsError(format!(
+            "{}: no certificates found",
+            settings.certificate
+        )));
+    }
+
+    let key = rustls_pemfile::read_all(&mut open(&settings.key)?)
+        .map_err(|error| TlsError(format!("{}: {}", settings.key, error)))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| TlsError(format!("{}: no private key found", settings.key)))?;
+    let key = rustls::sign::any_supported_type(&key)
+        .map_err(|error| TlsError(format!("{}: {}", settings.key, error)))?;
+
+    Ok(CertifiedKey::new(
+        certificates.into_iter().map(Certificate).collect(),
+        key,
+    ))
+}
+
+// Serve the filter over TLS on the incoming connections until the shutdown future resolves, then
+// wait for open requests like warp's own server. HTTP/2 is offered to clients through ALPN
+pub fn serve<F, I, S>(
+    filter: F,
+    incoming: I,
+    certificates: Arc<Certificates>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> impl Future<Output = ()> + Send + 'static
+where
+    F: Filter<Error = Rejection> + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+    I: Stream<Item = io::Result<S>> + Send + 'static,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let connections = accept_tls(incoming, certificates, &[b"h2", b"http/1.1"]);
+
+    let service = warp::service(filter);
+    let server = warp::hyper::Server::builder(accept::from_stream(connections))
+        .serve(make_service_fn(move |_| {
+            let service = service.clone();
+            async move { Ok::<_, Infallible>(service) }
+        }))
+        .with_graceful_shutdown(shutdown);
+    async move {
+        if let Err(error) = server.await {
+            tracing::error!(error = %error, "HTTPS server failed");
+        }
+    }
+}
+
+// Accept TLS connections on the incoming streams, offering clients the protocols in `alpn`.
+// Connections that fail their handshake are logged and left out
+pub fn accept_tls<I, S>(
+    incoming: I,
+    certificates: Arc<Certificates>,
+    alpn: &[&[u8]],
+) -> impl Stream<Item = std::result::Result<TlsStream<S>, Infallible>> + Send + 'static
+where
+    I: Stream<Item = io::Result<S>> + Send + 'static,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(certificates);
+    config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    // Handshakes run concurrently, so a slow client doesn't hold up the others
+    incoming
+        .filter_map(|stream| {
+            future::ready(match stream {
+                Ok(stream) => Some(stream),
+                Err(error) => {
+                    tracing::warn!(error = %error, "Could not accept connection");
+                    None
+                }
+            })
+        })
+        .map(move |stream| tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.clone().accept(stream)))
+        .buffer_unordered(CONCURRENT_HANDSHAKES)
+        .filter_map(|handshake| {
+            future::ready(match handshake {
+                Ok(Ok(stream)) => Some(Ok::<_, Infallible>(stream)),
+                Ok(Err(error)) => {
+                    tracing::debug!(error = %error, "TLS handshake failed");
+                    None
+                }
+                Err(_) => {
+                    tracing::debug!("TLS handshake timed out");
+                    None
+                }
+            })
+        })
+}
+
+// A TLS connection to the gRPC server. tonic only knows the connections of its own TLS stack, this
+// gives it the addresses of the ones accepted by the stack the HTTP server uses
+pub struct GrpcConnection(TlsStream<TcpStream>);
+
+impl Connected for GrpcConnection {
+    type ConnectInfo = TcpConnectInfo;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        self.0.get_ref().0.connect_info()
+    }
+}
+
+impl AsyncRead for GrpcConnection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for GrpcConnection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_shutdown(cx)
+    }
+}
+
+// Accept gRPC connections over TLS with the same certificates as the HTTP server. gRPC only
+// speaks HTTP/2, so that's the only protocol offered
+pub fn grpc_incoming<I>(
+    incoming: I,
+    certificates: Arc<Certificates>,
+) -> impl Stream<Item = std::result::Result<GrpcConnection, Infallible>> + Send + 'static
+where
+    I: Stream<Item = io::Result<TcpStream>> + Send + 'static,
+{
+    accept_tls(incoming, certificates, &[b"h2"]).map(|stream| stream.map(GrpcConnection))
+}
@@ -0,0 +1,332 @@
+use crate::error::Error::ValidationError;
+use crate::events::{Event, EventKind, Hub};
+use crate::{config, data, db, metrics, startup, Result};
+use chrono::prelude::*;
+use hmac::{Hmac, Mac};
+use ipnet::IpNet;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use serde::Serialize;
+use sha2::Sha256;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinSet;
+use warp::hyper::client::connect::dns::Name;
+
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+pub const TIMESTAMP_HEADER: &str = "x-webhook-timestamp";
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    delivery: uuid::Uuid,
+    event: data::WebhookEvent,
+    timestamp: DateTime<Utc>,
+    data: &'a EventKind,
+}
+
+impl data::WebhookEvent {
+    // The webhook event a change event corresponds to, if webhooks can subscribe to it at all
+    pub fn from_event(kind: &EventKind) -> Option<Self> {
+        match kind {
+            EventKind::TodoCreated { .. } => Some(data::WebhookEvent::TodoCreated),
+            EventKind::TodoCompleted { .. } => Some(data::WebhookEvent::TodoCompleted),
+            EventKind::TodoDeleted { .. } => Some(data::WebhookEvent::TodoDeleted),
+            EventKind::ListCleared => Some(data::WebhookEvent::ListCleared),
+            _ => None,
+        }
+    }
+}
+
+// Sign a payload the way receivers are expected to verify it: an HMAC-SHA256 over the timestamp
+// header and the body joined by a period, keyed with the webhook's secret
+pub fn sign(secret: &str, timestamp: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+// Decides which addresses webhooks may be delivered to. Loopback, private and link-local addresses
+// are refused unless they are in webhooks.allowed_networks, so sessions can't have the server send
+// requests to itself or into the network it runs in
+#[derive(Clone)]
+pub struct Receivers {
+    allowed: Arc<Vec<IpNet>>,
+}
+
+impl Receivers {
+    pub fn new(settings: &config::WebhookSettings) -> Self {
+        // The networks were checked when the settings were loaded
+        let allowed = settings
+            .allowed_networks
+            .iter()
+            .filter_map(|network| network.parse().ok())
+            .collect();
+        Self {
+            allowed: Arc::new(allowed),
+        }
+    }
+
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        is_public(ip) || self.allowed.iter().any(|network| network.contains(&ip))
+    }
+
+    // Check a webhook URL and every address its host resolves to
+    pub async fn check(&self, url: &str) -> Result<()> {
+        let url = match reqwest::Url::parse(url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => url,
+            _ => return Err(ValidationError(String::from("url must be an http(s) URL"))),
+        };
+        let port = url.port_or_known_default().unwrap_or(80);
+        let host = url.host_str().unwrap_or_default();
+        let addresses: Vec<IpAddr> = match host.trim_matches(|c| c == '[' || c == ']').parse() {
+            Ok(ip) => vec![ip],
+            Err(_) => tokio::net::lookup_host((host, port))
+                .await
+                .map(|addresses| addresses.map(|address| address.ip()).collect())
+                .unwrap_or_default(),
+        };
+
+        if addresses.is_empty() {
+            return Err(ValidationError(String::from(
+                "url host could not be resolved",
+            )));
+        }
+        if !addresses.into_iter().all(|ip| self.allows(ip)) {
+            return Err(ValidationError(String::from(
+                "url must not point to a loopback, private or link-local address",
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Deliveries resolve hosts through the receivers as well, so a host that resolved to a public
+// address when the webhook was registered can't be pointed somewhere else later
+impl Resolve for Receivers {
+    fn resolve(&self, name: Name) -> Resolving {
+        let receivers = self.clone();
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|address| receivers.allows(address.ip()))
+                .collect();
+            if addresses.is_empty() {
+                return Err(format!("{} has no address webhooks may be sent to", name).into());
+            }
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // 0.0.0.0/8 reaches the local host, 100.64.0.0/10 is carrier-grade NAT
+                || first == 0
+                || (first == 100 && second & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+// Deliver events published on the hub to the webhooks subscribed to them, runs until the hub closes
+// or the shutdown starts. Deliveries still going then are waited for, the server's shutdown timeout
+// drops whatever is left
+pub async fn run(
+    client: db::Client,
+    hub: Hub,
+    settings: config::WebhookSettings,
+    shutdown: startup::Shutdown,
+) {
+    let receivers = Receivers::new(&settings);
+    let http = reqwest::Client::builder()
+        .timeout(Duration::from_secs(settings.timeout))
+        .dns_resolver(Arc::new(receivers.clone()))
+        // A redirect could send the payload anywhere, receivers have to answer themselves
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("Could not create webhook HTTP client");
+    let mut events = hub.subscribe();
+    let mut deliveries = JoinSet::new();
+
+    loop {
+        let received = tokio::select! {
+            received = events.recv() => received,
+            // Finished deliveries are cleared out so the set doesn't grow
+            Some(_) = deliveries.join_next() => continue,
+            _ = shutdown.triggered() => break,
+        };
+        match received {
+            // Only the instance that made a change delivers its webhooks
+            Ok(event) if event.source == config::EventSource::Local => {
+                dispatch(
+                    &client,
+                    &http,
+                    &receivers,
+                    &settings,
+                    &mut deliveries,
+                    event,
+                )
+                .await
+            }
+            Ok(_) => continue,
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::warn!(
+                    skipped,
+                    "Webhook worker fell behind, events were not delivered"
+                );
+                metrics::skip_webhook_events(skipped);
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+
+    if !deliveries.is_empty() {
+        tracing::info!(
+            deliveries = deliveries.len(),
+            "Waiting for webhook deliveries to finish"
+        );
+    }
+    while deliveries.join_next().await.is_some() {}
+}
+
+async fn dispatch(
+    client: &db::Client,
+    http: &reqwest::Client,
+    receivers: &Receivers,
+    settings: &config::WebhookSettings,
+    deliveries: &mut JoinSet<()>,
+    event: Event,
+) {
+    let webhook_event = match data::WebhookEvent::from_event(&event.kind) {
+        Some(webhook_event) => webhook_event,
+        None => return,
+    };
+
+    let session = data::Session::from(event.session);
+    let webhooks = match db::get_subscribed_webhooks(client, &session, webhook_event).await {
+        Ok(webhooks) => webhooks,
+        Err(error) => {
+            tracing::warn!(error = ?error, "Could not look up webhooks for event");
+            return;
+        }
+    };
+
+    // Deliver in the background so a slow receiver doesn't hold up other webhooks
+    for webhook in webhooks {
+        deliveries.spawn(deliver(
+            client.clone(),
+            http.clone(),
+            receivers.clone(),
+            settings.clone(),
+            webhook,
+            webhook_event,
+            event.clone(),
+        ));
+    }
+}
+
+// Deliver an event to a webhook, retrying with exponential backoff and logging every attempt
+async fn deliver(
+    client: db::Client,
+    http: reqwest::Client,
+    receivers: Receivers,
+    settings: config::WebhookSettings,
+    webhook: data::Webhook,
+    webhook_event: data::WebhookEvent,
+    event: Event,
+) {
+    let mut delivery = data::Delivery::new(&webhook, webhook_event);
+    let body = match serde_json::to_vec(&Payload {
+        delivery: delivery.id,
+        event: webhook_event,
+        timestamp: event.timestamp,
+        data: &event.kind,
+    }) {
+        Ok(body) => body,
+        Err(error) => {
+            tracing::warn!(error = ?error, "Could not serialize webhook payload");
+            return;
+        }
+    };
+
+    // Webhooks registered with an address that is no longer allowed are not sent anything
+    if let Err(error) = receivers.check(&webhook.url).await {
+        delivery.state = data::DeliveryState::Failed;
+        delivery.error = Some(error.to_string());
+        if let Err(error) = db::save_delivery(&client, &delivery).await {
+            tracing::warn!(error = ?error, "Could not log webhook delivery");
+        }
+        return;
+    }
+
+    let max_backoff = Duration::from_millis(settings.max_backoff);
+    let mut backoff = Duration::from_millis(settings.initial_backoff).min(max_backoff);
+    while delivery.attempts < settings.max_attempts {
+        if delivery.attempts > 0 {
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(max_backoff);
+        }
+        delivery.attempts += 1;
+
+        let timestamp = Utc::now().timestamp().to_string();
+        let result = http
+            .post(&webhook.url)
+            .header("content-type", "application/json")
+            .header(TIMESTAMP_HEADER, &timestamp)
+            .header(SIGNATURE_HEADER, sign(&webhook.secret, &timestamp, &body))
+            .body(body.clone())
+            .send()
+            .await;
+
+        match result {
+            Ok(response) => {
+                delivery.status = Some(response.status().as_u16());
+                delivery.error = None;
+                if response.status().is_success() {
+                    delivery.state = data::DeliveryState::Succeeded;
+                }
+            }
+            Err(error) => {
+                delivery.status = None;
+                delivery.error = Some(error.to_string());
+            }
+        }
+        if delivery.state != data::DeliveryState::Succeeded
+            && delivery.attempts >= settings.max_attempts
+        {
+            delivery.state = data::DeliveryState::Failed;
+        }
+        delivery.updated_at = Utc::now();
+
+        if let Err(error) = db::save_delivery(&client, &delivery).await {
+            tracing::warn!(error = ?error, "Could not log webhook delivery");
+        }
+        if delivery.state == data::DeliveryState::Succeeded {
+            tracing::debug!(attempts = delivery.attempts, "Delivered webhook");
+            return;
+        }
+    }
+    tracing::warn!(
+        attempts = delivery.attempts,
+        "Giving up on delivering webhook"
+    );
+}
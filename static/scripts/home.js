@@ -119,6 +119,39 @@ class Model {
             })
     }
 
+    // Listen for changes made by other tabs and clients. The session cookie is only set once the
+    // list has been read so this is opened after the first successful read
+    subscribe() {
+        if (this.socket) {
+            return;
+        }
+        let protocol = window.location.protocol === 'https:' ? 'wss://' : 'ws://';
+        this.socket = new WebSocket(protocol + window.location.host + API_ADDRESS + 'ws');
+        this.socket.onmessage = (message) => {
+            this.$event_pump.trigger('model_state_changed', []);
+        };
+        this.socket.onclose = (event) => {
+            this.socket = null;
+            setTimeout(() => this.subscribe(), 5000);
+        };
+    }
+
+    undo() {
+        let ajax_options = {
+            type: 'POST',
+            url: API_ADDRESS + 'undo',
+            accepts: 'application/json',
+            dataType: 'json'
+        };
+        $.ajax(ajax_options)
+            .done((data) => {
+                this.$event_pump.trigger('model_read_success', [data]);
+            })
+            .fail((xhr, textStatus, errorThrown) => {
+                console.log(errorThrown);
+            })
+    }
+
 }
 
 class View {
@@ -198,12 +231,18 @@ class Controller {
         $('#reset').click((e) => {
             this.model.clearAllTodos()
         })
+
+        // Reverting the last change
+        $('#undo').click((e) => {
+            this.model.undo()
+        })
     }
 
     initialize_model_events() {
         // Handle the model events
         this.$event_pump.on('model_read_success', (e, data) => {
             this.view.build_table(data);
+            this.model.subscribe();
         });
 
         // Handle the model events
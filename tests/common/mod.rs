@@ -1,7 +1,42 @@
-#[cfg(test)]
+// Each test file uses its own part of this module
+#![allow(dead_code)]
+
 use std::net::SocketAddr;
-use tokio;
-use warp_crud::{config, error::Result, startup};
+use warp_crud::{config, error::Result, events, listen, readiness, routes, startup};
+
+// A database that isn't there, for requests that shouldn't reach one. Operations on it fail quickly
+pub const NO_DATABASE: &str = "mongodb://127.0.0.1:9/?serverSelectionTimeoutMS=100";
+
+// The settings of the test environment
+pub fn settings() -> config::Settings {
+    // Set the environment so the right config is loaded
+    std::env::set_var("RUN_ENV", "Test");
+    config::Settings::new().unwrap()
+}
+
+// The routes of the app backed by no database, for requests sent with warp::test
+pub async fn routes(
+    settings: &config::Settings,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    routes_with(
+        settings,
+        NO_DATABASE,
+        events::Hub::new(&settings.events),
+        readiness::Readiness::new(),
+    )
+    .await
+}
+
+// The routes of the app, with the parts tests look into passed in
+pub async fn routes_with(
+    settings: &config::Settings,
+    uri: &str,
+    hub: events::Hub,
+    readiness: readiness::Readiness,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static {
+    let client = mongodb::Client::with_uri_str(uri).await.unwrap();
+    routes::routes(client, hub, readiness, settings)
+}
 
 pub struct App {
     address: SocketAddr,
@@ -9,20 +44,34 @@ pub struct App {
 
 impl App {
     pub async fn launch(run_environment: Option<&str>) -> Result<App> {
-        let env = match run_environment {
-            Some(env) => env,
-            None => "Test",
-        };
+        let env = run_environment.unwrap_or("Test");
 
         // Set the environment so the right config is loaded
         std::env::set_var("RUN_ENV", env);
         let app_settings = config::Settings::new()?;
-        let (addr, server) = startup::run(app_settings).await?;
+        let (addresses, _shutdown, server) = startup::run(app_settings).await?;
         tokio::task::spawn(server);
-        Ok(App { address: addr })
+        let address = addresses
+            .into_iter()
+            .find_map(|address| match address {
+                listen::Address::Tcp(address) => Some(address),
+                _ => None,
+            })
+            .expect("The test configuration listens on TCP");
+        let app = App { address };
+
+        // The collections are set up in the background, tests expect them to be there
+        let ready = app.route("/health/ready");
+        for _ in 0..300 {
+            match reqwest::get(&ready).await {
+                Ok(resp) if resp.status().is_success() => return Ok(app),
+                _ => tokio::time::sleep(std::time::Duration::from_millis(100)).await,
+            }
+        }
+        panic!("The app did not become ready");
     }
 
-    pub fn route(self, endpoint: &str) -> String {
+    pub fn route(&self, endpoint: &str) -> String {
         format!(
             "http://{}:{}{}",
             self.address.ip(),
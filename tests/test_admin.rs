@@ -0,0 +1,73 @@
+mod common;
+use serde_json::{json, Value};
+use warp_crud::{config, telemetry};
+
+async fn launch(
+    token: Option<&str>,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let mut settings = common::settings();
+    settings.admin.token = token.map(String::from);
+    common::routes(&settings).await
+}
+
+#[tokio::test]
+async fn test_admin_requires_token() {
+    // Without a configured token nobody gets in
+    let routes = launch(None).await;
+    let resp = warp::test::request()
+        .path("/admin/log")
+        .header("authorization", "Bearer ")
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 401);
+    assert_eq!(resp.headers()["www-authenticate"], "Bearer");
+
+    let routes = launch(Some("the-trooper")).await;
+    for authorization in [None, Some("Bearer aces-high"), Some("the-trooper")] {
+        let mut request = warp::test::request().path("/admin/log");
+        if let Some(authorization) = authorization {
+            request = request.header("authorization", authorization);
+        }
+        let resp = request.reply(&routes).await;
+        assert_eq!(resp.status(), 401, "{:?}", authorization);
+        assert_eq!(resp.headers()["cache-control"], "no-store");
+    }
+}
+
+#[tokio::test]
+async fn test_log_filter_can_be_changed() {
+    let mut settings = config::Settings::new().unwrap();
+    settings.log.rules = vec![String::from("info")];
+    let _telemetry = telemetry::init(&settings).unwrap();
+    let routes = launch(Some("the-trooper")).await;
+
+    let resp = warp::test::request()
+        .path("/admin/log")
+        .header("authorization", "Bearer the-trooper")
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 200);
+    let body: Value = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(body, json!({"filter": "info"}));
+
+    let resp = warp::test::request()
+        .method("PUT")
+        .path("/admin/log")
+        .header("authorization", "Bearer the-trooper")
+        .json(&json!({"filter": "warn,warp_crud=debug"}))
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(telemetry::log_filter().unwrap(), "warp_crud=debug,warn");
+
+    // Directives that don't parse leave the filter as it was
+    let resp = warp::test::request()
+        .method("PUT")
+        .path("/admin/log")
+        .header("authorization", "Bearer the-trooper")
+        .json(&json!({"filter": "warp_crud=loud"}))
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 400);
+    assert_eq!(telemetry::log_filter().unwrap(), "warp_crud=debug,warn");
+}
@@ -0,0 +1,128 @@
+mod common;
+use warp_crud::{config, data};
+
+#[tokio::test]
+async fn test_versions_are_current_by_default() {
+    let routes = common::routes(&common::settings()).await;
+    let cookie = format!("session={}", data::Session::new().id().to_simple());
+
+    for path in [
+        "/api/v1/todos/history",
+        "/api/todos/history",
+        "/api/v2/todos",
+    ] {
+        let resp = warp::test::request()
+            .path(path)
+            .header("cookie", &cookie)
+            .reply(&routes)
+            .await;
+        assert!(resp.headers().get("deprecation").is_none());
+        assert!(resp.headers().get("link").is_none());
+    }
+}
+
+#[tokio::test]
+async fn test_deprecated_versions_send_lifecycle_headers() {
+    let mut settings = common::settings();
+    settings.api.versions.insert(
+        "v1".to_owned(),
+        config::VersionSettings {
+            deprecated: Some("2021-07-01T00:00:00Z".parse().unwrap()),
+            sunset: None,
+            successor: Some("/api/v2".to_owned()),
+        },
+    );
+    let routes = common::routes(&settings).await;
+    let cookie = format!("session={}", data::Session::new().id().to_simple());
+
+    // The unversioned alias is version 1, and shares its lifecycle
+    for path in ["/api/v1/todos/history", "/api/todos/history"] {
+        let resp = warp::test::request()
+            .path(path)
+            .header("cookie", &cookie)
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.headers()["deprecation"], "@1625097600");
+        assert_eq!(
+            resp.headers()["link"],
+            "</api/v2>; rel=\"successor-version\""
+        );
+    }
+
+    let resp = warp::test::request()
+        .path("/api/v2/todos")
+        .header("cookie", &cookie)
+        .reply(&routes)
+        .await;
+    assert!(resp.headers().get("deprecation").is_none());
+}
+
+#[tokio::test]
+async fn test_todos_created_in_v2_are_visible_in_v1() {
+    //spawn the app so the server is running
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+
+    // Run a get reqest to the app so we get a session cookie back
+    let resp = client
+        .get(app.route("/api/v2/todos"))
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+    assert!(resp.status().is_success());
+
+    let resp = client
+        .post(app.route("/api/v2/todos"))
+        .json(&data::v2::TodoRequest {
+            title: "Run To The Hills!".to_owned(),
+            due: None,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::CREATED);
+    let created = resp.json::<data::v2::Todo>().await.unwrap();
+    assert_eq!(created.title, "Run To The Hills!");
+
+    let resp = client.get(app.route("/api/v1/todos")).send().await.unwrap();
+    let todos = resp.json::<Vec<data::Todo>>().await.unwrap();
+    let todo = todos.iter().find(|todo| todo.id == created.id).unwrap();
+    assert_eq!(todo.name, "Run To The Hills!");
+}
+
+#[tokio::test]
+async fn test_updates_keep_the_creation_time() {
+    //spawn the app so the server is running
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+
+    // Run a get reqest to the app so we get a session cookie back
+    let resp = client
+        .get(app.route("/api/v2/todos"))
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+    let created = resp.json::<Vec<data::v2::Todo>>().await.unwrap().remove(0);
+
+    let resp = client
+        .put(app.route(&format!("/api/v2/todos/{}", created.id)))
+        .json(&data::v2::TodoRequest {
+            title: "Renamed".to_owned(),
+            due: None,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+
+    let resp = client.get(app.route("/api/v2/todos")).send().await.unwrap();
+    let todos = resp.json::<Vec<data::v2::Todo>>().await.unwrap();
+    assert_eq!(todos[0].title, "Renamed");
+    assert_eq!(todos[0].created_at, created.created_at);
+}
@@ -0,0 +1,128 @@
+mod common;
+use serde_json::json;
+use warp_crud::data;
+
+#[tokio::test]
+async fn test_batch_applies_all_operations() {
+    //spawn the app so the server is running
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let endpoint = app.route("/api/todos");
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+
+    // Run a get reqest to the app so we get a session cookie back
+    let resp = client
+        .get(&endpoint)
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+    let todo_id = resp.json::<Vec<data::Todo>>().await.unwrap()[0].id;
+
+    // Complete the default todo, create two new ones and delete the default one
+    let operations = json!([
+        {"op": "complete", "id": todo_id},
+        {"op": "create", "name": "Run To The Hills!"},
+        {"op": "create", "name": "Run For Your Lives!"},
+        {"op": "delete", "id": todo_id},
+    ]);
+    let resp = client
+        .post(format!("{}/batch", endpoint))
+        .json(&operations)
+        .send()
+        .await
+        .unwrap();
+
+    // Verify we got a success and every operation reported back
+    assert!(resp.status().is_success());
+    let body = resp.json::<data::BatchResponse>().await.unwrap();
+    assert!(body.committed);
+    assert_eq!(body.results.len(), 4);
+    assert!(body
+        .results
+        .iter()
+        .all(|result| result.status == data::BatchStatus::Ok));
+    assert!(body.results[0].todo.as_ref().unwrap().completed);
+
+    // Verify the list reflects the batch
+    let resp = client
+        .get(&endpoint)
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+    let body = resp.json::<Vec<data::Todo>>().await.unwrap();
+    assert_eq!(body.len(), 2);
+    assert_eq!(body[0].name, "Run To The Hills!");
+    assert_eq!(body[1].name, "Run For Your Lives!");
+}
+
+#[tokio::test]
+async fn test_failed_batch_is_not_applied() {
+    //spawn the app so the server is running
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let endpoint = app.route("/api/todos");
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+
+    // Run a get reqest to the app so we get a session cookie back
+    client
+        .get(&endpoint)
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+
+    // The update targets a todo that does not exist so the whole batch should be rejected
+    let operations = json!([
+        {"op": "create", "name": "Run To The Hills!"},
+        {"op": "update", "id": uuid::Uuid::new_v4(), "name": "Nope"},
+        {"op": "create", "name": "Run For Your Lives!"},
+    ]);
+    let resp = client
+        .post(format!("{}/batch", endpoint))
+        .json(&operations)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(resp.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    let body = resp.json::<data::BatchResponse>().await.unwrap();
+    assert!(!body.committed);
+    assert_eq!(body.results[0].status, data::BatchStatus::Ok);
+    assert_eq!(body.results[1].status, data::BatchStatus::Failed);
+    assert_eq!(body.results[2].status, data::BatchStatus::Skipped);
+
+    // Verify that the list was left untouched
+    let resp = client
+        .get(&endpoint)
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+    let body = resp.json::<Vec<data::Todo>>().await.unwrap();
+    assert_eq!(body.len(), 1);
+    assert_eq!(body[0].name, "Delete This Todo");
+}
+
+#[test]
+fn test_batch_update_keeps_due_date_unless_cleared() {
+    let due = chrono::Utc::now();
+    let mut todos = vec![data::Todo {
+        due: Some(due),
+        ..data::Todo::from("Due")
+    }];
+    let id = todos[0].id;
+
+    let update =
+        |body: serde_json::Value| -> data::BatchOperation { serde_json::from_value(body).unwrap() };
+    update(json!({"op": "update", "id": id, "name": "Renamed"}))
+        .apply(&mut todos)
+        .unwrap();
+    assert_eq!(todos[0].due, Some(due));
+
+    update(json!({"op": "update", "id": id, "name": "Renamed", "due": null}))
+        .apply(&mut todos)
+        .unwrap();
+    assert_eq!(todos[0].due, None);
+}
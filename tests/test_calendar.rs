@@ -0,0 +1,154 @@
+mod common;
+use chrono::prelude::*;
+use warp_crud::{data, formats};
+
+#[test]
+fn test_ical_round_trip() {
+    let mut todo = data::Todo::from("Run To The Hills, then; rest");
+    todo.created_at = Utc.with_ymd_and_hms(2021, 5, 29, 9, 0, 0).unwrap();
+    todo.timestamp = Utc.with_ymd_and_hms(2021, 5, 30, 8, 30, 0).unwrap();
+    todo.due = Some(Utc.with_ymd_and_hms(2021, 6, 1, 12, 0, 0).unwrap());
+    todo.completed = true;
+    todo.completed_at = Some(Utc.with_ymd_and_hms(2021, 5, 30, 8, 30, 0).unwrap());
+    let export = formats::Export {
+        todos: vec![todo.clone()],
+        trash: Vec::new(),
+    };
+
+    let body = formats::export(&export, formats::Format::Ical).unwrap();
+    let text = std::str::from_utf8(&body).unwrap();
+    assert!(text.contains("BEGIN:VTODO\r\n"));
+    assert!(text.contains("STATUS:COMPLETED\r\n"));
+    assert!(text.contains("DUE:20210601T120000Z\r\n"));
+    assert!(text.contains("CREATED:20210529T090000Z\r\n"));
+    assert!(text.contains("LAST-MODIFIED:20210530T083000Z\r\n"));
+
+    let import = formats::import(&body, formats::Format::Ical).unwrap();
+    assert!(import.rejected.is_empty());
+    let imported = &import.todos[0].todo;
+    assert_eq!(imported.id, todo.id);
+    assert_eq!(imported.name, todo.name);
+    assert_eq!(imported.due, todo.due);
+    assert_eq!(imported.completed_at, todo.completed_at);
+    assert_eq!(imported.created_at, todo.created_at);
+    assert_eq!(imported.timestamp, todo.timestamp);
+    assert!(imported.completed);
+}
+
+#[test]
+fn test_ical_import_from_other_clients() {
+    let body = "BEGIN:VCALENDAR\r\n\
+                VERSION:2.0\r\n\
+                BEGIN:VTODO\r\n\
+                UID:20210601-1@example.com\r\n\
+                SUMMARY:A todo with a summary that is long enough that it had to be fo\r\n \
+                lded\r\n\
+                DUE;VALUE=DATE:20210601\r\n\
+                BEGIN:VALARM\r\n\
+                SUMMARY:Not the todo\r\n\
+                END:VALARM\r\n\
+                END:VTODO\r\n\
+                BEGIN:VTODO\r\n\
+                SUMMARY:Broken\r\n\
+                DUE:tomorrow\r\n\
+                END:VTODO\r\n\
+                END:VCALENDAR\r\n";
+
+    let import = formats::import(body.as_bytes(), formats::Format::Ical).unwrap();
+    assert_eq!(import.todos.len(), 1);
+    let todo = &import.todos[0].todo;
+    assert_eq!(
+        todo.name,
+        "A todo with a summary that is long enough that it had to be folded"
+    );
+    assert_eq!(
+        todo.due,
+        Some(Utc.with_ymd_and_hms(2021, 6, 1, 0, 0, 0).unwrap())
+    );
+    assert_eq!(import.rejected[0].row, 12);
+
+    // The same UID always maps to the same todo
+    let again = formats::import(body.as_bytes(), formats::Format::Ical).unwrap();
+    assert_eq!(again.todos[0].todo.id, todo.id);
+}
+
+#[test]
+fn test_ical_line_breaks_and_time_zones() {
+    // Carriage returns can't end up in a content line, where they would break it in two
+    let export = formats::Export {
+        todos: vec![data::Todo::from("Run To\r\nThe\rHills!")],
+        trash: Vec::new(),
+    };
+    let body = formats::export(&export, formats::Format::Ical).unwrap();
+    let text = std::str::from_utf8(&body).unwrap();
+    assert!(text.contains("SUMMARY:Run To\\nThe\\nHills!\r\n"));
+
+    // Times in a time zone other than UTC are refused instead of being read as UTC
+    let body = "BEGIN:VCALENDAR\r\n\
+                BEGIN:VTODO\r\n\
+                SUMMARY:Aces High\r\n\
+                DUE;TZID=Etc/UTC:20210601T120000\r\n\
+                END:VTODO\r\n\
+                BEGIN:VTODO\r\n\
+                SUMMARY:Run To The Hills!\r\n\
+                DUE;TZID=Europe/London:20210601T120000\r\n\
+                END:VTODO\r\n\
+                END:VCALENDAR\r\n";
+    let import = formats::import(body.as_bytes(), formats::Format::Ical).unwrap();
+    assert_eq!(import.todos.len(), 1);
+    assert_eq!(
+        import.todos[0].todo.due,
+        Some(Utc.with_ymd_and_hms(2021, 6, 1, 12, 0, 0).unwrap())
+    );
+    assert_eq!(import.rejected[0].row, 6);
+    assert!(import.rejected[0].reason.contains("Europe/London"));
+}
+
+#[tokio::test]
+async fn test_calendar_feed_requires_token() {
+    //spawn the app so the server is running
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let endpoint = app.route("/api/todos");
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+
+    // Run a get reqest to the app so we get a session cookie back
+    client
+        .get(&endpoint)
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+
+    let resp = client
+        .post(format!("{}/calendar", endpoint))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::CREATED);
+    let feed = resp.json::<data::CalendarFeed>().await.unwrap();
+
+    // Calendar clients don't send the session cookie, the token alone grants access
+    let resp = reqwest::get(app.route(&feed.path)).await.unwrap();
+    assert!(resp.status().is_success());
+    assert!(resp
+        .text()
+        .await
+        .unwrap()
+        .contains("SUMMARY:Delete This Todo"));
+
+    let resp = reqwest::get(app.route("/api/todos/calendar/not-a-token.ics"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+
+    // Once revoked the feed is gone
+    client
+        .delete(format!("{}/calendar", endpoint))
+        .send()
+        .await
+        .unwrap();
+    let resp = reqwest::get(app.route(&feed.path)).await.unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+}
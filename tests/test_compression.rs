@@ -0,0 +1,103 @@
+mod common;
+use std::io::Read;
+
+#[tokio::test]
+async fn test_static_files_are_compressed() {
+    let routes = common::routes(&common::settings()).await;
+    let css = std::fs::read("static/css/bootstrap.min.css").unwrap();
+
+    let resp = warp::test::request()
+        .path("/css/bootstrap.min.css")
+        .header("accept-encoding", "gzip, deflate")
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["content-encoding"], "gzip");
+    assert_eq!(resp.headers()["vary"], "accept-encoding");
+    let mut body = Vec::new();
+    flate2::read::GzDecoder::new(resp.body().as_ref())
+        .read_to_end(&mut body)
+        .unwrap();
+    assert_eq!(body, css);
+
+    let resp = warp::test::request()
+        .path("/css/bootstrap.min.css")
+        .header("accept-encoding", "gzip;q=0.5, br")
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.headers()["content-encoding"], "br");
+    let mut body = Vec::new();
+    brotli::Decompressor::new(resp.body().as_ref(), 4096)
+        .read_to_end(&mut body)
+        .unwrap();
+    assert_eq!(body, css);
+
+    // Without Accept-Encoding, and below the threshold, files are sent as they are
+    let resp = warp::test::request()
+        .path("/css/bootstrap.min.css")
+        .reply(&routes)
+        .await;
+    assert!(!resp.headers().contains_key("content-encoding"));
+    assert_eq!(resp.body().as_ref(), css.as_slice());
+
+    let resp = warp::test::request()
+        .path("/css/custom.css")
+        .header("accept-encoding", "gzip, br")
+        .reply(&routes)
+        .await;
+    assert!(!resp.headers().contains_key("content-encoding"));
+}
+
+#[tokio::test]
+async fn test_replies_without_content_length_are_compressed() {
+    let routes = common::routes(&common::settings()).await;
+
+    // JSON replies don't carry a Content-Length, their bodies still know how long they are
+    let resp = warp::test::request()
+        .path("/api/openapi.json")
+        .header("accept-encoding", "gzip")
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["content-encoding"], "gzip");
+    let mut body = Vec::new();
+    flate2::read::GzDecoder::new(resp.body().as_ref())
+        .read_to_end(&mut body)
+        .unwrap();
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+        warp_crud::openapi::spec()
+    );
+}
+
+#[tokio::test]
+async fn test_static_files_are_cached() {
+    let routes = common::routes(&common::settings()).await;
+
+    let resp = warp::test::request()
+        .path("/css/custom.css")
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["cache-control"], "public, max-age=604800");
+    let etag = resp.headers()["etag"].clone();
+
+    let resp = warp::test::request()
+        .path("/css/custom.css")
+        .header("if-none-match", etag.to_str().unwrap())
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 304);
+    assert!(resp.body().is_empty());
+
+    let resp = warp::test::request()
+        .path("/css/custom.css")
+        .header("if-none-match", "\"stale\"")
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 200);
+
+    // Pages are revalidated on every load
+    let resp = warp::test::request().path("/").reply(&routes).await;
+    assert_eq!(resp.headers()["cache-control"], "no-cache");
+}
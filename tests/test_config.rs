@@ -0,0 +1,33 @@
+use warp_crud::config;
+
+#[test]
+fn test_values_that_would_panic_are_refused() {
+    // Set the environment so the right config is loaded
+    std::env::set_var("RUN_ENV", "Test");
+    assert!(config::Settings::new().is_ok());
+
+    std::env::set_var("EA_TRASH__PURGE_INTERVAL", "0");
+    let error = config::Settings::new().unwrap_err();
+    assert!(error.to_string().contains("trash.purge_interval"));
+    std::env::remove_var("EA_TRASH__PURGE_INTERVAL");
+
+    // The event hub can't be created without room for a single event
+    std::env::set_var("EA_EVENTS__CAPACITY", "0");
+    let error = config::Settings::new().unwrap_err();
+    assert!(error.to_string().contains("events.capacity"));
+    std::env::remove_var("EA_EVENTS__CAPACITY");
+}
+
+#[test]
+fn test_log_rules_can_still_be_a_list() {
+    // Configurations from before the log format could be set only list the rules
+    let settings: config::LoggerSettings =
+        serde_json::from_value(serde_json::json!(["info", "warp_crud=debug"])).unwrap();
+    assert_eq!(settings.rules, vec!["info", "warp_crud=debug"]);
+    assert_eq!(settings.format, config::LogFormat::Pretty);
+    assert!(settings.file.is_none());
+
+    let settings: config::LoggerSettings =
+        serde_json::from_value(serde_json::json!({"rules": ["info"], "format": "json"})).unwrap();
+    assert_eq!(settings.format, config::LogFormat::Json);
+}
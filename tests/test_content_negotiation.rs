@@ -0,0 +1,160 @@
+mod common;
+use warp_crud::data;
+
+#[tokio::test]
+async fn test_unsupported_bodies_are_refused() {
+    let routes = common::routes(&common::settings()).await;
+    let cookie = format!("session={}", data::Session::new().id().to_simple());
+
+    let resp = warp::test::request()
+        .method("POST")
+        .path("/api/v1/todos")
+        .header("cookie", &cookie)
+        .header("content-type", "text/plain")
+        .body("Run To The Hills!")
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 415);
+
+    let resp = warp::test::request()
+        .method("POST")
+        .path("/api/v1/todos")
+        .header("cookie", &cookie)
+        .header("content-type", "application/msgpack")
+        .body(vec![0xc1])
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 400);
+}
+
+#[tokio::test]
+async fn test_unacceptable_requests_are_refused_before_they_run() {
+    // There is no database, so only a request that never reached its handler can get a 406
+    let routes = common::routes(&common::settings()).await;
+    let cookie = format!("session={}", data::Session::new().id().to_simple());
+
+    let resp = warp::test::request()
+        .path("/api/v1/todos")
+        .header("accept", "text/html")
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 406);
+    assert!(resp.headers().get("set-cookie").is_none());
+
+    let resp = warp::test::request()
+        .method("POST")
+        .path("/api/v2/todos")
+        .header("cookie", &cookie)
+        .header("accept", "text/html")
+        .json(&data::v2::TodoRequest {
+            title: "Run To The Hills!".to_owned(),
+            due: None,
+        })
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 406);
+
+    // Unknown paths are still not found
+    let resp = warp::test::request()
+        .path("/api/v1/nothing")
+        .header("accept", "text/html")
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 404);
+}
+
+#[tokio::test]
+async fn test_unacceptable_posts_write_nothing() {
+    //spawn the app so the server is running
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let endpoint = app.route("/api/todos");
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+
+    // Run a get reqest to the app so we get a session cookie back
+    let resp = client
+        .get(&endpoint)
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+    let before = resp.json::<Vec<data::Todo>>().await.unwrap();
+
+    let resp = client
+        .post(&endpoint)
+        .header("accept", "text/html")
+        .json(&data::TodoRequest {
+            name: "Run To The Hills!".to_owned(),
+            due: None,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::NOT_ACCEPTABLE);
+
+    let resp = client.get(&endpoint).send().await.unwrap();
+    let after = resp.json::<Vec<data::Todo>>().await.unwrap();
+    assert_eq!(after.len(), before.len());
+    assert!(after.iter().all(|todo| todo.name != "Run To The Hills!"));
+}
+
+#[tokio::test]
+async fn test_binary_encodings_round_trip() {
+    //spawn the app so the server is running
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let endpoint = app.route("/api/todos");
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+
+    // Run a get reqest to the app so we get a session cookie back
+    client
+        .get(&endpoint)
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+
+    let request = data::TodoRequest {
+        name: "Run To The Hills!".to_owned(),
+        due: None,
+    };
+    let resp = client
+        .post(&endpoint)
+        .header("content-type", "application/msgpack")
+        .body(rmp_serde::to_vec_named(&request).unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+
+    let resp = client
+        .get(&endpoint)
+        .header("accept", "application/json;q=0.5, application/msgpack")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.headers()["content-type"], "application/msgpack");
+    let todos: Vec<data::Todo> = rmp_serde::from_slice(&resp.bytes().await.unwrap()).unwrap();
+    assert!(todos.iter().any(|todo| todo.name == "Run To The Hills!"));
+
+    let resp = client
+        .get(&endpoint)
+        .header("accept", "application/cbor")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.headers()["content-type"], "application/cbor");
+    let body = resp.bytes().await.unwrap();
+    let cbor: Vec<data::Todo> = ciborium::de::from_reader(body.as_ref()).unwrap();
+    assert_eq!(cbor.len(), todos.len());
+
+    let resp = client
+        .get(&endpoint)
+        .header("accept", "text/plain")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::NOT_ACCEPTABLE);
+}
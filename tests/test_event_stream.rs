@@ -0,0 +1,219 @@
+mod common;
+use futures::StreamExt;
+use warp_crud::{config, data, events, readiness};
+
+async fn launch() -> (
+    events::Hub,
+    impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone + 'static,
+) {
+    let settings = common::settings();
+    let hub = events::Hub::new(&settings.events);
+    let routes = common::routes_with(
+        &settings,
+        &settings.database.uri,
+        hub.clone(),
+        readiness::Readiness::new(),
+    )
+    .await;
+    (hub, routes)
+}
+
+#[tokio::test]
+async fn test_websocket_only_receives_own_session_events() {
+    let (hub, routes) = launch().await;
+    let session = data::Session::new();
+    let mut socket = warp::test::ws()
+        .path("/api/todos/ws")
+        .header("cookie", format!("session={}", session.id().to_simple()))
+        .handshake(routes)
+        .await
+        .expect("Could not open websocket");
+
+    // Publish an event for another session first, which should not be delivered
+    hub.publish(&data::Session::new(), events::EventKind::ListCleared);
+    hub.publish(
+        &session,
+        events::EventKind::TodoDeleted {
+            todo_id: *session.id(),
+        },
+    );
+
+    let message = socket.recv().await.unwrap();
+    let event: events::Event = serde_json::from_str(message.to_str().unwrap()).unwrap();
+    match event.kind {
+        events::EventKind::TodoDeleted { todo_id } => assert_eq!(&todo_id, session.id()),
+        kind => panic!("Received unexpected event {:?}", kind),
+    }
+
+    // The todo keeps the field name clients from before event ids read it by
+    let message: serde_json::Value = serde_json::from_str(message.to_str().unwrap()).unwrap();
+    assert_eq!(message["id"], session.id().to_string());
+    assert_eq!(message["event_id"], event.id);
+}
+
+#[tokio::test]
+async fn test_websocket_receives_created_todos() {
+    let (_, routes) = launch().await;
+
+    // Run a get reqest to the app so we get a session cookie back
+    let resp = warp::test::request()
+        .path("/api/todos")
+        .reply(&routes)
+        .await;
+    let cookie = resp.headers()["set-cookie"].to_str().unwrap().to_owned();
+
+    let mut socket = warp::test::ws()
+        .path("/api/todos/ws")
+        .header("cookie", &cookie)
+        .handshake(routes.clone())
+        .await
+        .expect("Could not open websocket");
+
+    let resp = warp::test::request()
+        .method("POST")
+        .path("/api/todos")
+        .header("cookie", &cookie)
+        .json(&data::TodoRequest {
+            name: "Run To The Hills!".to_owned(),
+            due: None,
+        })
+        .reply(&routes)
+        .await;
+    assert!(resp.status().is_success());
+
+    let message = socket.recv().await.unwrap();
+    let event: events::Event = serde_json::from_str(message.to_str().unwrap()).unwrap();
+    match event.kind {
+        events::EventKind::TodoCreated { todo } => assert_eq!(todo.name, "Run To The Hills!"),
+        kind => panic!("Received unexpected event {:?}", kind),
+    }
+}
+
+#[tokio::test]
+async fn test_changes_that_do_nothing_are_not_published() {
+    let (_, routes) = launch().await;
+
+    // Run a get reqest to the app so we get a session cookie back
+    let resp = warp::test::request()
+        .path("/api/todos")
+        .reply(&routes)
+        .await;
+    let cookie = resp.headers()["set-cookie"].to_str().unwrap().to_owned();
+
+    let mut socket = warp::test::ws()
+        .path("/api/todos/ws")
+        .header("cookie", &cookie)
+        .handshake(routes.clone())
+        .await
+        .expect("Could not open websocket");
+
+    // Deleting a todo that doesn't exist, or clearing a list twice, changes nothing
+    let resp = warp::test::request()
+        .method("DELETE")
+        .path(&format!("/api/todos/{}", uuid::Uuid::new_v4()))
+        .header("cookie", &cookie)
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 404);
+    for _ in 0..2 {
+        let resp = warp::test::request()
+            .method("DELETE")
+            .path("/api/todos")
+            .header("cookie", &cookie)
+            .reply(&routes)
+            .await;
+        assert!(resp.status().is_success());
+    }
+    let resp = warp::test::request()
+        .method("POST")
+        .path("/api/todos")
+        .header("cookie", &cookie)
+        .json(&data::TodoRequest {
+            name: "Run To The Hills!".to_owned(),
+            due: None,
+        })
+        .reply(&routes)
+        .await;
+    assert!(resp.status().is_success());
+
+    // Only the first clear and the new todo are published
+    let mut kinds = Vec::new();
+    for _ in 0..2 {
+        let message = socket.recv().await.unwrap();
+        let event: events::Event = serde_json::from_str(message.to_str().unwrap()).unwrap();
+        kinds.push(event.kind);
+    }
+    assert!(matches!(kinds[0], events::EventKind::ListCleared));
+    assert!(matches!(kinds[1], events::EventKind::TodoCreated { .. }));
+}
+
+fn event_settings(backlog: usize) -> config::EventSettings {
+    config::EventSettings {
+        capacity: 16,
+        backlog,
+        source: config::EventSource::Local,
+    }
+}
+
+#[tokio::test]
+async fn test_feed_replays_events_after_last_id() {
+    let hub = events::Hub::new(&event_settings(16));
+    let session = data::Session::new();
+    for _ in 0..3 {
+        hub.publish(&session, events::EventKind::ListCleared);
+    }
+
+    let feed = hub.feed(&session, Some(1));
+    futures::pin_mut!(feed);
+    assert_eq!(feed.next().await.unwrap().id, 2);
+    assert_eq!(feed.next().await.unwrap().id, 3);
+}
+
+#[tokio::test]
+async fn test_feed_resyncs_when_events_were_dropped() {
+    let hub = events::Hub::new(&event_settings(2));
+    let session = data::Session::new();
+    for _ in 0..4 {
+        hub.publish(&session, events::EventKind::ListCleared);
+    }
+
+    // Events 2 and 3 fell out of the backlog, so the client has to re-read its list
+    let feed = hub.feed(&session, Some(1));
+    futures::pin_mut!(feed);
+    assert!(matches!(
+        feed.next().await.unwrap().kind,
+        events::EventKind::Resync
+    ));
+    assert_eq!(feed.next().await.unwrap().id, 3);
+}
+
+#[tokio::test]
+async fn test_server_sent_events_resume_from_last_event_id() {
+    let (hub, routes) = launch().await;
+    let (address, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+
+    let session = data::Session::new();
+    hub.publish(&session, events::EventKind::ListCleared);
+    hub.publish(
+        &session,
+        events::EventKind::TodoDeleted {
+            todo_id: *session.id(),
+        },
+    );
+
+    // Resuming after the first event should replay the second one
+    let mut resp = reqwest::Client::new()
+        .get(format!("http://{}/api/todos/events", address))
+        .header("cookie", format!("session={}", session.id().to_simple()))
+        .header("last-event-id", "1")
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+
+    let chunk = resp.chunk().await.unwrap().unwrap();
+    let message = std::str::from_utf8(&chunk).unwrap();
+    assert!(message.contains("event:todo_deleted"));
+    assert!(message.contains("id:2"));
+}
@@ -0,0 +1,152 @@
+mod common;
+use warp_crud::{data, formats};
+
+#[test]
+fn test_csv_round_trip_keeps_trash() {
+    let mut completed = data::Todo::from("Run To The Hills!");
+    completed.completed = true;
+    completed.completed_at = Some(completed.timestamp);
+    let export = formats::Export {
+        todos: vec![completed.clone()],
+        trash: vec![data::TrashedTodo::from(data::Todo::from("Aces High")).into()],
+    };
+
+    let body = formats::export(&export, formats::Format::Csv).unwrap();
+    let import = formats::import(&body, formats::Format::Csv).unwrap();
+    assert!(import.rejected.is_empty());
+    assert_eq!(import.todos.len(), 1);
+    assert_eq!(import.todos[0].todo.id, completed.id);
+    assert!(import.todos[0].todo.completed);
+    assert_eq!(import.trash.len(), 1);
+    assert_eq!(import.trash[0].trashed.todo.name, "Aces High");
+}
+
+#[test]
+fn test_import_reports_rejected_rows() {
+    let body = "name,completed\nRun To The Hills!,false\n,false\nAces High,maybe\n";
+    let import = formats::import(body.as_bytes(), formats::Format::Csv).unwrap();
+    assert_eq!(import.todos.len(), 1);
+    let rows: Vec<usize> = import.rejected.iter().map(|row| row.row).collect();
+    assert_eq!(rows, vec![3, 4]);
+
+    let body = r#"[{"name": "Run To The Hills!"}, {"completed": true}]"#;
+    let import = formats::import(body.as_bytes(), formats::Format::Json).unwrap();
+    assert_eq!(import.todos.len(), 1);
+    assert_eq!(import.rejected[0].row, 2);
+}
+
+#[test]
+fn test_csv_export_quotes_formulas() {
+    let export = formats::Export {
+        todos: vec![data::Todo::from("=HYPERLINK(\"http://example.com\")")],
+        trash: Vec::new(),
+    };
+
+    let body = formats::export(&export, formats::Format::Csv).unwrap();
+    let body = String::from_utf8(body).unwrap();
+    assert!(body.contains("\"'=HYPERLINK("));
+
+    // The quote is dropped again on import
+    let import = formats::import(body.as_bytes(), formats::Format::Csv).unwrap();
+    assert_eq!(import.todos[0].todo.name, export.todos[0].name);
+
+    // Tabs and carriage returns start formulas too
+    for name in ["\tcmd", "\r=1"] {
+        let export = formats::Export {
+            todos: vec![data::Todo::from(name)],
+            trash: Vec::new(),
+        };
+        let body = formats::export(&export, formats::Format::Csv).unwrap();
+        assert!(String::from_utf8(body)
+            .unwrap()
+            .contains(&format!("'{}", name)));
+    }
+
+    // Names the user started with a quote keep it
+    let names = ["'quoted'", "'=1", "''+1", "plain"];
+    let export = formats::Export {
+        todos: names.iter().map(|name| data::Todo::from(*name)).collect(),
+        trash: Vec::new(),
+    };
+    let body = formats::export(&export, formats::Format::Csv).unwrap();
+    let body = String::from_utf8(body).unwrap();
+    assert!(body.contains(",'quoted',"));
+    let import = formats::import(body.as_bytes(), formats::Format::Csv).unwrap();
+    let imported: Vec<&str> = import
+        .todos
+        .iter()
+        .map(|row| row.todo.name.as_str())
+        .collect();
+    assert_eq!(imported, names);
+}
+
+#[test]
+fn test_import_caps_the_trash() {
+    let trash: Vec<data::DeletedTodo> = (0..=formats::MAX_IMPORTED_TRASH)
+        .map(|index| data::TrashedTodo::from(data::Todo::from(index.to_string().as_str())).into())
+        .collect();
+    let body = serde_json::to_vec(&formats::Export {
+        todos: Vec::new(),
+        trash,
+    })
+    .unwrap();
+    let import = formats::import(&body, formats::Format::Json).unwrap();
+
+    let mut todos = Vec::new();
+    let mut trash = Vec::new();
+    let report =
+        formats::ImportReport::execute(formats::ImportMode::Merge, &import, &mut todos, &mut trash);
+    assert_eq!(report.trashed, formats::MAX_IMPORTED_TRASH);
+    assert_eq!(report.rejected.len(), 1);
+    assert_eq!(trash.len(), formats::MAX_IMPORTED_TRASH);
+}
+
+#[tokio::test]
+async fn test_exported_list_can_replace_another() {
+    //spawn the app so the server is running
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let endpoint = app.route("/api/todos");
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+
+    // Run a get reqest to the app so we get a session cookie back
+    let resp = client
+        .get(&endpoint)
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+    let todos = resp.json::<Vec<data::Todo>>().await.unwrap();
+
+    let resp = client
+        .get(format!("{}/export?format=csv", endpoint))
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+    assert_eq!(resp.headers()["content-type"], "text/csv");
+    let export = resp.bytes().await.unwrap();
+
+    // Import the export into a fresh session, replacing its default list
+    let other = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+    other.get(&endpoint).send().await.unwrap();
+    let resp = other
+        .post(format!("{}/import?format=csv&mode=replace", endpoint))
+        .body(export)
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+    let report = resp.json::<formats::ImportReport>().await.unwrap();
+    assert_eq!(report.imported, todos.len());
+    assert!(report.rejected.is_empty());
+
+    let resp = other.get(&endpoint).send().await.unwrap();
+    let body = resp.json::<Vec<data::Todo>>().await.unwrap();
+    assert_eq!(body.len(), todos.len());
+    assert_eq!(body[0].id, todos[0].id);
+}
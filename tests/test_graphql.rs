@@ -0,0 +1,152 @@
+mod common;
+use serde_json::{json, Value};
+use warp_crud::data;
+
+#[tokio::test]
+async fn test_explorer_is_served() {
+    let routes = common::routes(&common::settings()).await;
+    let resp = warp::test::request().path("/graphql").reply(&routes).await;
+    assert_eq!(resp.status(), 200);
+    let page = std::str::from_utf8(resp.body()).unwrap();
+    assert!(page.contains("GraphQL Explorer"));
+
+    // Everything the page needs comes from the static assets
+    assert!(page.contains("src=\"/graphiql/graphiql@4/graphiql.min.js\""));
+    assert!(!page.contains("src=\"http"));
+    assert!(!page.contains("href=\"http"));
+}
+
+#[tokio::test]
+async fn test_graphql_requires_session() {
+    let routes = common::routes(&common::settings()).await;
+    let query = json!({"query": "{ __schema { queryType { name } } }"});
+
+    let resp = warp::test::request()
+        .method("POST")
+        .path("/graphql")
+        .json(&query)
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 400);
+
+    let resp = warp::test::request()
+        .method("POST")
+        .path("/graphql")
+        .header(
+            "cookie",
+            format!("session={}", data::Session::new().id().to_simple()),
+        )
+        .json(&query)
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 200);
+    let body: Value = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(body["data"]["__schema"]["queryType"]["name"], "Query");
+}
+
+#[tokio::test]
+async fn test_graphql_errors_carry_codes() {
+    let routes = common::routes(&common::settings()).await;
+    let resp = warp::test::request()
+        .method("POST")
+        .path("/graphql")
+        .header(
+            "cookie",
+            format!("session={}", data::Session::new().id().to_simple()),
+        )
+        .json(&json!({"query": "{ todo(id: \"not-a-uuid\") { name } }"}))
+        .reply(&routes)
+        .await;
+    let body: Value = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(body["errors"][0]["extensions"]["code"], "BAD_REQUEST");
+}
+
+#[tokio::test]
+async fn test_graphql_mutations_and_filtered_queries() {
+    //spawn the app so the server is running
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+
+    // Run a get reqest to the app so we get a session cookie back
+    client
+        .get(app.route("/api/todos"))
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+
+    let graphql = |query: Value| {
+        let request = client.post(app.route("/graphql")).json(&query);
+        async move { request.send().await.unwrap().json::<Value>().await.unwrap() }
+    };
+
+    let created = graphql(json!({
+        "query": "mutation($input: TodoInput!) { createTodo(input: $input) { id name } }",
+        "variables": {"input": {"name": "Run To The Hills!"}},
+    }))
+    .await;
+    let id = created["data"]["createTodo"]["id"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+
+    graphql(json!({
+        "query": "mutation($id: ID!) { completeTodo(id: $id) { completed } }",
+        "variables": {"id": id},
+    }))
+    .await;
+
+    let listed = graphql(json!({
+        "query": "{ list { todos(filter: {completed: true, search: \"hills\"}, first: 1) { \
+                    edges { node { id completed } } pageInfo { hasNextPage } } } }",
+    }))
+    .await;
+    let todos = &listed["data"]["list"]["todos"];
+    assert_eq!(todos["edges"].as_array().unwrap().len(), 1);
+    assert_eq!(todos["edges"][0]["node"]["id"], id.as_str());
+    assert_eq!(todos["edges"][0]["node"]["completed"], true);
+    assert_eq!(todos["pageInfo"]["hasNextPage"], false);
+}
+
+#[tokio::test]
+async fn test_updating_a_missing_todo_is_not_found() {
+    //spawn the app so the server is running
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+
+    // Run a get reqest to the app so we get a session cookie back
+    client
+        .get(app.route("/api/todos"))
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+
+    let body = client
+        .post(app.route("/graphql"))
+        .json(&json!({
+            "query": "mutation($id: ID!, $input: TodoInput!) { \
+                        updateTodo(id: $id, input: $input) { id } }",
+            "variables": {"id": uuid::Uuid::new_v4(), "input": {"name": "Aces High"}},
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json::<Value>()
+        .await
+        .unwrap();
+    assert_eq!(body["errors"][0]["extensions"]["code"], "NOT_FOUND");
+
+    // Nothing was changed, so nothing was recorded either
+    let resp = client
+        .get(app.route("/api/todos/history"))
+        .send()
+        .await
+        .unwrap();
+    let history = resp.json::<Vec<data::Change>>().await.unwrap();
+    assert!(history.is_empty());
+}
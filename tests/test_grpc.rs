@@ -0,0 +1,100 @@
+mod common;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Channel, Endpoint, Server};
+use tonic::{Code, Request};
+use warp_crud::grpc::proto::todos_client::TodosClient;
+use warp_crud::grpc::{self, proto};
+use warp_crud::{data, events};
+
+// Serve the gRPC service on its own, with a client connected to it
+async fn launch(uri: Option<&str>) -> TodosClient<Channel> {
+    let settings = common::settings();
+
+    // Without a uri point at a database that isn't there, none of the requests should reach it
+    let uri = uri.unwrap_or(common::NO_DATABASE);
+    let client = mongodb::Client::with_uri_str(uri).await.unwrap();
+    let service = grpc::service(client, events::Hub::new(&settings.events));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_service(service)
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+
+    let channel = Endpoint::from_shared(format!("http://{}", address))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    TodosClient::new(channel)
+}
+
+fn with_session<T>(message: T, session: &str) -> Request<T> {
+    let mut request = Request::new(message);
+    request
+        .metadata_mut()
+        .insert("session", session.parse().unwrap());
+    request
+}
+
+#[tokio::test]
+async fn test_grpc_requires_session() {
+    let mut client = launch(None).await;
+
+    let status = client.get_list(()).await.unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+
+    let status = client
+        .get_list(with_session((), "not-a-session"))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+
+    // A valid session gets through to validating the request
+    let session = data::Session::new().id().to_simple().to_string();
+    let status = client
+        .complete_todo(with_session(
+            proto::CompleteTodoRequest {
+                id: String::from("not-a-uuid"),
+                completed: true,
+            },
+            &session,
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn test_grpc_streams_change_events() {
+    let settings = common::settings();
+    let mut client = launch(Some(&settings.database.uri)).await;
+
+    let list = client.create_list(()).await.unwrap().into_inner();
+    let mut events = client
+        .watch_events(with_session(
+            proto::WatchEventsRequest { last_id: None },
+            &list.session,
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+
+    let todo = client
+        .create_todo(with_session(
+            proto::CreateTodoRequest {
+                name: String::from("Run To The Hills!"),
+                due: None,
+            },
+            &list.session,
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+
+    let event = events.message().await.unwrap().unwrap();
+    assert_eq!(event.r#type, "todo_created");
+    assert_eq!(event.todo.unwrap().id, todo.id);
+}
@@ -0,0 +1,83 @@
+mod common;
+use serde_json::Value;
+use warp_crud::{events, readiness};
+
+async fn launch(
+    readiness: readiness::Readiness,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // The database isn't there, so the readiness probe fails quickly
+    let settings = common::settings();
+    let hub = events::Hub::new(&settings.events);
+    common::routes_with(&settings, common::NO_DATABASE, hub, readiness).await
+}
+
+fn check<'a>(report: &'a Value, name: &str) -> &'a Value {
+    report["checks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|check| check["name"] == name)
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_liveness_ignores_dependencies() {
+    let routes = launch(readiness::Readiness::new()).await;
+    let resp = warp::test::request()
+        .path("/health/live")
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["cache-control"], "no-store");
+    let report: Value = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(report["status"], "pass");
+}
+
+#[tokio::test]
+async fn test_readiness_reports_each_check() {
+    let readiness = readiness::Readiness::new();
+    let routes = launch(readiness.clone()).await;
+
+    for path in ["/health/ready", "/health"] {
+        let resp = warp::test::request().path(path).reply(&routes).await;
+        assert_eq!(resp.status(), 503, "{}", path);
+        let report: Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(report["status"], "fail");
+
+        let database = check(&report, "database");
+        assert_eq!(database["status"], "fail");
+        assert!(database["latency_ms"].is_number());
+        assert!(database["error"].is_string());
+        assert_eq!(check(&report, "migrations")["status"], "fail");
+        assert_eq!(check(&report, "shutdown")["status"], "pass");
+    }
+
+    readiness.set_initialized();
+    readiness.start_draining();
+    let resp = warp::test::request()
+        .path("/health/ready")
+        .reply(&routes)
+        .await;
+    let report: Value = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(check(&report, "migrations")["status"], "pass");
+    assert_eq!(check(&report, "shutdown")["status"], "fail");
+
+    // Draining doesn't make the process look dead
+    let resp = warp::test::request()
+        .path("/health/live")
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 200);
+}
+
+#[tokio::test]
+async fn test_probes_pass_once_the_server_has_started() {
+    //spawn the app so the server is running
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let client = reqwest::Client::new();
+
+    for probe in ["/health/live", "/health/ready"] {
+        let resp = client.get(app.route(probe)).send().await.unwrap();
+        assert!(resp.status().is_success());
+    }
+}
@@ -0,0 +1,141 @@
+mod common;
+use warp_crud::data;
+
+#[tokio::test]
+async fn test_undo_and_redo_delete_all() {
+    //spawn the app so the server is running
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let endpoint = app.route("/api/todos");
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+
+    // Run a get reqest to the app so we get a session cookie back
+    client
+        .get(&endpoint)
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+
+    // Wipe the list, then undo it
+    let resp = client.delete(&endpoint).send().await.unwrap();
+    assert!(resp.status().is_success());
+
+    let resp = client
+        .post(format!("{}/undo", endpoint))
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+    let body = resp.json::<Vec<data::Todo>>().await.unwrap();
+    assert_eq!(body.len(), 1);
+    assert_eq!(body[0].name, "Delete This Todo");
+
+    // Redoing the change should wipe the list again
+    let resp = client
+        .post(format!("{}/redo", endpoint))
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+    let body = resp.json::<Vec<data::Todo>>().await.unwrap();
+    assert_eq!(body.len(), 0);
+
+    // Nothing is left to redo
+    let resp = client
+        .post(format!("{}/redo", endpoint))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_history_lists_changes() {
+    //spawn the app so the server is running
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let endpoint = app.route("/api/todos");
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+
+    // Run a get reqest to the app so we get a session cookie back
+    client
+        .get(&endpoint)
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+
+    let new_todo = data::TodoRequest {
+        name: "Run To The Hills!".to_owned(),
+        due: None,
+    };
+    client
+        .post(&endpoint)
+        .json::<data::TodoRequest>(&new_todo)
+        .send()
+        .await
+        .unwrap();
+    client.delete(&endpoint).send().await.unwrap();
+
+    // The most recent change comes first
+    let resp = client
+        .get(format!("{}/history", endpoint))
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+    let body = resp.json::<Vec<data::Change>>().await.unwrap();
+    assert_eq!(body.len(), 2);
+    assert_eq!(body[0].action, data::HistoryAction::DeleteAllTodos);
+    assert_eq!(body[1].action, data::HistoryAction::CreateTodo);
+}
+
+#[tokio::test]
+async fn test_changes_that_do_nothing_are_not_recorded() {
+    //spawn the app so the server is running
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let endpoint = app.route("/api/todos");
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+
+    // Run a get reqest to the app so we get a session cookie back
+    client
+        .get(&endpoint)
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+
+    // Deleting a todo that doesn't exist, or clearing a list twice, changes nothing
+    let resp = client
+        .delete(format!("{}/{}", endpoint, uuid::Uuid::new_v4()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+    client.delete(&endpoint).send().await.unwrap();
+    client.delete(&endpoint).send().await.unwrap();
+
+    let resp = client
+        .get(format!("{}/history", endpoint))
+        .send()
+        .await
+        .unwrap();
+    let body = resp.json::<Vec<data::Change>>().await.unwrap();
+    assert_eq!(body.len(), 1);
+    assert_eq!(body[0].action, data::HistoryAction::DeleteAllTodos);
+
+    // The limit is bounded by the number of changes that are kept
+    for limit in &["0", "-1", "51"] {
+        let resp = client
+            .get(format!("{}/history?limit={}", endpoint, limit))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,164 @@
+mod common;
+use warp_crud::{config, data, db};
+
+#[tokio::test]
+async fn test_retried_post_creates_one_todo() {
+    //spawn the app so the server is running
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let endpoint = app.route("/api/todos");
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+
+    // Run a get reqest to the app so we get a session cookie back
+    client
+        .get(&endpoint)
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+
+    let new_todo = data::TodoRequest {
+        name: "Run To The Hills!".to_owned(),
+        due: None,
+    };
+
+    // Send the same request twice, the second one should be replayed
+    for attempt in 0..2 {
+        let resp = client
+            .post(&endpoint)
+            .header("Idempotency-Key", "retry-me")
+            .json::<data::TodoRequest>(&new_todo)
+            .send()
+            .await
+            .unwrap();
+
+        assert!(resp.status().is_success());
+        assert_eq!(
+            resp.headers().contains_key("idempotent-replayed"),
+            attempt == 1
+        );
+    }
+
+    // Verify that only one todo was created
+    let resp = client
+        .get(&endpoint)
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+    let body = resp.json::<Vec<data::Todo>>().await.unwrap();
+    assert_eq!(body.len(), 2);
+}
+
+#[tokio::test]
+async fn test_reused_key_with_different_body_conflicts() {
+    //spawn the app so the server is running
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let endpoint = app.route("/api/todos");
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+
+    // Run a get reqest to the app so we get a session cookie back
+    client
+        .get(&endpoint)
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+
+    let resp = client
+        .post(&endpoint)
+        .header("Idempotency-Key", "reused")
+        .json(&data::TodoRequest {
+            name: "Run To The Hills!".to_owned(),
+            due: None,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+
+    // Reusing the key for a different todo is a client error
+    let resp = client
+        .post(&endpoint)
+        .header("Idempotency-Key", "reused")
+        .json(&data::TodoRequest {
+            name: "Run For Your Lives!".to_owned(),
+            due: None,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn test_abandoned_claim_can_be_retried() {
+    //spawn the app so the server is running
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let endpoint = app.route("/api/todos");
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+
+    // Run a get reqest to the app so we get a session cookie back
+    let resp = client
+        .get(&endpoint)
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+    let session: uuid::Uuid = resp
+        .cookies()
+        .find(|cookie| cookie.name() == "session")
+        .unwrap()
+        .value()
+        .parse()
+        .unwrap();
+
+    // Claim the key the way a request that crashed before answering would have left it
+    let settings = config::Settings::new().unwrap();
+    let database = mongodb::Client::with_uri_str(&settings.database.uri)
+        .await
+        .unwrap();
+    let mut record = data::IdempotencyRecord::new(
+        &data::Session::from(session),
+        "crashed".to_owned(),
+        "unknown".to_owned(),
+        &settings.idempotency,
+    );
+    db::claim_idempotency_key(&database, &record).await.unwrap();
+
+    let retry = || {
+        client
+            .post(&endpoint)
+            .header("Idempotency-Key", "crashed")
+            .json(&data::TodoRequest {
+                name: "Run To The Hills!".to_owned(),
+                due: None,
+            })
+            .send()
+    };
+
+    // While the lease lasts the first request might still finish
+    let resp = retry().await.unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::CONFLICT);
+
+    // Once it has run out the retry takes the key over
+    record.claim = uuid::Uuid::new_v4();
+    record.locked_until = chrono::Utc::now() - chrono::Duration::seconds(1);
+    database
+        .database("warp_crud")
+        .collection::<data::IdempotencyRecord>("idempotency")
+        .replace_one(
+            mongodb::bson::doc! {"session.id": db::uuid_to_bson(&session).unwrap(), "key": "crashed"},
+            &record,
+            None,
+        )
+        .await
+        .unwrap();
+    let resp = retry().await.unwrap();
+    assert!(resp.status().is_success());
+    assert!(!resp.headers().contains_key("idempotent-replayed"));
+}
@@ -0,0 +1,108 @@
+use warp_crud::error::Error;
+use warp_crud::{config, listen};
+
+fn settings(listen: &[&str]) -> config::ServerSettings {
+    // Set the environment so the right config is loaded
+    std::env::set_var("RUN_ENV", "Test");
+    let mut settings = config::Settings::new().unwrap().server;
+    settings.listen = listen.iter().map(|target| String::from(*target)).collect();
+    settings
+}
+
+fn addresses(listeners: &[listen::Listener]) -> Vec<listen::Address> {
+    listeners
+        .iter()
+        .map(|listener| listener.address().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_listens_on_every_target() {
+    let directory = tempfile::tempdir().unwrap();
+    let socket = directory
+        .path()
+        .join("warp_crud.sock")
+        .display()
+        .to_string();
+
+    let listeners = listen::bind(&settings(&["127.0.0.1:0", &format!("unix:{}", socket)]))
+        .await
+        .unwrap();
+    let addresses = addresses(&listeners);
+    assert_eq!(addresses.len(), 2);
+    assert!(matches!(addresses[0], listen::Address::Tcp(address) if address.is_ipv4()));
+    assert_eq!(addresses[1], listen::Address::Unix(socket.clone()));
+    assert_eq!(addresses[1].to_string(), format!("unix:{}", socket));
+
+    // The socket left behind by the listener is replaced on the next start
+    drop(listeners);
+    let listeners = listen::bind(&settings(&[&format!("unix:{}", socket)]))
+        .await
+        .unwrap();
+    assert_eq!(listeners.len(), 1);
+    tokio::net::UnixStream::connect(&socket).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_address_without_targets() {
+    // The address and application port are used when there are no targets, IPv6 included
+    let mut settings = settings(&[]);
+    settings.address = String::from("::1");
+    settings.application_port = 0;
+    match listen::bind(&settings).await {
+        Ok(listeners) => {
+            assert!(matches!(
+                addresses(&listeners)[0],
+                listen::Address::Tcp(address) if address.is_ipv6()
+            ));
+        }
+        // Hosts without IPv6 can't bind it, which is an error rather than a panic
+        Err(error) => assert!(matches!(error, Error::ListenError { .. })),
+    }
+    assert_eq!(listen::socket_target("::1", 3030), "[::1]:3030");
+    assert_eq!(listen::socket_target("[::1]", 3030), "[::1]:3030");
+    assert_eq!(listen::socket_target("localhost", 3030), "localhost:3030");
+}
+
+#[tokio::test]
+async fn test_invalid_targets_are_errors() {
+    for target in [
+        "not an address",
+        "127.0.0.1",
+        "unix:/nonexistent/warp_crud.sock",
+    ] {
+        match listen::bind(&settings(&[target])).await {
+            Err(Error::ListenError { address, .. }) => assert_eq!(address, target),
+            other => panic!("{} gave {:?}", target, other.map(|_| ())),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_grpc_listens_next_to_http() {
+    let directory = tempfile::tempdir().unwrap();
+    let socket = directory
+        .path()
+        .join("warp_crud.sock")
+        .display()
+        .to_string();
+
+    // One gRPC listener for each IP address HTTP is served on, whatever the HTTP ports are
+    let settings = settings(&[]);
+    let http = [
+        listen::Address::Tcp("127.0.0.1:3030".parse().unwrap()),
+        listen::Address::Tcp("127.0.0.1:3031".parse().unwrap()),
+        listen::Address::Unix(socket),
+    ];
+    let listeners = listen::bind_grpc(&settings, &http).await.unwrap();
+    assert_eq!(listeners.len(), 1);
+    let address = listeners[0].local_addr().unwrap();
+    assert_eq!(address.ip().to_string(), "127.0.0.1");
+    assert_ne!(address.port(), 3030);
+
+    // With only Unix sockets, gRPC falls back to the configured address
+    let http = [listen::Address::Unix(String::from("/tmp/warp_crud.sock"))];
+    let listeners = listen::bind_grpc(&settings, &http).await.unwrap();
+    assert_eq!(listeners.len(), 1);
+    assert!(listeners[0].local_addr().unwrap().ip().is_loopback());
+}
@@ -0,0 +1,61 @@
+mod common;
+use warp_crud::data;
+
+#[tokio::test]
+async fn test_metrics_are_recorded() {
+    let routes = common::routes(&common::settings()).await;
+    let cookie = format!("session={}", data::Session::new().id().to_simple());
+
+    warp::test::request()
+        .path("/css/custom.css")
+        .header("cookie", &cookie)
+        .reply(&routes)
+        .await;
+    let resp = warp::test::request()
+        .method("DELETE")
+        .path(&format!("/api/todos/{}", uuid::Uuid::new_v4()))
+        .header("cookie", &cookie)
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 500);
+    warp::test::request()
+        .path("/api/todos/trash")
+        .header("cookie", &cookie)
+        .reply(&routes)
+        .await;
+    warp::test::request()
+        .path("/not/a/route")
+        .reply(&routes)
+        .await;
+
+    let resp = warp::test::request().path("/metrics").reply(&routes).await;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    let metrics = String::from_utf8(resp.body().to_vec()).unwrap();
+
+    // Ids are left out of the route so every todo doesn't get series of its own
+    assert!(metrics
+        .contains(r#"http_requests_total{method="GET",route="/css/custom.css",status="200"} 1"#));
+    assert!(metrics.contains(
+        r#"http_requests_total{method="DELETE",route="/api/todos/{id}",status="500"} 1"#
+    ));
+    assert!(
+        metrics.contains(r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#)
+    );
+    assert!(metrics.contains("http_request_duration_seconds_bucket"));
+    assert!(metrics.contains(r#"db_operation_errors_total{operation="delete_todo"} 1"#));
+    assert!(metrics.contains(r#"db_operation_duration_seconds_count{operation="delete_todo"} 1"#));
+
+    // Reading the trash reads the list, which is only counted as the operation the route asked for
+    assert!(metrics.contains(r#"db_operation_errors_total{operation="get_trash"} 1"#));
+    assert!(!metrics.contains(r#"operation="get_todo_list""#));
+
+    assert!(metrics.contains("active_sessions 1"));
+    assert!(metrics.contains(&format!(
+        r#"build_info{{profile="debug",version="{}"}} 1"#,
+        env!("CARGO_PKG_VERSION")
+    )));
+}
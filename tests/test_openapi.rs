@@ -0,0 +1,153 @@
+mod common;
+use serde_json::Value;
+use std::time::Duration;
+use warp_crud::{data, events, openapi, readiness, routes};
+
+const METHODS: [&str; 4] = ["get", "post", "put", "delete"];
+
+// Send a request and check whether any route picked it up. Requests that weren't routed are
+// rejected with a 405, or a 404 whose body only carries the request id where handlers that don't
+// find something say what. Streaming routes never finish so timing out counts as routed
+async fn is_routed<F>(routes: &F, method: &str, path: &str) -> bool
+where
+    F: warp::Filter + Clone + Send + Sync + 'static,
+    F::Extract: warp::Reply + Send,
+{
+    let request = warp::test::request()
+        .method(&method.to_uppercase())
+        .path(path)
+        .header(
+            "cookie",
+            format!("session={}", data::Session::new().id().to_simple()),
+        )
+        .reply(routes);
+    match tokio::time::timeout(Duration::from_secs(2), request).await {
+        Ok(resp) => {
+            let status = resp.status().as_u16();
+            let body = String::from_utf8_lossy(resp.body());
+            let is_bare = body.starts_with("Request ID: ") && !body.contains('\n');
+            !(status == 405 || (status == 404 && is_bare))
+        }
+        Err(_) => true,
+    }
+}
+
+fn concrete_path(path: &str) -> String {
+    path.replace("{id}", &uuid::Uuid::new_v4().to_string())
+        .replace("{token}", "token.ics")
+}
+
+#[tokio::test]
+async fn test_unrouted_requests_are_told_apart() {
+    let routes = common::routes(&common::settings()).await;
+    assert!(!is_routed(&routes, "get", "/api/v1/nothing/here").await);
+    assert!(!is_routed(&routes, "post", "/health").await);
+    assert!(is_routed(&routes, "get", "/health/live").await);
+}
+
+#[tokio::test]
+async fn test_every_documented_operation_is_routed() {
+    let routes = common::routes(&common::settings()).await;
+    let spec = openapi::spec();
+
+    for (path, operations) in spec["paths"].as_object().unwrap() {
+        for method in METHODS {
+            let documented = operations.get(method).is_some();
+            let routed = is_routed(&routes, method, &concrete_path(path)).await;
+            assert_eq!(
+                documented,
+                routed,
+                "{} {} is {} but {}",
+                method.to_uppercase(),
+                path,
+                if documented {
+                    "documented"
+                } else {
+                    "not documented"
+                },
+                if routed { "routed" } else { "not routed" },
+            );
+        }
+    }
+}
+
+// The route table is the one list of everything that is served, the test above makes sure the
+// document only has routes that are served and this one that it has all of them
+#[tokio::test]
+async fn test_every_route_is_documented() {
+    let settings = common::settings();
+    let client = mongodb::Client::with_uri_str(common::NO_DATABASE)
+        .await
+        .unwrap();
+    let table = routes::table(
+        client,
+        events::Hub::new(&settings.events),
+        readiness::Readiness::new(),
+        &settings,
+    );
+
+    let mut routed: Vec<(String, String)> = Vec::new();
+    for route in table {
+        let (method, path) = (route.method, route.path);
+        routed.push((method.to_string(), path.to_string()));
+        if let Some(alias) = openapi::unversioned_alias(path) {
+            routed.push((method.to_string(), alias));
+        }
+    }
+    routed.sort();
+
+    let spec = openapi::spec();
+    let mut documented: Vec<(String, String)> = Vec::new();
+    for (path, operations) in spec["paths"].as_object().unwrap() {
+        for method in operations.as_object().unwrap().keys() {
+            documented.push((method.clone(), path.clone()));
+        }
+    }
+    documented.sort();
+
+    assert_eq!(routed, documented);
+}
+
+#[tokio::test]
+async fn test_spec_is_served() {
+    let routes = common::routes(&common::settings()).await;
+    let resp = warp::test::request()
+        .path("/api/openapi.json")
+        .reply(&routes)
+        .await;
+    assert!(resp.status().is_success());
+    let served: Value = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(served, openapi::spec());
+    assert_eq!(served["openapi"], openapi::OPENAPI_VERSION);
+}
+
+fn collect_refs<'a>(value: &'a Value, refs: &mut Vec<&'a str>) {
+    match value {
+        Value::Object(object) => {
+            if let Some(Value::String(reference)) = object.get("$ref") {
+                refs.push(reference);
+            }
+            object.values().for_each(|value| collect_refs(value, refs));
+        }
+        Value::Array(array) => array.iter().for_each(|value| collect_refs(value, refs)),
+        _ => {}
+    }
+}
+
+#[test]
+fn test_schema_references_resolve() {
+    let spec = openapi::spec();
+    let mut refs = Vec::new();
+    collect_refs(&spec, &mut refs);
+    assert!(!refs.is_empty());
+    for reference in refs {
+        let name = reference
+            .strip_prefix("#/components/schemas/")
+            .unwrap_or_else(|| panic!("Unexpected reference {}", reference));
+        assert!(
+            spec["components"]["schemas"].get(name).is_some(),
+            "Schema {} is referenced but not defined",
+            name
+        );
+    }
+}
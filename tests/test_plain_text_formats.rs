@@ -0,0 +1,152 @@
+use chrono::prelude::*;
+use warp_crud::formats::markdown::ChecklistItem;
+use warp_crud::formats::todotxt::Task;
+use warp_crud::{data, formats};
+
+#[test]
+fn test_todotxt_task_parsing() {
+    let task = Task::parse("(A) 2021-05-30 Call Mom +Family @phone due:2021-06-01");
+    assert!(!task.completed);
+    assert_eq!(task.priority, Some('A'));
+    assert_eq!(task.creation_date, NaiveDate::from_ymd_opt(2021, 5, 30));
+    assert_eq!(task.due, NaiveDate::from_ymd_opt(2021, 6, 1));
+    assert_eq!(task.text, "Call Mom +Family @phone");
+    assert_eq!(task.projects(), vec!["Family"]);
+    assert_eq!(task.contexts(), vec!["phone"]);
+
+    let task = Task::parse("x 2021-06-02 2021-05-30 Call Mom pri:B");
+    assert!(task.completed);
+    assert_eq!(task.priority, Some('B'));
+    assert_eq!(task.completion_date, NaiveDate::from_ymd_opt(2021, 6, 2));
+    assert_eq!(task.creation_date, NaiveDate::from_ymd_opt(2021, 5, 30));
+    assert_eq!(task.to_string(), "x 2021-06-02 2021-05-30 Call Mom pri:B");
+
+    // Tags that don't parse are part of the description
+    let task = Task::parse("Call Mom due:soon id:42");
+    assert_eq!(task.due, None);
+    assert_eq!(task.id, None);
+    assert_eq!(task.text, "Call Mom due:soon id:42");
+}
+
+#[test]
+fn test_todotxt_round_trip() {
+    let body = "(A) 2021-05-30 Call Mom +Family @phone due:2021-06-01 \
+                id:936da01f-9abd-4d9d-80c7-02af85c822a8\n\
+                x 2021-06-02 2021-05-30 Run To The Hills! pri:C \
+                id:67e55044-10b1-426f-9247-bb680e5fe0c8\n";
+    let import = formats::import(body.as_bytes(), formats::Format::TodoTxt).unwrap();
+    assert!(import.rejected.is_empty());
+
+    let export = formats::Export {
+        todos: import.todos.into_iter().map(|row| row.todo).collect(),
+        trash: Vec::new(),
+    };
+    let exported = formats::export(&export, formats::Format::TodoTxt).unwrap();
+    assert_eq!(std::str::from_utf8(&exported).unwrap(), body);
+}
+
+#[test]
+fn test_todotxt_completed_without_completion_date() {
+    // A lone date would be read back as the completion date, so the creation date is left out
+    let mut todo = data::Todo::from("Run To The Hills!");
+    todo.completed = true;
+    let id = todo.id;
+    let export = formats::Export {
+        todos: vec![todo],
+        trash: Vec::new(),
+    };
+    let exported = formats::export(&export, formats::Format::TodoTxt).unwrap();
+    assert_eq!(
+        std::str::from_utf8(&exported).unwrap(),
+        format!("x Run To The Hills! id:{}\n", id)
+    );
+
+    let import = formats::import(&exported, formats::Format::TodoTxt).unwrap();
+    assert!(import.todos[0].todo.completed);
+    assert_eq!(import.todos[0].todo.completed_at, None);
+}
+
+#[test]
+fn test_todotxt_creation_date_is_not_the_last_change() {
+    let mut todo = data::Todo::from("Call Mom");
+    todo.created_at = Utc.with_ymd_and_hms(2021, 5, 30, 9, 0, 0).unwrap();
+    todo.timestamp = Utc.with_ymd_and_hms(2021, 6, 2, 18, 0, 0).unwrap();
+    let task = Task::from(&todo);
+    assert_eq!(task.creation_date, NaiveDate::from_ymd_opt(2021, 5, 30));
+
+    let import = formats::import(task.to_string().as_bytes(), formats::Format::TodoTxt).unwrap();
+    let midnight = Utc.with_ymd_and_hms(2021, 5, 30, 0, 0, 0).unwrap();
+    assert_eq!(import.todos[0].todo.created_at, midnight);
+}
+
+#[test]
+fn test_markdown_checklist() {
+    assert_eq!(
+        ChecklistItem::parse("  * [X] Run To The Hills!"),
+        Some(ChecklistItem {
+            checked: true,
+            text: "Run To The Hills!".to_owned(),
+            id: None,
+        })
+    );
+    assert_eq!(
+        ChecklistItem::parse("- [x]link](https://example.com)"),
+        None
+    );
+    assert_eq!(ChecklistItem::parse("- Just a list item"), None);
+
+    let mut completed = data::Todo::from("Aces High");
+    completed.completed = true;
+    let export = formats::Export {
+        todos: vec![data::Todo::from("Run To The Hills!"), completed],
+        trash: Vec::new(),
+    };
+    let body = formats::export(&export, formats::Format::Markdown).unwrap();
+    assert_eq!(
+        std::str::from_utf8(&body).unwrap(),
+        format!(
+            "- [ ] Run To The Hills! <!-- id:{} -->\n- [x] Aces High <!-- id:{} -->\n",
+            export.todos[0].id, export.todos[1].id
+        )
+    );
+
+    // Anything that isn't a checklist item is skipped on import
+    let body = format!("# Todos\n\n{}", std::str::from_utf8(&body).unwrap());
+    let import = formats::import(body.as_bytes(), formats::Format::Markdown).unwrap();
+    assert_eq!(import.todos.len(), 2);
+    assert_eq!(import.todos[1].row, 4);
+    assert!(import.todos[1].todo.completed);
+    assert_eq!(import.todos[1].todo.id, export.todos[1].id);
+    assert_eq!(import.todos[1].todo.name, "Aces High");
+
+    // Comments that don't hold an id stay in the name
+    let item = ChecklistItem::parse("- [ ] Aces High <!-- later -->").unwrap();
+    assert_eq!(item.text, "Aces High <!-- later -->");
+    assert_eq!(item.id, None);
+}
+
+#[test]
+fn test_merging_an_export_updates_the_todos() {
+    let todos: Vec<data::Todo> = ["Run To The Hills!", "Aces High"]
+        .iter()
+        .map(|name| data::Todo::from(*name))
+        .collect();
+    let export = formats::Export {
+        todos: todos.clone(),
+        trash: Vec::new(),
+    };
+    for format in [formats::Format::TodoTxt, formats::Format::Markdown] {
+        let body = formats::export(&export, format).unwrap();
+        let import = formats::import(&body, format).unwrap();
+
+        let mut merged = todos.clone();
+        let mut trash = Vec::new();
+        formats::ImportReport::execute(
+            formats::ImportMode::Merge,
+            &import,
+            &mut merged,
+            &mut trash,
+        );
+        assert_eq!(merged.len(), todos.len());
+    }
+}
@@ -0,0 +1,82 @@
+mod common;
+use warp_crud::data;
+
+#[tokio::test]
+async fn test_request_ids_are_echoed() {
+    let routes = common::routes(&common::settings()).await;
+
+    // Ids from upstream are kept
+    let resp = warp::test::request()
+        .path("/css/custom.css")
+        .header("x-request-id", "run-to-the-hills")
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["x-request-id"], "run-to-the-hills");
+
+    // Requests without one get a generated id, as do requests with one that can't be echoed back
+    let resp = warp::test::request()
+        .path("/css/custom.css")
+        .reply(&routes)
+        .await;
+    let id = resp.headers()["x-request-id"].to_str().unwrap();
+    assert!(uuid::Uuid::parse_str(id).is_ok());
+
+    let resp = warp::test::request()
+        .path("/css/custom.css")
+        .header("x-request-id", "run to the hills")
+        .reply(&routes)
+        .await;
+    let id = resp.headers()["x-request-id"].to_str().unwrap();
+    assert!(uuid::Uuid::parse_str(id).is_ok());
+}
+
+#[tokio::test]
+async fn test_errors_include_request_id() {
+    let routes = common::routes(&common::settings()).await;
+
+    // Errors from the handlers
+    let resp = warp::test::request()
+        .method("DELETE")
+        .path(&format!("/api/todos/{}", uuid::Uuid::new_v4()))
+        .header(
+            "cookie",
+            format!("session={}", data::Session::new().id().to_simple()),
+        )
+        .header("x-request-id", "aces-high")
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 500);
+    assert_eq!(resp.headers()["x-request-id"], "aces-high");
+    assert!(String::from_utf8_lossy(resp.body()).ends_with("\nRequest ID: aces-high"));
+
+    // Errors from warp
+    let resp = warp::test::request()
+        .method("POST")
+        .path("/api/todos")
+        .header("x-request-id", "aces-high")
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 400);
+    assert!(String::from_utf8_lossy(resp.body()).ends_with("\nRequest ID: aces-high"));
+
+    // Errors sent as JSON carry it as a field
+    let resp = warp::test::request()
+        .path("/health/ready")
+        .header("x-request-id", "aces-high")
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 503);
+    let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(body["request_id"], "aces-high");
+    assert_eq!(body["status"], "fail");
+
+    let resp = warp::test::request()
+        .path("/not/a/route")
+        .header("x-request-id", "aces-high")
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 404);
+    assert_eq!(resp.headers()["x-request-id"], "aces-high");
+    assert_eq!(resp.body().as_ref(), b"Request ID: aces-high");
+}
@@ -0,0 +1,112 @@
+use std::time::Duration;
+use tonic::transport::Endpoint;
+use warp_crud::grpc::proto::todos_client::TodosClient;
+use warp_crud::{config, startup};
+
+#[tokio::test]
+async fn test_shutdown_drains_before_stopping() {
+    // Set the environment so the right config is loaded
+    std::env::set_var("RUN_ENV", "Test");
+    let mut settings = config::Settings::new().unwrap();
+    settings.server.shutdown.drain_period = 1;
+    settings.server.shutdown.timeout = 1;
+
+    let (addresses, shutdown, server) = startup::run(settings).await.unwrap();
+    let server = tokio::spawn(server);
+    let ready = format!("http://{}/health/ready", addresses[0]);
+    let client = reqwest::Client::new();
+
+    // The collections are set up in the background
+    let mut status = reqwest::StatusCode::SERVICE_UNAVAILABLE;
+    for _ in 0..300 {
+        status = client.get(&ready).send().await.unwrap().status();
+        if status.is_success() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(status, 200);
+
+    // During the drain period requests are still served, but readiness fails
+    shutdown.trigger();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(client.get(&ready).send().await.unwrap().status(), 503);
+
+    tokio::time::timeout(Duration::from_secs(10), server)
+        .await
+        .expect("Server did not stop")
+        .unwrap();
+    assert!(reqwest::Client::new().get(&ready).send().await.is_err());
+}
+
+#[tokio::test]
+async fn test_server_starts_without_a_database() {
+    // Set the environment so the right config is loaded
+    std::env::set_var("RUN_ENV", "Test");
+    let mut settings = config::Settings::new().unwrap();
+    settings.database.uri = "mongodb://127.0.0.1:9/?serverSelectionTimeoutMS=100".to_owned();
+    settings.server.shutdown.drain_period = 0;
+    settings.server.shutdown.timeout = 1;
+
+    let (addresses, shutdown, server) = startup::run(settings).await.unwrap();
+    let server = tokio::spawn(server);
+    let client = reqwest::Client::new();
+
+    // The process is up, but can't serve until the collections are set up
+    let live = format!("http://{}/health/live", addresses[0]);
+    assert_eq!(client.get(&live).send().await.unwrap().status(), 200);
+    let ready = format!("http://{}/health/ready", addresses[0]);
+    let resp = client.get(&ready).send().await.unwrap();
+    assert_eq!(resp.status(), 503);
+    let report: serde_json::Value = resp.json().await.unwrap();
+    let migrations = report["checks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|check| check["name"] == "migrations")
+        .unwrap();
+    assert_eq!(migrations["status"], "fail");
+
+    // The retries stop with the server
+    shutdown.trigger();
+    tokio::time::timeout(Duration::from_secs(10), server)
+        .await
+        .expect("Server did not stop")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_grpc_is_served_while_draining() {
+    // Set the environment so the right config is loaded
+    std::env::set_var("RUN_ENV", "Test");
+    let mut settings = config::Settings::new().unwrap();
+    settings.database.uri = "mongodb://127.0.0.1:9/?serverSelectionTimeoutMS=100".to_owned();
+    settings.server.shutdown.drain_period = 1;
+    settings.server.shutdown.timeout = 1;
+
+    // Take a free port for gRPC, the server only reports its HTTP addresses
+    let reserved = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = reserved.local_addr().unwrap().port();
+    settings.server.grpc_port = port;
+    drop(reserved);
+
+    let (_, shutdown, server) = startup::run(settings).await.unwrap();
+    let server = tokio::spawn(server);
+
+    // Calls keep being answered during the drain period, like HTTP requests are
+    shutdown.trigger();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let channel = Endpoint::from_shared(format!("http://127.0.0.1:{}", port))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    let mut client = TodosClient::new(channel);
+    let status = client.get_list(()).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+    tokio::time::timeout(Duration::from_secs(10), server)
+        .await
+        .expect("Server did not stop")
+        .unwrap();
+}
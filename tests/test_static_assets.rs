@@ -0,0 +1,77 @@
+mod common;
+use sha2::{Digest, Sha256};
+
+#[tokio::test]
+async fn test_embedded_assets_are_served() {
+    let routes = common::routes(&common::settings()).await;
+    let script = std::fs::read("static/scripts/home.js").unwrap();
+
+    let resp = warp::test::request()
+        .path("/scripts/home.js")
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .contains("javascript"));
+    assert_eq!(resp.body().as_ref(), script.as_slice());
+
+    // The ETag is the hash of the contents
+    let etag = format!("\"{}\"", hex::encode(&Sha256::digest(&script)[..16]));
+    assert_eq!(resp.headers()["etag"], etag.as_str());
+
+    let resp = warp::test::request()
+        .path("/scripts/home.js")
+        .header("if-none-match", &etag)
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 304);
+
+    // Compressed responses are tagged apart from the uncompressed ones
+    let resp = warp::test::request()
+        .path("/scripts/home.js")
+        .header("accept-encoding", "gzip")
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.headers()["content-encoding"], "gzip");
+    assert_ne!(resp.headers()["etag"], etag.as_str());
+
+    // HEAD answers with the headers of GET
+    let resp = warp::test::request()
+        .method("HEAD")
+        .path("/scripts/home.js")
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["etag"], etag.as_str());
+
+    let resp = warp::test::request().path("/").reply(&routes).await;
+    assert!(resp.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+
+    let resp = warp::test::request()
+        .path("/scripts/missing.js")
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 404);
+}
+
+#[tokio::test]
+async fn test_static_dir_overrides_embedded_assets() {
+    let dir = std::env::temp_dir().join(format!("warp_crud_static_{}", rand::random::<u32>()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("index.html"), "<p>Run To The Hills!</p>").unwrap();
+
+    let mut settings = common::settings();
+    settings.http.static_dir = Some(dir.to_str().unwrap().to_owned());
+    let routes = common::routes(&settings).await;
+
+    let resp = warp::test::request().path("/").reply(&routes).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body().as_ref(), b"<p>Run To The Hills!</p>");
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,68 @@
+mod common;
+use futures::future::BoxFuture;
+use opentelemetry::trace::{SpanKind, TraceId, TracerProvider as _};
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry_sdk::trace::TracerProvider;
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::prelude::*;
+use warp_crud::data;
+
+// Keeps exported spans around so the test can look at them
+#[derive(Clone, Debug, Default)]
+struct Exporter(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanExporter for Exporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        self.0.lock().unwrap().extend(batch);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[tokio::test]
+async fn test_requests_join_upstream_traces() {
+    let exporter = Exporter::default();
+    let provider = TracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    // The database isn't there, the operation is traced all the same
+    let routes = common::routes(&common::settings()).await;
+
+    let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+    let resp = warp::test::request()
+        .method("DELETE")
+        .path(&format!("/api/todos/{}", uuid::Uuid::new_v4()))
+        .header(
+            "cookie",
+            format!("session={}", data::Session::new().id().to_simple()),
+        )
+        .header(
+            "traceparent",
+            format!("00-{}-00f067aa0ba902b7-01", trace_id),
+        )
+        .reply(&routes)
+        .await;
+    assert_eq!(resp.status(), 500);
+    provider.force_flush();
+
+    let spans = exporter.0.lock().unwrap();
+    let trace_id = TraceId::from_hex(trace_id).unwrap();
+    let request = spans
+        .iter()
+        .find(|span| span.name == "request")
+        .expect("request span was not exported");
+    assert_eq!(request.span_context.trace_id(), trace_id);
+    assert_eq!(request.span_kind, SpanKind::Server);
+
+    // Database operations are children of the request
+    let operation = spans
+        .iter()
+        .find(|span| span.name == "warp_crud delete_todo")
+        .expect("database span was not exported");
+    assert_eq!(operation.span_context.trace_id(), trace_id);
+    assert_eq!(operation.span_kind, SpanKind::Client);
+    assert_eq!(operation.status, opentelemetry::trace::Status::error(""));
+}
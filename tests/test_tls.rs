@@ -0,0 +1,202 @@
+mod common;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::{Certificate, ClientConfig, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+use tokio_stream::wrappers::TcpListenerStream;
+use warp_crud::grpc::proto::todos_client::TodosClient;
+use warp_crud::{config, events, grpc, routes, tls};
+
+// A self-signed certificate for localhost, returned as PEM and DER
+fn self_signed() -> (String, String, Certificate) {
+    let certificate = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+    let pem = certificate.serialize_pem().unwrap();
+    let der = rustls_pemfile::certs(&mut pem.as_bytes())
+        .unwrap()
+        .remove(0);
+    (
+        pem,
+        certificate.serialize_private_key_pem(),
+        Certificate(der),
+    )
+}
+
+async fn connect(
+    address: std::net::SocketAddr,
+    root: &Certificate,
+) -> tokio_rustls::client::TlsStream<TcpStream> {
+    let mut roots = RootCertStore::empty();
+    roots.add(root).unwrap();
+    let mut config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let stream = TcpStream::connect(address).await.unwrap();
+    TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_tls_serves_http2_and_reloads_certificates() {
+    let settings = common::settings();
+
+    let directory = tempfile::tempdir().unwrap();
+    let tls_settings = config::TlsSettings {
+        certificate: directory.path().join("cert.pem").display().to_string(),
+        key: directory.path().join("key.pem").display().to_string(),
+        reload_interval: 60,
+        redirect_port: None,
+    };
+    let (certificate, key, first) = self_signed();
+    std::fs::write(&tls_settings.certificate, certificate).unwrap();
+    std::fs::write(&tls_settings.key, key).unwrap();
+
+    let routes = common::routes(&settings).await;
+    let certificates = tls::Certificates::load(tls_settings.clone()).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(tls::serve(
+        routes,
+        TcpListenerStream::new(listener),
+        certificates.clone(),
+        async move {
+            stopped.await.ok();
+        },
+    ));
+
+    // Clients that offer HTTP/2 get it
+    let stream = connect(address, &first).await;
+    assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+    let (mut sender, connection) = warp::hyper::client::conn::Builder::new()
+        .http2_only(true)
+        .handshake(stream)
+        .await
+        .unwrap();
+    tokio::spawn(connection);
+    let request = warp::http::Request::get("https://localhost/health/live")
+        .body(warp::hyper::Body::empty())
+        .unwrap();
+    let resp = sender.send_request(request).await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.version(), warp::http::Version::HTTP_2);
+
+    // Clients without ALPN fall back to HTTP/1.1
+    let resp = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap()
+        .get(format!("https://localhost:{}/health/live", address.port()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    // Nothing changed, so nothing is reloaded
+    assert!(!certificates.reload().unwrap());
+
+    // A renewed certificate is served to new connections
+    let (certificate, key, second) = self_signed();
+    std::fs::write(&tls_settings.certificate, certificate).unwrap();
+    std::fs::write(&tls_settings.key, key).unwrap();
+    assert!(certificates.reload().unwrap());
+    let stream = connect(address, &second).await;
+    assert_eq!(stream.get_ref().1.peer_certificates().unwrap()[0], second);
+
+    // A broken certificate is refused and the last good one kept
+    std::fs::write(&tls_settings.certificate, "not a certificate").unwrap();
+    assert!(certificates.reload().is_err());
+    connect(address, &second).await;
+
+    // Connections that never sent a request hold up the graceful shutdown
+    drop(stream);
+    stop.send(()).unwrap();
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_http_redirects_to_https() {
+    let redirect = routes::https_redirect(8443);
+    let resp = warp::test::request()
+        .method("POST")
+        .path("/api/todos?page=2")
+        .header("host", "example.com:8080")
+        .reply(&redirect)
+        .await;
+    assert_eq!(resp.status(), 308);
+    assert_eq!(
+        resp.headers()["location"],
+        "https://example.com:8443/api/todos?page=2"
+    );
+
+    // The default port is left out
+    let resp = warp::test::request()
+        .path("/")
+        .header("host", "example.com")
+        .reply(&routes::https_redirect(443))
+        .await;
+    assert_eq!(resp.headers()["location"], "https://example.com/");
+
+    let resp = warp::test::request().path("/").reply(&redirect).await;
+    assert_eq!(resp.status(), 400);
+}
+
+#[tokio::test]
+async fn test_grpc_is_served_over_tls() {
+    let settings = common::settings();
+
+    let directory = tempfile::tempdir().unwrap();
+    let tls_settings = config::TlsSettings {
+        certificate: directory.path().join("cert.pem").display().to_string(),
+        key: directory.path().join("key.pem").display().to_string(),
+        reload_interval: 60,
+        redirect_port: None,
+    };
+    let (certificate, key, root) = self_signed();
+    std::fs::write(&tls_settings.certificate, certificate).unwrap();
+    std::fs::write(&tls_settings.key, key).unwrap();
+
+    // Point at a database that isn't there, the request is refused before it gets that far
+    let client = mongodb::Client::with_uri_str(common::NO_DATABASE)
+        .await
+        .unwrap();
+    let certificates = tls::Certificates::load(tls_settings).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(grpc::service(client, events::Hub::new(&settings.events)))
+            .serve_with_incoming(tls::grpc_incoming(
+                TcpListenerStream::new(listener),
+                certificates,
+            )),
+    );
+
+    let channel = tonic::transport::Endpoint::from_static("http://localhost")
+        .connect_with_connector(tower::service_fn(move |_| {
+            let root = root.clone();
+            async move {
+                let stream = connect(address, &root).await;
+                assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+                Ok::<_, std::io::Error>(hyper_util::rt::TokioIo::new(stream))
+            }
+        }))
+        .await
+        .unwrap();
+    let status = TodosClient::new(channel).get_list(()).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+    // Plain text clients never get as far as the service
+    let channel = tonic::transport::Endpoint::from_shared(format!("http://{}", address))
+        .unwrap()
+        .connect()
+        .await;
+    if let Ok(channel) = channel {
+        let status = TodosClient::new(channel).get_list(()).await.unwrap_err();
+        assert_ne!(status.code(), tonic::Code::Unauthenticated);
+    }
+}
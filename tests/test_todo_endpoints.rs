@@ -1,5 +1,5 @@
 mod common;
-use warp_crud::data;
+use warp_crud::{config, data, db};
 
 #[tokio::test]
 async fn test_getting_todos() {
@@ -42,6 +42,7 @@ async fn test_creating_todo() {
 
     let new_todo = data::TodoRequest {
         name: "Run To The Hills!".to_owned(),
+        due: None,
     };
 
     // Send a post request to the
@@ -90,6 +91,7 @@ async fn test_updating_todo() {
     // make a new todo request and send an update request with the todo_id endpoint
     let new_todo = data::TodoRequest {
         name: "Run To The Hills!".to_owned(),
+        due: None,
     };
     let resp = client
         .put(format!("{}/{}", endpoint, todo_id))
@@ -114,6 +116,61 @@ async fn test_updating_todo() {
     assert_eq!(body[0].name, "Run To The Hills!");
 }
 
+#[tokio::test]
+async fn test_updating_todo_keeps_its_due_date() {
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let endpoint = app.route("/api/todos");
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+
+    // Run a get reqest to the app so we get a session cookie back
+    let resp = client
+        .get(&endpoint)
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+    let todo_id = resp.json::<Vec<data::Todo>>().await.unwrap()[0].id;
+    let todo = format!("{}/{}", endpoint, todo_id);
+    let due = |client: reqwest::Client| {
+        let endpoint = endpoint.clone();
+        async move {
+            let resp = client.get(&endpoint).send().await.unwrap();
+            resp.json::<Vec<data::Todo>>().await.unwrap()[0].due
+        }
+    };
+
+    let resp = client
+        .put(&todo)
+        .json(&serde_json::json!({"name": "Due", "due": "2030-01-01T00:00:00Z"}))
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+    assert!(due(client.clone()).await.is_some());
+
+    // Clients that don't know about due dates leave them alone
+    let resp = client
+        .put(&todo)
+        .json(&serde_json::json!({"name": "Renamed"}))
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+    assert!(due(client.clone()).await.is_some());
+
+    // An explicit null clears it
+    let resp = client
+        .put(&todo)
+        .json(&serde_json::json!({"name": "Renamed", "due": null}))
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+    assert!(due(client.clone()).await.is_none());
+}
+
 #[tokio::test]
 async fn test_deleting_todo() {
     //spawn the app so the server is running
@@ -190,3 +247,95 @@ async fn test_deleting_all() {
     // assure that the body has a length of 1 and that the only element says the default message
     assert_eq!(body.len(), 0);
 }
+
+#[tokio::test]
+async fn test_creating_todo_in_a_full_list() {
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let endpoint = app.route("/api/todos");
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+
+    // Run a get reqest to the app so we get a session cookie back
+    client
+        .get(&endpoint)
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+
+    // The list starts out with one todo, fill it up
+    for index in 1..data::MAX_TODOS {
+        let resp = client
+            .post(&endpoint)
+            .json(&data::TodoRequest {
+                name: format!("Todo {}", index),
+                due: None,
+            })
+            .send()
+            .await
+            .unwrap();
+        assert!(resp.status().is_success());
+    }
+
+    // A full list turns new todos away rather than dropping them
+    let resp = client
+        .post(&endpoint)
+        .json(&data::TodoRequest {
+            name: String::from("One Too Many"),
+            due: None,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::CONFLICT);
+
+    let body = client
+        .get(&endpoint)
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<data::Todo>>()
+        .await
+        .unwrap();
+    assert_eq!(body.len(), data::MAX_TODOS);
+    assert!(body.iter().all(|todo| todo.name != "One Too Many"));
+
+    let resp = client
+        .get(format!("{}/history", endpoint))
+        .send()
+        .await
+        .unwrap();
+    let history = resp.json::<Vec<data::Change>>().await.unwrap();
+    assert_eq!(history.len(), data::MAX_TODOS - 1);
+}
+
+#[tokio::test]
+async fn test_todos_stored_without_creation_time_are_backfilled() {
+    let settings = config::Settings::new().unwrap();
+    let database = mongodb::Client::with_uri_str(&settings.database.uri)
+        .await
+        .unwrap();
+
+    // A list the way it was stored before todos had a creation time, or a trash
+    let session = data::Session::new();
+    let mut todo = mongodb::bson::to_document(&data::Todo::from("Run To The Hills!")).unwrap();
+    todo.remove("created_at");
+    database
+        .database("warp_crud")
+        .collection::<mongodb::bson::Document>("todos")
+        .insert_one(
+            mongodb::bson::doc! {
+                "session": {"id": db::uuid_to_bson(session.id()).unwrap()},
+                "todos": [todo],
+            },
+            None,
+        )
+        .await
+        .unwrap();
+
+    db::initialize(&database).await.unwrap();
+    let list = db::get_todo_list(&database, &session).await.unwrap();
+    assert_eq!(list.todos[0].created_at, list.todos[0].timestamp);
+    assert!(list.trash.is_empty());
+}
@@ -0,0 +1,99 @@
+mod common;
+use warp_crud::data;
+
+#[tokio::test]
+async fn test_deleted_todo_can_be_restored() {
+    //spawn the app so the server is running
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let endpoint = app.route("/api/todos");
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+
+    // Run a get reqest to the app so we get a session cookie back
+    let resp = client
+        .get(&endpoint)
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+    let todo_id = resp.json::<Vec<data::Todo>>().await.unwrap()[0].id;
+
+    // Delete the todo, it should show up in the trash
+    client
+        .delete(format!("{}/{}", endpoint, todo_id))
+        .send()
+        .await
+        .unwrap();
+    let resp = client
+        .get(format!("{}/trash", endpoint))
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+    let body = resp.json::<Vec<data::DeletedTodo>>().await.unwrap();
+    assert_eq!(body.len(), 1);
+    assert_eq!(body[0].todo.id, todo_id);
+
+    // Restore it and verify it moved back into the list
+    let resp = client
+        .post(format!("{}/trash/{}/restore", endpoint, todo_id))
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+
+    let resp = client
+        .get(&endpoint)
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+    let body = resp.json::<Vec<data::Todo>>().await.unwrap();
+    assert_eq!(body.len(), 1);
+    assert_eq!(body[0].id, todo_id);
+
+    let resp = client
+        .get(format!("{}/trash", endpoint))
+        .send()
+        .await
+        .unwrap();
+    let body = resp.json::<Vec<data::DeletedTodo>>().await.unwrap();
+    assert_eq!(body.len(), 0);
+}
+
+#[tokio::test]
+async fn test_purging_trashed_todo() {
+    //spawn the app so the server is running
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let endpoint = app.route("/api/todos");
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+
+    // Run a get reqest to the app so we get a session cookie back
+    let resp = client
+        .get(&endpoint)
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+    let todo_id = resp.json::<Vec<data::Todo>>().await.unwrap()[0].id;
+
+    // Clearing the list moves everything into the trash
+    client.delete(&endpoint).send().await.unwrap();
+
+    // Purge the todo, after which it can't be restored
+    let resp = client
+        .delete(format!("{}/trash/{}", endpoint, todo_id))
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+
+    let resp = client
+        .post(format!("{}/trash/{}/restore", endpoint, todo_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,153 @@
+mod common;
+use tokio::sync::mpsc;
+use warp::Filter;
+use warp_crud::{config, data, webhooks};
+
+#[test]
+fn test_signature_covers_timestamp_and_body() {
+    let signature = webhooks::sign("secret", "1600000000", b"{}");
+    assert!(signature.starts_with("sha256="));
+    assert_eq!(signature, webhooks::sign("secret", "1600000000", b"{}"));
+    assert_ne!(signature, webhooks::sign("secret", "1600000001", b"{}"));
+    assert_ne!(signature, webhooks::sign("other", "1600000000", b"{}"));
+}
+
+#[tokio::test]
+async fn test_webhook_rejects_invalid_url() {
+    //spawn the app so the server is running
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let resp = reqwest::Client::new()
+        .post(app.route("/api/webhooks"))
+        .header(
+            "cookie",
+            format!("session={}", data::Session::new().id().to_simple()),
+        )
+        .json(&data::WebhookRequest {
+            url: "ftp://example.com".to_owned(),
+            events: vec![data::WebhookEvent::TodoCreated],
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    // The test configuration only allows loopback receivers
+    for url in &["http://10.0.0.1/hook", "http://169.254.169.254/latest"] {
+        let resp = reqwest::Client::new()
+            .post(app.route("/api/webhooks"))
+            .header(
+                "cookie",
+                format!("session={}", data::Session::new().id().to_simple()),
+            )
+            .json(&data::WebhookRequest {
+                url: (*url).to_owned(),
+                events: vec![data::WebhookEvent::TodoCreated],
+            })
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+    }
+}
+
+fn webhook_settings(allowed_networks: &[&str]) -> config::WebhookSettings {
+    config::WebhookSettings {
+        max_attempts: 1,
+        initial_backoff: 0,
+        max_backoff: 0,
+        timeout: 1,
+        max_per_session: 1,
+        allowed_networks: allowed_networks
+            .iter()
+            .map(|network| network.to_string())
+            .collect(),
+    }
+}
+
+#[tokio::test]
+async fn test_receivers_on_internal_networks_are_refused() {
+    let receivers = webhooks::Receivers::new(&webhook_settings(&[]));
+    for url in &[
+        "http://127.0.0.1:8080/hook",
+        "http://localhost/hook",
+        "http://[::1]/hook",
+        "http://[::ffff:10.0.0.1]/hook",
+        "http://192.168.1.20/hook",
+        "http://169.254.169.254/latest/meta-data",
+        "http://0.0.0.0/hook",
+    ] {
+        assert!(receivers.check(url).await.is_err(), "{} was allowed", url);
+    }
+    assert!(receivers.check("https://93.184.216.34/hook").await.is_ok());
+
+    // Operators can allow networks they trust
+    let receivers = webhooks::Receivers::new(&webhook_settings(&["127.0.0.0/8"]));
+    assert!(receivers.check("http://127.0.0.1:8080/hook").await.is_ok());
+    assert!(receivers.check("http://10.0.0.1/hook").await.is_err());
+}
+
+#[tokio::test]
+async fn test_webhook_receives_signed_payload() {
+    // Start a receiver that hands every delivery it gets back to the test
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let receiver_route = warp::post()
+        .and(warp::header::<String>(webhooks::TIMESTAMP_HEADER))
+        .and(warp::header::<String>(webhooks::SIGNATURE_HEADER))
+        .and(warp::body::bytes())
+        .map(
+            move |timestamp: String, signature: String, body: warp::hyper::body::Bytes| {
+                sender.send((timestamp, signature, body)).unwrap();
+                warp::reply()
+            },
+        );
+    let (receiver_address, receiver_server) =
+        warp::serve(receiver_route).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(receiver_server);
+
+    //spawn the app so the server is running
+    let app = common::App::launch(Some("Test")).await.unwrap();
+    let client = reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .expect("Could not Create Client");
+
+    // Run a get reqest to the app so we get a session cookie back
+    client
+        .get(app.route("/api/todos"))
+        .send()
+        .await
+        .expect("Error Running Get Request to App");
+
+    let resp = client
+        .post(app.route("/api/webhooks"))
+        .json(&data::WebhookRequest {
+            url: format!("http://{}/hook", receiver_address),
+            events: vec![data::WebhookEvent::TodoCreated],
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::CREATED);
+    let webhook = resp.json::<data::WebhookView>().await.unwrap();
+    let secret = webhook.secret.expect("Secret was not returned on creation");
+
+    client
+        .post(app.route("/api/todos"))
+        .json(&data::TodoRequest {
+            name: "Run To The Hills!".to_owned(),
+            due: None,
+        })
+        .send()
+        .await
+        .unwrap();
+
+    let (timestamp, signature, body) =
+        tokio::time::timeout(std::time::Duration::from_secs(10), receiver.recv())
+            .await
+            .expect("Webhook was never delivered")
+            .unwrap();
+    assert_eq!(signature, webhooks::sign(&secret, &timestamp, &body));
+    let payload: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(payload["event"], "todo_created");
+    assert_eq!(payload["data"]["todo"]["name"], "Run To The Hills!");
+}